@@ -0,0 +1,191 @@
+// Служебные маршруты для администратора
+// Доступны только с ключом API: без ключа в настройках они отключены и отвечают 403
+// Отладочные маршруты /debug/... дополнительно регистрируются, только если включен debug_endpoints
+use axum::{
+    extract::{Json, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    model::Event,
+    params::query_flag,
+    responses::{store_error_message, store_error_response},
+    store::{EventStore, StoreError},
+    AppState,
+};
+
+// Сколько последних записей журнала изменений попадает в /debug/dump
+const DUMP_AUDIT_ENTRIES: usize = 20;
+
+// Обработчик, перестраивающий индексы хранилища и возвращающий отчет о согласованности данных
+#[utoipa::path(
+    post,
+    path = "/admin/reindex",
+    responses(
+        (status = 200, description = "Индексы перестроены", body = crate::openapi::ReindexResponse),
+        (status = 401, description = "Неверный ключ API", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "Ключ API в настройках не задан", body = crate::openapi::ErrorResponse),
+        (status = 503, description = "Хранилище недоступно", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn reindex_handler(State(AppState { store, .. }): State<AppState>) -> Response {
+    match store.reindex().await {
+        Ok(report) => {
+            let res = json!({
+                "result": report,
+            });
+            (StatusCode::OK, Json(res)).into_response()
+        }
+        Err(e) => store_error_response(e),
+    }
+}
+
+// Сравнение хранилища с состоянием, восстановленным по журналу изменений
+#[derive(Default, Serialize, ToSchema)]
+pub struct ReplayReport {
+    // Сколько записей журнала воспроизведено и сколько событий по ним получилось
+    entries: usize,
+    events: usize,
+    // События, которые есть по журналу, но которых нет в хранилище
+    missing: Vec<Uuid>,
+    // События хранилища, которых по журналу быть не должно
+    unexpected: Vec<Uuid>,
+    // События, которые в хранилище отличаются от последнего снимка в журнале
+    different: Vec<Uuid>,
+    // Записи журнала, которые не удалось воспроизвести, и ошибки при приведении хранилища к журналу
+    problems: Vec<String>,
+    // Хранилище приведено к состоянию по журналу
+    applied: bool,
+}
+
+// Обработчик, восстанавливающий события по журналу изменений и сравнивающий результат с хранилищем
+// С apply=true хранилище приводится к состоянию по журналу: недостающие события добавляются,
+// отличающиеся заменяются, лишние удаляются. Сами исправления в журнал не записываются
+#[utoipa::path(
+    post,
+    path = "/admin/replay",
+    params(crate::openapi::ReplayParam),
+    responses(
+        (status = 200, description = "Журнал воспроизведен", body = crate::openapi::ReplayResponse),
+        (status = 401, description = "Неверный ключ API", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "Ключ API в настройках не задан", body = crate::openapi::ErrorResponse),
+        (status = 503, description = "Хранилище недоступно", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn replay_handler(
+    State(AppState { store, audit, .. }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    let replay = audit.replay().await;
+    let stored = match store.list(None).await {
+        Ok(events) => events,
+        Err(e) => {
+            return store_error_response(e);
+        }
+    };
+    let mut report = ReplayReport {
+        entries: replay.entries,
+        events: replay.events.len(),
+        problems: replay.problems,
+        ..ReplayReport::default()
+    };
+    for event in &stored {
+        match replay.events.get(&event.id) {
+            None => report.unexpected.push(event.id),
+            Some(replayed) if json!(replayed) != json!(event) => report.different.push(event.id),
+            Some(_) => {}
+        }
+    }
+    report.missing = replay
+        .events
+        .keys()
+        .filter(|id| !stored.iter().any(|event| event.id == **id))
+        .copied()
+        .collect();
+
+    if query_flag(&param, "apply") {
+        for id in &report.unexpected {
+            if let Err(e) = store.remove(*id).await {
+                report.problems.push(apply_problem(*id, &e));
+            }
+        }
+        for id in &report.different {
+            let version = stored
+                .iter()
+                .find(|event| event.id == *id)
+                .map(|event| event.version)
+                .unwrap_or_default();
+            if let Err(e) = store.update(replay.events[id].clone(), version).await {
+                report.problems.push(apply_problem(*id, &e));
+            }
+        }
+        for id in &report.missing {
+            if let Err(e) = insert_replayed(&*store, replay.events[id].clone()).await {
+                report.problems.push(apply_problem(*id, &e));
+            }
+        }
+        report.applied = true;
+    }
+
+    let res = json!({
+        "result": report,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Функция для добавления недостающего события: мягко удаленное событие сначала восстанавливается и затем заменяется
+async fn insert_replayed(store: &dyn EventStore, event: Event) -> Result<(), StoreError> {
+    match store.insert(event.clone()).await {
+        Err(StoreError::Exists) => {
+            let restored = store.restore(event.id).await?;
+            store.update(event, restored.version).await
+        }
+        result => result,
+    }
+}
+
+fn apply_problem(id: Uuid, e: &StoreError) -> String {
+    format!("event {}: {}", id, store_error_message(e))
+}
+
+// Обработчик, возвращающий внутреннее состояние сервера для диагностики
+// Ключ API в настройках не выводится, в записях журнала от него остаются только последние символы
+#[utoipa::path(
+    get,
+    path = "/debug/dump",
+    responses(
+        (status = 200, description = "Внутреннее состояние сервера", body = crate::openapi::DebugDumpResponse),
+        (status = 401, description = "Неверный ключ API", body = crate::openapi::ErrorResponse),
+        (status = 403, description = "Ключ API в настройках не задан", body = crate::openapi::ErrorResponse),
+        (status = 503, description = "Хранилище недоступно", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn debug_dump_handler(
+    State(AppState {
+        store,
+        settings,
+        audit,
+        ..
+    }): State<AppState>,
+) -> Response {
+    let dump = match store.dump().await {
+        Ok(dump) => dump,
+        Err(e) => {
+            return store_error_response(e);
+        }
+    };
+    let res = json!({
+        "result": {
+            "store": dump,
+            "unsaved_changes": store.has_unsaved_changes(),
+            "settings": settings.as_ref(),
+            "recent_audit": audit.recent(DUMP_AUDIT_ENTRIES).await,
+        },
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
@@ -0,0 +1,289 @@
+// Журнал изменений событий: кто, когда и какое событие создал, изменил или удалил
+// Журнал хранится в памяти и, если задан путь, дописывается в файл по одной записи JSON в строке
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    fs::{self, OpenOptions},
+    future::Future,
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::request::Parts,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    model::{Event, INCLUDE_NULLS},
+    API_KEY_HEADER,
+};
+
+// Сколько последних символов ключа API остается в журнале, остальные скрываются
+const VISIBLE_KEY_CHARS: usize = 4;
+
+// Вид изменения
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOp {
+    Create,
+    Update,
+    Delete,
+    Restore,
+}
+
+// Запись журнала
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
+pub struct AuditEntry {
+    timestamp: DateTime<Utc>,
+    operation: AuditOp,
+    event_id: Uuid,
+    // Ключ API, с которым пришел запрос, - только последние символы
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    ip: Option<IpAddr>,
+    // Событие после создания или изменения, по этим снимкам журнал можно воспроизвести с пустого хранилища
+    // У записей, сделанных до появления снимков, не задано
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    event: Option<Event>,
+}
+
+// Автор изменения: ключ API из заголовка и адрес клиента
+#[derive(Clone, Default)]
+pub struct Actor {
+    api_key: Option<String>,
+    ip: Option<IpAddr>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Actor {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let api_key = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(mask_key);
+        let ip = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Ok(Actor { api_key, ip })
+    }
+}
+
+// Ключ API в журнале не хранится целиком, чтобы журнал нельзя было использовать для доступа к API
+fn mask_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    let visible = if chars.len() > VISIBLE_KEY_CHARS {
+        &chars[chars.len() - VISIBLE_KEY_CHARS..]
+    } else {
+        &[]
+    };
+    format!("****{}", visible.iter().collect::<String>())
+}
+
+pub struct AuditLog {
+    entries: Mutex<Vec<AuditEntry>>,
+    path: Option<String>,
+}
+
+impl AuditLog {
+    // Создаем журнал, загружая записи из файла, если он задан
+    // Поврежденные строки файла пропускаются с предупреждением
+    pub fn open(path: Option<String>) -> Self {
+        let mut entries = Vec::new();
+        if let Some(path) = &path {
+            match fs::read_to_string(path) {
+                Ok(data) => {
+                    for (number, line) in data.lines().enumerate() {
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        match serde_json::from_str(line) {
+                            Ok(entry) => entries.push(entry),
+                            Err(e) => {
+                                tracing::warn!(path, line = number + 1, error = %e, "corrupt audit log entry")
+                            }
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!(path, error = %e, "failed to read audit log"),
+            }
+        }
+        AuditLog {
+            entries: Mutex::new(entries),
+            path,
+        }
+    }
+
+    // Выполняем изменение и при успехе записываем его в журнал
+    // Изменение выполняется под блокировкой журнала, поэтому порядок записей совпадает с порядком изменений
+    pub async fn record<T, E, F>(
+        &self,
+        actor: &Actor,
+        operation: AuditOp,
+        event_ids: &[Uuid],
+        mutation: F,
+    ) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let targets = event_ids.iter().map(|id| (*id, None)).collect();
+        self.record_targets(actor, operation, targets, mutation)
+            .await
+    }
+
+    // То же для создания и изменения: в журнал вместе с идентификаторами попадают снимки событий
+    pub async fn record_events<T, E, F>(
+        &self,
+        actor: &Actor,
+        operation: AuditOp,
+        events: &[Event],
+        mutation: F,
+    ) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let targets = events
+            .iter()
+            .map(|event| (event.id, Some(event.clone())))
+            .collect();
+        self.record_targets(actor, operation, targets, mutation)
+            .await
+    }
+
+    async fn record_targets<T, E, F>(
+        &self,
+        actor: &Actor,
+        operation: AuditOp,
+        targets: Vec<(Uuid, Option<Event>)>,
+        mutation: F,
+    ) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let mut entries = self.entries.lock().await;
+        let result = mutation.await;
+        if result.is_ok() {
+            let timestamp = Utc::now();
+            let new_entries: Vec<AuditEntry> = targets
+                .into_iter()
+                .map(|(event_id, event)| AuditEntry {
+                    timestamp,
+                    operation,
+                    event_id,
+                    api_key: actor.api_key.clone(),
+                    ip: actor.ip,
+                    event,
+                })
+                .collect();
+            if let Some(path) = &self.path {
+                if let Err(e) = append_entries(path, &new_entries) {
+                    tracing::warn!(path, error = %e, "failed to write audit log");
+                }
+            }
+            entries.extend(new_entries);
+        }
+        result
+    }
+
+    // Страница журнала от старых записей к новым и общее количество записей
+    pub async fn page(&self, offset: usize, limit: usize) -> (Vec<AuditEntry>, usize) {
+        let entries = self.entries.lock().await;
+        let page = entries.iter().skip(offset).take(limit).cloned().collect();
+        (page, entries.len())
+    }
+
+    // Последние записи журнала, от старых к новым
+    pub async fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let entries = self.entries.lock().await;
+        entries[entries.len().saturating_sub(limit)..].to_vec()
+    }
+
+    // Воспроизводим журнал с пустого хранилища и получаем события, которые должны в нем остаться
+    // Удаленные события откладываются в сторону, чтобы их можно было восстановить следующей записью
+    // Записи, которые нельзя применить к состоянию на тот момент, попадают в список проблем и пропускаются
+    pub async fn replay(&self) -> Replay {
+        let entries = self.entries.lock().await;
+        let mut replay = Replay {
+            entries: entries.len(),
+            ..Replay::default()
+        };
+        let mut deleted = HashMap::new();
+        for (number, entry) in entries.iter().enumerate() {
+            let id = entry.event_id;
+            match (entry.operation, &entry.event) {
+                (AuditOp::Create | AuditOp::Update, None) => replay
+                    .problems
+                    .push(format!("entry {}: no snapshot of event {}", number, id)),
+                (AuditOp::Create, Some(event)) => {
+                    if replay.events.insert(id, event.clone()).is_some() {
+                        replay
+                            .problems
+                            .push(format!("entry {}: event {} created twice", number, id));
+                    }
+                }
+                (AuditOp::Update, Some(event)) => {
+                    if replay.events.insert(id, event.clone()).is_none() {
+                        replay
+                            .problems
+                            .push(format!("entry {}: update of missing event {}", number, id));
+                    }
+                }
+                (AuditOp::Delete, _) => match replay.events.remove(&id) {
+                    Some(event) => {
+                        deleted.insert(id, event);
+                    }
+                    None => replay
+                        .problems
+                        .push(format!("entry {}: delete of missing event {}", number, id)),
+                },
+                (AuditOp::Restore, _) => match deleted.remove(&id) {
+                    Some(event) => {
+                        replay.events.insert(id, event);
+                    }
+                    None => replay.problems.push(format!(
+                        "entry {}: restore of event {} that was not deleted",
+                        number, id
+                    )),
+                },
+            }
+        }
+        replay
+    }
+}
+
+// Результат воспроизведения журнала
+#[derive(Default)]
+pub struct Replay {
+    // Сколько записей журнала воспроизведено
+    pub entries: usize,
+    pub events: BTreeMap<Uuid, Event>,
+    pub problems: Vec<String>,
+}
+
+// Функция для дописывания записей в конец файла журнала
+fn append_entries(path: &str, entries: &[AuditEntry]) -> io::Result<()> {
+    let mut data = Vec::new();
+    // Снимки событий записываются без незаданных полей независимо от include_null_fields
+    INCLUDE_NULLS.sync_scope(false, || -> io::Result<()> {
+        for entry in entries {
+            serde_json::to_writer(&mut data, entry)?;
+            data.push(b'\n');
+        }
+        Ok(())
+    })?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&data)
+}
@@ -0,0 +1,250 @@
+// Уведомления об изменениях событий для клиентов, подписанных через WebSocket или SSE
+// Обработчики после успешного изменения публикуют его в канал, каждое соединение читает канал независимо
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Json,
+};
+use futures_util::{stream, Stream, StreamExt};
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{metrics::Metrics, AppState, Event};
+
+// Как часто в поток SSE отправляется комментарий, чтобы прокси не закрывали простаивающее соединение
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+// Вид изменения события
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+// Сообщение, отправляемое подписчикам
+#[derive(Clone, Serialize)]
+pub struct Change {
+    // Номер изменения, растет с каждым изменением, в сообщение не входит
+    #[serde(skip)]
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub kind: ChangeKind,
+    pub event: Event,
+}
+
+// Канал изменений, общий для всех обработчиков
+#[derive(Clone)]
+pub struct ChangeFeed {
+    sender: broadcast::Sender<Change>,
+    history: Arc<Mutex<History>>,
+    // Сколько изменений хранит канал для отстающих подписчиков, более старые они пропускают
+    // Столько же последних изменений хранится для возобновления потока SSE по Last-Event-ID
+    capacity: usize,
+}
+
+// Последние изменения и номер последнего из них
+#[derive(Default)]
+struct History {
+    last_id: u64,
+    recent: VecDeque<Change>,
+}
+
+impl ChangeFeed {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        ChangeFeed {
+            sender,
+            history: Arc::new(Mutex::new(History::default())),
+            capacity,
+        }
+    }
+
+    // Публикуем изменение, если подписчиков нет - оно просто отбрасывается
+    // Номер назначается и изменение отправляется под блокировкой истории, поэтому номера приходят подписчикам по возрастанию
+    pub fn publish(&self, kind: ChangeKind, event: &Event) {
+        let mut history = self.history.lock().unwrap_or_else(|e| {
+            self.history.clear_poison();
+            e.into_inner()
+        });
+        history.last_id += 1;
+        let change = Change {
+            id: history.last_id,
+            kind,
+            event: event.clone(),
+        };
+        if history.recent.len() == self.capacity {
+            history.recent.pop_front();
+        }
+        history.recent.push_back(change.clone());
+        let _ = self.sender.send(change);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+        self.sender.subscribe()
+    }
+
+    // Подписка с изменениями после last_id, которые еще хранятся в истории
+    // Подписываемся под блокировкой истории, чтобы между историей и каналом не потерялось ни одно изменение
+    pub fn subscribe_after(&self, last_id: u64) -> (Vec<Change>, broadcast::Receiver<Change>) {
+        let history = self.history.lock().unwrap_or_else(|e| {
+            self.history.clear_poison();
+            e.into_inner()
+        });
+        let missed = history
+            .recent
+            .iter()
+            .filter(|change| change.id > last_id)
+            .cloned()
+            .collect();
+        (missed, self.sender.subscribe())
+    }
+}
+
+// Обработчик подключения по WebSocket: клиент получает JSON-сообщение о каждом изменении событий
+// Если клиент не успевает читать и пропускает изменения, он получает сообщение {"type": "resync_required", "skipped": N}
+#[utoipa::path(
+    get,
+    path = "/ws",
+    responses(
+        (status = 101, description = "Соединение переключено на WebSocket, далее приходят сообщения {type, event} и {type: resync_required, skipped}"),
+    )
+)]
+pub async fn ws_handler(
+    State(AppState {
+        changes, metrics, ..
+    }): State<AppState>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    // Подписываемся до переключения протокола, чтобы не пропустить изменения во время рукопожатия
+    let receiver = changes.subscribe();
+    ws.on_upgrade(move |socket| forward_changes(socket, receiver, metrics))
+}
+
+// Пересылаем изменения клиенту, пока он не закроет соединение
+async fn forward_changes(
+    mut socket: WebSocket,
+    mut receiver: broadcast::Receiver<Change>,
+    metrics: Arc<Metrics>,
+) {
+    loop {
+        tokio::select! {
+            change = receiver.recv() => match change {
+                Ok(change) => {
+                    let text = match serde_json::to_string(&change) {
+                        Ok(text) => text,
+                        Err(e) => {
+                            tracing::warn!(error = %e, "failed to serialize change");
+                            continue;
+                        }
+                    };
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                // Клиент не успевает читать: пропущенные изменения уже вытеснены из канала
+                // Сообщаем ему об этом, чтобы он заново загрузил события, а не продолжал с пропусками
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "websocket client lagged behind");
+                    metrics.observe_lagged("ws", skipped);
+                    let text = json!({ "type": "resync_required", "skipped": skipped }).to_string();
+                    if socket.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Входящие сообщения клиента не обрабатываются, ждем только закрытия соединения
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// Обработчик потока изменений в формате SSE (text/event-stream)
+// Каждое сообщение содержит номер изменения, переданный в Last-Event-ID номер возобновляет поток с пропущенных изменений
+#[utoipa::path(
+    get,
+    path = "/events/stream",
+    params(("Last-Event-ID" = Option<u64>, Header, description = "Номер последнего полученного изменения")),
+    responses(
+        (status = 200, description = "Поток сообщений {type, event}", content_type = "text/event-stream"),
+        (status = 400, description = "Некорректный Last-Event-ID", body = crate::openapi::ErrorResponse),
+    )
+)]
+pub async fn sse_handler(
+    State(AppState {
+        changes, metrics, ..
+    }): State<AppState>,
+    headers: HeaderMap,
+) -> Response {
+    let last_id = match headers.get(LAST_EVENT_ID_HEADER) {
+        None => None,
+        Some(value) => match value
+            .to_str()
+            .ok()
+            .and_then(|v| v.trim().parse::<u64>().ok())
+        {
+            Some(id) => Some(id),
+            None => {
+                let res = json!({
+                    "error": format!("invalid Last-Event-ID"),
+                });
+                return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+            }
+        },
+    };
+    // Без Last-Event-ID клиент получает только новые изменения
+    let (missed, receiver) = match last_id {
+        Some(last_id) => changes.subscribe_after(last_id),
+        None => (Vec::new(), changes.subscribe()),
+    };
+    let stream = stream::iter(missed)
+        .chain(live_changes(receiver, metrics))
+        .map(|change| {
+            sse::Event::default()
+                .id(change.id.to_string())
+                .json_data(&change)
+        });
+    Sse::new(stream)
+        .keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+        .into_response()
+}
+
+// Поток изменений из канала, отставший подписчик пропускает вытесненные изменения
+// Отставший клиент может заметить пропуск по номерам изменений и возобновить поток по Last-Event-ID
+fn live_changes(
+    receiver: broadcast::Receiver<Change>,
+    metrics: Arc<Metrics>,
+) -> impl Stream<Item = Change> {
+    stream::unfold((receiver, metrics), |(mut receiver, metrics)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(change) => return Some((change, (receiver, metrics))),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "event stream client lagged behind");
+                    metrics.observe_lagged("sse", skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
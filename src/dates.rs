@@ -0,0 +1,129 @@
+// Вычисления с датами: границы недели и месяца, дни события с учетом часового пояса
+use chrono::{DateTime, Datelike, Duration, Months, NaiveDate, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+
+use crate::{
+    model::{Event, Period},
+    settings::{MidnightBoundary, WeekStart},
+};
+
+// Функция для определения первого и последнего дня события в указанном часовом поясе
+// Для события без окончания оба дня совпадают и определяются правилом полуночи
+// Событие с окончанием занимает дни с начала по окончание, окончание ровно в полночь новый день не занимает
+pub fn event_days(
+    date: DateTime<Utc>,
+    end_date: Option<DateTime<Utc>>,
+    tz: Tz,
+    boundary: MidnightBoundary,
+) -> (NaiveDate, NaiveDate) {
+    match end_date {
+        Some(end_date) => {
+            let first_day = event_day(date, tz, MidnightBoundary::Start);
+            let last_day = event_day(end_date, tz, MidnightBoundary::End).max(first_day);
+            (first_day, last_day)
+        }
+        None => {
+            let day = event_day(date, tz, boundary);
+            (day, day)
+        }
+    }
+}
+
+// Функция для определения ячеек недельной сетки (номер дня недели и час), которые занимает событие
+// Событие без времени окончания занимает одну ячейку - час своего начала,
+// событие с окончанием - все часы от начала до окончания (не включая его), обрезанные границами недели
+pub fn grid_cells(event: &Event, week_start: NaiveDate, tz: Tz) -> Vec<(usize, u32)> {
+    let local_midnight = |date: NaiveDate| {
+        let date_time = date.and_time(NaiveTime::MIN);
+        tz.from_local_datetime(&date_time)
+            .earliest()
+            .unwrap_or_else(|| tz.from_utc_datetime(&date_time))
+            .with_timezone(&Utc)
+    };
+    let week_begin = local_midnight(week_start);
+    let week_finish = local_midnight(week_start + Duration::days(7));
+    let end = match event.end_date {
+        Some(end) if end > event.date => end.min(week_finish),
+        // Без окончания (или с нулевой длительностью) - только час начала
+        _ => event.date + Duration::nanoseconds(1),
+    };
+    let mut cells: Vec<(usize, u32)> = Vec::new();
+    let mut cursor = event.date.max(week_begin);
+    while cursor < end && cursor < week_finish {
+        let local = cursor.with_timezone(&tz);
+        let day = (local.date_naive() - week_start).num_days() as usize;
+        // При переводе часов назад один и тот же час повторяется - второй раз его не добавляем
+        if !cells.contains(&(day, local.hour())) {
+            cells.push((day, local.hour()));
+        }
+        // Переходим к началу следующего часа по местному времени
+        cursor += Duration::minutes(60 - local.minute() as i64)
+            - Duration::seconds(local.second() as i64)
+            - Duration::nanoseconds(local.nanosecond() as i64);
+    }
+    cells
+}
+
+// Функция для определения первого и последнего дня месяца для указанной даты
+pub fn month_bounds(date: NaiveDate) -> (NaiveDate, NaiveDate) {
+    let start = date - Duration::days(date.day0() as i64);
+    let end = start
+        .checked_add_months(Months::new(1))
+        .map(|next| next - Duration::days(1))
+        .unwrap_or(NaiveDate::MAX);
+    (start, end)
+}
+
+// Функция для определения календарного дня события в указанном часовом поясе
+// Событие ровно в полночь относится либо к начинающемуся дню, либо к завершившемуся - в зависимости от настройки
+fn event_day(date: DateTime<Utc>, tz: Tz, boundary: MidnightBoundary) -> NaiveDate {
+    let date = date.with_timezone(&tz);
+    let day = date.date_naive();
+    match boundary {
+        MidnightBoundary::End if date.time() == NaiveTime::MIN => day - Duration::days(1),
+        _ => day,
+    }
+}
+
+// Функция для определения начала недели для указанной даты
+pub fn start_of_week(date: NaiveDate, week_start: WeekStart) -> NaiveDate {
+    let diff = match week_start {
+        WeekStart::Monday => date.weekday().num_days_from_monday(),
+        WeekStart::Sunday => date.weekday().num_days_from_sunday(),
+    };
+    date - Duration::days(diff as i64)
+}
+
+// Функция для определения первого и последнего дня периода, содержащего указанную дату
+pub fn period_bounds(
+    period: Period,
+    date: NaiveDate,
+    week_start: WeekStart,
+) -> (NaiveDate, NaiveDate) {
+    match period {
+        Period::Day => (date, date),
+        Period::Week => {
+            let start = start_of_week(date, week_start);
+            (start, start + Duration::days(6))
+        }
+        Period::Month => month_bounds(date),
+        Period::Year => {
+            let start = date - Duration::days(date.ordinal0() as i64);
+            let end = start
+                .with_year(start.year() + 1)
+                .map(|next| next - Duration::days(1))
+                .unwrap_or(NaiveDate::MAX);
+            (start, end)
+        }
+    }
+}
+
+// Функция для определения момента начала дня (местной полуночи) в указанном часовом поясе
+// Если полночи в этот день нет из-за перевода часов, берется то же время по UTC
+pub fn local_midnight(date: NaiveDate, tz: Tz) -> DateTime<Utc> {
+    let date_time = date.and_time(NaiveTime::MIN);
+    tz.from_local_datetime(&date_time)
+        .earliest()
+        .unwrap_or_else(|| tz.from_utc_datetime(&date_time))
+        .with_timezone(&Utc)
+}
@@ -0,0 +1,995 @@
+// Обработчики создания, изменения, удаления и получения отдельных событий
+use std::sync::{atomic::Ordering, Arc};
+
+use axum::{
+    extract::{Json, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    audit::{Actor, AuditOp},
+    changes::ChangeKind,
+    idempotency::Reservation,
+    model::{occurrences, CreateMode, Event},
+    openapi,
+    params::{create_mode_parse, event_req_parse, json_body_parse, query_flag},
+    responses::{
+        ndjson_response, response_format, store_error_message, store_error_response, xml_response,
+        ResponseFormat,
+    },
+    settings::MidnightBoundary,
+    store::{BulkInsertError, EventStore, StoreError},
+    validation,
+    validation::Validator,
+    xml, AppState, API_KEY_HEADER, IDEMPOTENCY_KEY_HEADER, PREFER_HEADER,
+};
+
+// Обработчик создания события
+#[utoipa::path(
+    post,
+    path = "/create_event",
+    params(
+        openapi::CreateParam,
+        ("Idempotency-Key" = Option<String>, Header, description = "Ключ идемпотентности: повтор запроса с тем же ключом возвращает первый ответ"),
+    ),
+    request_body = openapi::EventReq,
+    responses(
+        (status = 201, description = "Событие добавлено", body = openapi::CreatedResponse),
+        (status = 400, description = "Некорректные поля запроса", body = openapi::ValidationErrors),
+        (status = 200, description = "Событие с переданным id заменено (mode=upsert)", body = openapi::CreatedResponse),
+        (status = 409, description = "Событие с таким id уже существует или пересекается с другими", body = openapi::ErrorResponse),
+        (status = 429, description = "Достигнут лимит событий", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn create_event_handler(
+    State(AppState {
+        store,
+        settings,
+        idempotency_keys,
+        changes,
+        audit,
+        ..
+    }): State<AppState>,
+    actor: Actor,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    let mode = match create_mode_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Проверяем на валидность входные данные
+    let mut event = match json_body_parse(body, settings.trim_strings).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Ключ резервируется только за корректным запросом, некорректный отклоняется, не занимая ключ
+    // Если запрос с этим ключом идемпотентности уже выполнялся - возвращаем его ответ, не добавляя событие повторно
+    // Ключи разных клиентов (по ключу API) не пересекаются
+    let mut reserved_key = None;
+    if let Some(key) = headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        let api_key = headers
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok());
+        let ttl = std::time::Duration::from_secs(settings.idempotency_key_ttl_secs);
+        match idempotency_keys.reserve(api_key, key, ttl).await {
+            Reservation::Replay(status, res) => {
+                return (status, [("idempotent-replayed", "true")], Json(res)).into_response();
+            }
+            Reservation::Reserved(reserved) => reserved_key = Some(reserved),
+        }
+    }
+    // Событие определяется идентификатором, события с одинаковыми датой и названием допускаются
+    // В режиме upsert событие с переданным идентификатором заменяется, время его создания сохраняется
+    let existing = match mode {
+        CreateMode::Create => None,
+        CreateMode::Upsert => match store.find(event.id).await {
+            Ok(existing) => existing,
+            Err(e) => return store_error_response(e),
+        },
+    };
+    if let Some(existing) = &existing {
+        event.created_at = existing.created_at;
+        event.version = existing.version + 1;
+    }
+    // Событие с временем окончания проверяем на пересечение с событиями того же календаря
+    // События ближе min_gap_minutes друг к другу тоже считаются пересекающимися
+    let gap = Duration::minutes(settings.min_gap_minutes as i64);
+    let conflicts = match overlapping_events(&store, &event, gap).await {
+        Ok(conflicts) => conflicts,
+        Err(e) => return store_error_response(e),
+    };
+    let overlaps: Vec<Uuid> = conflicts.iter().map(|other| other.id).collect();
+    if !overlaps.is_empty() && query_flag(&param, "reject_overlap") {
+        let res = json!({
+            "error": format!("event overlaps existing events"),
+            "overlaps": overlaps,
+            "conflicts": conflicts,
+        });
+        return (StatusCode::CONFLICT, Json(res)).into_response();
+    }
+    // Возвращаем созданное событие, в подробном режиме - вместе с текстовым описанием
+    let mut res = json!({
+        "id": event.id,
+        "result": event,
+    });
+    if query_flag(&param, "verbose") {
+        let action = if existing.is_some() {
+            "Replaced"
+        } else {
+            "Added"
+        };
+        res["summary"] = json!(format!(
+            "{} event: '{}' for date {} to calendar '{}'",
+            action, event.name, event.date, event.calendar
+        ));
+    }
+    if !overlaps.is_empty() {
+        let warnings: Vec<String> = overlaps
+            .iter()
+            .map(|id| format!("overlaps with {}", id))
+            .collect();
+        res["warnings"] = json!(warnings);
+    }
+    // Сохраняем полученные данные, хранилище не дает превысить лимит количества событий
+    // и добавить второе событие с тем же идентификатором
+    let (saved, status, change) = match &existing {
+        Some(existing) => (
+            audit
+                .record_events(
+                    &actor,
+                    AuditOp::Update,
+                    std::slice::from_ref(&event),
+                    store.update(event.clone(), existing.version),
+                )
+                .await,
+            StatusCode::OK,
+            ChangeKind::Updated,
+        ),
+        None => (
+            audit
+                .record_events(
+                    &actor,
+                    AuditOp::Create,
+                    std::slice::from_ref(&event),
+                    store.insert(event.clone()),
+                )
+                .await,
+            StatusCode::CREATED,
+            ChangeKind::Created,
+        ),
+    };
+    if let Err(e) = saved {
+        return store_error_response(e);
+    }
+    changes.publish(change, &event);
+    // Запоминаем только успешный ответ: при ошибке резерв ключа снимается, и запрос можно повторить с тем же ключом
+    if let Some(reserved) = reserved_key {
+        reserved.complete(status, res.clone());
+    }
+    (status, Json(res)).into_response()
+}
+
+// Обработчик создания нескольких событий за один запрос, тело - массив событий как при создании
+// Каждое событие проверяется отдельно, ошибки возвращаются с номером события в массиве
+// С ?atomic=true (или ?rollback_on_error=true) события добавляются только если ошибок нет ни в одном из них
+#[utoipa::path(
+    post,
+    path = "/events/bulk",
+    params(openapi::BulkParam),
+    request_body = Vec<openapi::EventReq>,
+    responses(
+        (status = 200, description = "Добавленные события и ошибки по номерам", body = openapi::BulkResponse),
+        (status = 400, description = "Тело не массив либо ошибки в атомарном режиме", body = openapi::BulkResponse),
+    )
+)]
+pub async fn bulk_create_handler(
+    State(AppState {
+        store,
+        settings,
+        changes,
+        audit,
+        ..
+    }): State<AppState>,
+    actor: Actor,
+    Query(param): Query<Value>,
+    Json(body): Json<Value>,
+) -> Response {
+    let items = match body {
+        Value::Array(items) => items,
+        _ => {
+            let res = json!({
+                "error": format!("expected an array of events"),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+    };
+    // rollback_on_error - другое название атомарного режима: при любой ошибке не добавляется ни одно событие
+    let atomic = query_flag(&param, "atomic") || query_flag(&param, "rollback_on_error");
+    let mut accepted: Vec<(usize, Event)> = Vec::with_capacity(items.len());
+    let mut errors: Vec<(usize, Value)> = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        match event_req_parse(item, settings.trim_strings) {
+            Ok(event) => accepted.push((index, event)),
+            Err(e) => errors.push((index, json!({ "index": index, "errors": e }))),
+        }
+    }
+    let mut created: Vec<Uuid> = Vec::with_capacity(accepted.len());
+    if atomic {
+        // В атомарном режиме при любой ошибке ничего не добавляем
+        let indexes: Vec<usize> = accepted.iter().map(|(index, _)| *index).collect();
+        let ids: Vec<Uuid> = accepted.iter().map(|(_, event)| event.id).collect();
+        let events: Vec<Event> = accepted.into_iter().map(|(_, event)| event).collect();
+        if errors.is_empty() {
+            match audit
+                .record_events(
+                    &actor,
+                    AuditOp::Create,
+                    &events,
+                    store.insert_all(events.clone()),
+                )
+                .await
+            {
+                Ok(()) => {
+                    for event in &events {
+                        changes.publish(ChangeKind::Created, event);
+                    }
+                    created = ids;
+                }
+                // Ошибка всего списка (например, изменения не удалось записать) не относится ни к одному событию
+                Err(BulkInsertError::Failed(e)) => return store_error_response(e),
+                Err(BulkInsertError::Rejected(store_errors)) => {
+                    for (i, e) in store_errors {
+                        let index = indexes[i];
+                        errors.push((
+                            index,
+                            json!({ "index": index, "error": store_error_message(&e) }),
+                        ));
+                    }
+                }
+            }
+        }
+    } else {
+        // Каждое событие добавляем отдельно: уже существующее или сверх лимита попадает в ошибки
+        for (index, event) in accepted {
+            let id = event.id;
+            match audit
+                .record_events(
+                    &actor,
+                    AuditOp::Create,
+                    std::slice::from_ref(&event),
+                    store.insert(event.clone()),
+                )
+                .await
+            {
+                Ok(()) => {
+                    changes.publish(ChangeKind::Created, &event);
+                    created.push(id);
+                }
+                Err(e) => errors.push((
+                    index,
+                    json!({ "index": index, "error": store_error_message(&e) }),
+                )),
+            }
+        }
+    }
+    errors.sort_by_key(|(index, _)| *index);
+    let errors: Vec<Value> = errors.into_iter().map(|(_, error)| error).collect();
+    let status = if atomic && !errors.is_empty() {
+        StatusCode::BAD_REQUEST
+    } else {
+        StatusCode::OK
+    };
+    let res = json!({
+        "mode": if atomic { "atomic" } else { "best_effort" },
+        "created": created,
+        "errors": errors,
+    });
+    (status, Json(res)).into_response()
+}
+
+// Функция для обновления данных о событии
+#[utoipa::path(
+    post,
+    path = "/update_event",
+    params(
+        ("Prefer" = Option<String>, Header, description = "return=minimal - не возвращать текущее событие в ответе 409"),
+    ),
+    request_body = openapi::EventUpdateReq,
+    responses(
+        (status = 200, description = "Событие изменено (устаревший маршрут, следует использовать PUT /event/{id})", body = openapi::EventResponse),
+        (status = 400, description = "Некорректные поля запроса", body = openapi::ValidationErrors),
+        (status = 404, description = "Событие не найдено", body = openapi::ErrorResponse),
+        (status = 409, description = "Событие изменено после того, как его прочитал клиент либо под описание подходит несколько событий", body = openapi::VersionConflict),
+    )
+)]
+pub async fn update_event_handler(
+    State(AppState {
+        store,
+        settings,
+        changes,
+        audit,
+        ..
+    }): State<AppState>,
+    actor: Actor,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    // Проверяем на валидность входные данные, пробелы в названиях обрезаются до любых проверок
+    let body = match event_update_parse(body, settings.trim_strings).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Проверяем что указанное событие пристутствует в памяти
+    // Дата запроса уже приведена к UTC, поэтому событие находится при любом смещении в запросе
+    let mut event =
+        match single_event_by_name(&*store, &body.calendar, body.date, &body.event_name).await {
+            Ok(value) => value,
+            Err(e) => {
+                return e;
+            }
+        };
+    // Изменяем данные, время окончания сдвигаем вместе с началом, сохраняя длительность
+    // Событие на весь день остается на весь день: от новой даты берется только день в UTC
+    let new_date = if event.all_day {
+        body.new_date
+            .date_naive()
+            .and_time(NaiveTime::MIN)
+            .and_utc()
+    } else {
+        body.new_date
+    };
+    event.end_date = event.end_date.map(|end| end + (new_date - event.date));
+    event.date = new_date;
+    event.name = body.new_event_name.clone();
+    event.updated_at = Utc::now();
+    // Если событие успели изменить после того, как клиент его прочитал, - изменение отклоняется
+    event.version = body.version + 1;
+    let res = json!({
+        "result": event,
+    });
+    match audit
+        .record_events(
+            &actor,
+            AuditOp::Update,
+            std::slice::from_ref(&event),
+            store.update(event.clone(), body.version),
+        )
+        .await
+    {
+        Ok(()) => {}
+        Err(StoreError::VersionMismatch(version)) => {
+            return version_conflict_response(&*store, event.id, version, &headers).await
+        }
+        Err(e) => return store_error_response(e),
+    }
+    changes.publish(ChangeKind::Updated, &event);
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик для удаления событий
+#[utoipa::path(
+    post,
+    path = "/delete_event",
+    request_body = openapi::EventReq,
+    responses(
+        (status = 200, description = "Событие удалено (устаревший маршрут, следует использовать DELETE /event/{id})", body = openapi::MessageResponse),
+        (status = 202, description = "Удаление отложено", body = openapi::ScheduledDeleteResponse),
+        (status = 400, description = "Некорректные поля запроса", body = openapi::ValidationErrors),
+        (status = 404, description = "Событие не найдено", body = openapi::ErrorResponse),
+        (status = 409, description = "Удаление уже назначено либо под описание подходит несколько событий", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn delete_event_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    Json(body): Json<Value>,
+) -> Response {
+    // Проверяем на валидность входные данные
+    let event = match json_body_parse(body, state.settings.trim_strings).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Проверяем что указанное событие было добавлено ранее
+    let found =
+        match single_event_by_name(&*state.store, &event.calendar, event.date, &event.name).await {
+            Ok(value) => value,
+            Err(e) => {
+                return e;
+            }
+        };
+    // Если задан период ожидания - откладываем удаление
+    if state.settings.delete_grace_period_secs > 0 {
+        return schedule_delete(&state, found, actor);
+    }
+    // Удаляем найденное событие
+    match state
+        .audit
+        .record(
+            &actor,
+            AuditOp::Delete,
+            &[found.id],
+            state.store.remove(found.id),
+        )
+        .await
+    {
+        Ok(removed) => state.changes.publish(ChangeKind::Deleted, &removed),
+        Err(e) => return store_error_response(e),
+    }
+    let res = json!({
+        "result": format!("Removed event: '{}' for date {}",event.name,event.date),
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Функция для поиска события устаревших маршрутов по календарю, дате и названию
+// Если под описание подходит несколько событий, выбрать одно нельзя - клиент должен указать идентификатор
+async fn single_event_by_name(
+    store: &dyn EventStore,
+    calendar: &str,
+    date: DateTime<Utc>,
+    name: &str,
+) -> Result<Event, Response> {
+    let mut found = match store.find_by_name(calendar, date, name).await {
+        Ok(found) => found,
+        Err(e) => return Err(store_error_response(e)),
+    };
+    match found.len() {
+        // Если указанное событие не было найдено - возвращаем  HTTP 404
+        0 => {
+            let res = json!({
+                "error": format!("event not found"),
+            });
+            Err((StatusCode::NOT_FOUND, Json(res)).into_response())
+        }
+        1 => Ok(found.remove(0)),
+        _ => {
+            let res = json!({
+                "error": format!("ambiguous, use /event/:id"),
+                "ids": found.iter().map(|event| event.id).collect::<Vec<_>>(),
+            });
+            Err((StatusCode::CONFLICT, Json(res)).into_response())
+        }
+    }
+}
+
+// Функция для отложенного удаления события: событие остается в памяти до истечения периода ожидания
+// В журнал изменений удаление попадает при фактическом удалении от имени запросившего его клиента
+fn schedule_delete(state: &AppState, event: Event, actor: Actor) -> Response {
+    let mut pending = state.pending_deletes.lock().unwrap_or_else(|e| {
+        tracing::warn!("recovered poisoned pending deletes lock");
+        state.pending_deletes.clear_poison();
+        e.into_inner()
+    });
+    // Повторное удаление уже ожидающего события не допускаем
+    if pending
+        .values()
+        .any(|p| p.calendar == event.calendar && p.date == event.date && p.name == event.name)
+    {
+        let res = json!({
+            "error": format!("Deletion already scheduled"),
+        });
+        return (StatusCode::CONFLICT, Json(res)).into_response();
+    }
+    let id = state.next_pending_id.fetch_add(1, Ordering::Relaxed);
+    let res = json!({
+        "result": format!("Scheduled removal of event: '{}' for date {}", event.name, event.date),
+        "undo_id": id,
+        "delay_secs": state.settings.delete_grace_period_secs,
+    });
+    pending.insert(id, event);
+
+    // Фоновая задача удаляет событие, если удаление не было отменено
+    let state = state.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_secs(
+            state.settings.delete_grace_period_secs,
+        ))
+        .await;
+        let event = state
+            .pending_deletes
+            .lock()
+            .unwrap_or_else(|e| {
+                tracing::warn!("recovered poisoned pending deletes lock");
+                state.pending_deletes.clear_poison();
+                e.into_inner()
+            })
+            .remove(&id);
+        if let Some(event) = event {
+            match state
+                .audit
+                .record(
+                    &actor,
+                    AuditOp::Delete,
+                    &[event.id],
+                    state.store.remove(event.id),
+                )
+                .await
+            {
+                Ok(removed) => state.changes.publish(ChangeKind::Deleted, &removed),
+                Err(e) => {
+                    tracing::warn!(id = %event.id, error = ?e, "failed to remove event after grace period")
+                }
+            }
+        }
+    });
+    (StatusCode::ACCEPTED, Json(res)).into_response()
+}
+
+// Обработчик для отмены отложенного удаления
+#[utoipa::path(
+    post,
+    path = "/undo_delete",
+    request_body = UndoDeleteReq,
+    responses(
+        (status = 200, description = "Удаление отменено", body = openapi::MessageResponse),
+        (status = 400, description = "Некорректное тело запроса", body = openapi::ErrorResponse),
+        (status = 404, description = "Отложенное удаление не найдено", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn undo_delete_handler(
+    State(AppState {
+        pending_deletes, ..
+    }): State<AppState>,
+    Json(body): Json<Value>,
+) -> Response {
+    let body: UndoDeleteReq = match serde_json::from_value(body) {
+        Ok(body) => body,
+        Err(e) => {
+            let res = json!({
+                "error": format!("{}",e),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+    };
+    let removed = pending_deletes
+        .lock()
+        .unwrap_or_else(|e| {
+            tracing::warn!("recovered poisoned pending deletes lock");
+            pending_deletes.clear_poison();
+            e.into_inner()
+        })
+        .remove(&body.id);
+    match removed {
+        Some(event) => {
+            let res = json!({
+                "result": format!("Restored event: '{}' for date {}", event.name, event.date),
+            });
+            (StatusCode::OK, Json(res)).into_response()
+        }
+        // Период ожидания истек либо удаление с таким id не назначалось
+        None => {
+            let res = json!({
+                "error": format!("pending deletion not found"),
+            });
+            (StatusCode::NOT_FOUND, Json(res)).into_response()
+        }
+    }
+}
+
+// Обработчик, возвращающий событие по его идентификатору
+// В ответе - ETag события; если клиент передал его в If-None-Match и событие не менялось, ответ 304 без тела
+#[utoipa::path(
+    get,
+    path = "/event/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Идентификатор события"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag ранее полученного события"),
+    ),
+    responses(
+        (status = 200, description = "Событие, с заголовком Accept: application/x-ndjson - одной строкой, application/xml - в XML", body = openapi::EventResponse),
+        (status = 304, description = "Событие не изменилось"),
+        (status = 404, description = "Событие не найдено", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn get_event_handler(
+    State(AppState { store, .. }): State<AppState>,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    match store.find(id).await {
+        Ok(Some(event)) => {
+            let etag = event_etag(&event);
+            if etag_matches(&headers, &etag) {
+                return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+            }
+            let mut res = match format {
+                ResponseFormat::Json => Json(json!({
+                    "result": event,
+                }))
+                .into_response(),
+                ResponseFormat::Ndjson => ndjson_response(vec![event], None),
+                ResponseFormat::Xml => xml_response(xml::write_event(&event)),
+            };
+            if let Ok(etag) = HeaderValue::from_str(&etag) {
+                res.headers_mut().insert(header::ETAG, etag);
+            }
+            res
+        }
+        Ok(None) => {
+            let res = json!({
+                "error": format!("event not found"),
+            });
+            (StatusCode::NOT_FOUND, Json(res)).into_response()
+        }
+        Err(e) => store_error_response(e),
+    }
+}
+
+// Функция для вычисления ETag события из его идентификатора и версии
+// Любое изменение события увеличивает версию, а значит меняет и ETag; между перезапусками и сборками ETag не меняется
+fn event_etag(event: &Event) -> String {
+    format!("\"{}-{}\"", event.id, event.version)
+}
+
+// Функция для проверки заголовка If-None-Match: список ETag через запятую или *
+// Слабые ETag (W/"...") сравниваются без учета префикса, как требует RFC 9110 для If-None-Match
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+// Обработчик полной замены события по его идентификатору, тело запроса - как при создании события
+// Клиент передает версию, которую видел: поле version тела или ETag события в заголовке If-Match
+#[utoipa::path(
+    put,
+    path = "/event/{id}",
+    params(
+        ("id" = Uuid, Path, description = "Идентификатор события"),
+        ("If-Match" = Option<String>, Header, description = "ETag события, которое видел клиент, вместо поля version"),
+        ("Prefer" = Option<String>, Header, description = "return=minimal - не возвращать текущее событие в ответе 409"),
+    ),
+    request_body = openapi::EventReplaceReq,
+    responses(
+        (status = 200, description = "Событие заменено", body = openapi::EventResponse),
+        (status = 400, description = "Некорректные поля запроса", body = openapi::ValidationErrors),
+        (status = 404, description = "Событие не найдено", body = openapi::ErrorResponse),
+        (status = 409, description = "Событие изменено после того, как его прочитал клиент", body = openapi::VersionConflict),
+    )
+)]
+pub async fn replace_event_handler(
+    State(AppState {
+        store,
+        settings,
+        changes,
+        audit,
+        ..
+    }): State<AppState>,
+    actor: Actor,
+    Path(id): Path<Uuid>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Response {
+    let expected_version = match expected_version_parse(&body, &headers, id).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Проверяем на валидность входные данные
+    let mut event = match json_body_parse(body, settings.trim_strings).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let existing = match store.find(id).await {
+        Ok(Some(existing)) => existing,
+        Ok(None) => {
+            let res = json!({
+                "error": format!("event not found"),
+            });
+            return (StatusCode::NOT_FOUND, Json(res)).into_response();
+        }
+        Err(e) => return store_error_response(e),
+    };
+    // Идентификатор и время создания события при замене сохраняются
+    event.id = id;
+    event.created_at = existing.created_at;
+    event.version = expected_version + 1;
+    let res = json!({
+        "result": event,
+    });
+    // Если календарь изменился - хранилище переносит событие в новый календарь
+    // Если событие успели изменить после того, как клиент его прочитал, - замена отклоняется
+    match audit
+        .record_events(
+            &actor,
+            AuditOp::Update,
+            std::slice::from_ref(&event),
+            store.update(event.clone(), expected_version),
+        )
+        .await
+    {
+        Ok(()) => {}
+        Err(StoreError::VersionMismatch(version)) => {
+            return version_conflict_response(&*store, id, version, &headers).await
+        }
+        Err(e) => return store_error_response(e),
+    }
+    changes.publish(ChangeKind::Updated, &event);
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Функция для формирования ответа 409 о том, что событие изменено после того, как его прочитал клиент
+// Вместе с ошибкой возвращается текущее событие (поле current), чтобы клиенту не нужно было перечитывать его отдельно
+// Заголовок Prefer: return=minimal отключает current (остается только версия), return=representation - включает явно
+// Если предпочтение учтено, оно повторяется в заголовке Preference-Applied
+async fn version_conflict_response(
+    store: &dyn EventStore,
+    id: Uuid,
+    version: u64,
+    headers: &HeaderMap,
+) -> Response {
+    let preference = headers
+        .get_all(PREFER_HEADER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|preference| preference.trim().to_ascii_lowercase())
+        .find(|preference| preference == "return=minimal" || preference == "return=representation");
+    let current = match preference.as_deref() {
+        Some("return=minimal") => None,
+        _ => store.find(id).await.ok().flatten(),
+    };
+    let res = match &current {
+        Some(current) => json!({
+            "error": format!("version mismatch"),
+            "version": current.version,
+            "current": current,
+        }),
+        None => json!({
+            "error": format!("version mismatch"),
+            "version": version,
+        }),
+    };
+    let mut res = (StatusCode::CONFLICT, Json(res)).into_response();
+    if let Some(preference) = preference {
+        if let Ok(value) = HeaderValue::from_str(&preference) {
+            res.headers_mut().insert("preference-applied", value);
+        }
+    }
+    res
+}
+
+// Функция для извлечения версии события, которую видел клиент, для PUT /event/:id
+// Заголовок If-Match с ETag события (см. event_etag) заменяет поле version тела запроса
+async fn expected_version_parse(
+    body: &Value,
+    headers: &HeaderMap,
+    id: Uuid,
+) -> Result<u64, Response> {
+    if let Some(value) = headers.get(header::IF_MATCH) {
+        let version = value
+            .to_str()
+            .ok()
+            .map(|tag| tag.trim().trim_matches('"'))
+            .and_then(|tag| tag.strip_prefix(&format!("{}-", id)))
+            .and_then(|version| version.parse::<u64>().ok());
+        return match version {
+            Some(version) => Ok(version),
+            None => {
+                let res = json!({
+                    "error": format!("If-Match must be the ETag of this event"),
+                });
+                Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+            }
+        };
+    }
+    let mut validator = Validator::new(body);
+    let version = validator.field("version");
+    match version {
+        Some(version) => Ok(version),
+        None => Err(validation::errors_response(validator.into_errors())),
+    }
+}
+
+// Обработчик удаления события по его идентификатору
+#[utoipa::path(
+    delete,
+    path = "/event/{id}",
+    params(("id" = Uuid, Path, description = "Идентификатор события")),
+    responses(
+        (status = 200, description = "Событие удалено", body = openapi::MessageResponse),
+        (status = 202, description = "Удаление отложено", body = openapi::ScheduledDeleteResponse),
+        (status = 404, description = "Событие не найдено", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn delete_event_by_id_handler(
+    State(state): State<AppState>,
+    actor: Actor,
+    Path(id): Path<Uuid>,
+) -> Response {
+    let event = match state.store.find(id).await {
+        Ok(Some(event)) => event,
+        Ok(None) => {
+            let res = json!({
+                "error": format!("event not found"),
+            });
+            return (StatusCode::NOT_FOUND, Json(res)).into_response();
+        }
+        Err(e) => return store_error_response(e),
+    };
+    // Если задан период ожидания - откладываем удаление
+    if state.settings.delete_grace_period_secs > 0 {
+        return schedule_delete(&state, event, actor);
+    }
+    let event = match state
+        .audit
+        .record(&actor, AuditOp::Delete, &[id], state.store.remove(id))
+        .await
+    {
+        Ok(event) => event,
+        Err(e) => return store_error_response(e),
+    };
+    state.changes.publish(ChangeKind::Deleted, &event);
+    let res = json!({
+        "result": format!("Removed event: '{}' for date {}", event.name, event.date),
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик восстановления мягко удаленного события по его идентификатору
+#[utoipa::path(
+    post,
+    path = "/event/{id}/restore",
+    params(("id" = Uuid, Path, description = "Идентификатор события")),
+    responses(
+        (status = 200, description = "Событие восстановлено", body = openapi::EventResponse),
+        (status = 404, description = "Удаленное событие не найдено", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn restore_event_handler(
+    State(AppState {
+        store,
+        changes,
+        audit,
+        ..
+    }): State<AppState>,
+    actor: Actor,
+    Path(id): Path<Uuid>,
+) -> Response {
+    match audit
+        .record(&actor, AuditOp::Restore, &[id], store.restore(id))
+        .await
+    {
+        Ok(event) => {
+            // Для подписчиков восстановленное событие появляется заново
+            changes.publish(ChangeKind::Created, &event);
+            let res = json!({
+                "result": event,
+            });
+            (StatusCode::OK, Json(res)).into_response()
+        }
+        Err(StoreError::NotFound) => {
+            let res = json!({
+                "error": format!("deleted event not found"),
+            });
+            (StatusCode::NOT_FOUND, Json(res)).into_response()
+        }
+        Err(e) => store_error_response(e),
+    }
+}
+
+// Функция для поиска событий календаря, пересекающихся по времени с указанным событием
+// Проверяются только события с временем окончания, событие без окончания считается моментом
+// Интервалы, касающиеся границами (одно заканчивается, когда начинается другое), не пересекаются
+// С ненулевым промежутком gap пересекающимися считаются и события, между которыми меньше gap,
+// тогда проверяются и события без окончания
+async fn overlapping_events(
+    store: &Arc<dyn EventStore>,
+    event: &Event,
+    gap: Duration,
+) -> Result<Vec<Event>, StoreError> {
+    let end_date = match event.end_date {
+        Some(end_date) => end_date,
+        None if gap > Duration::zero() => event.date,
+        None => return Ok(Vec::new()),
+    };
+    let (from, to) = (
+        (event.date - gap).date_naive(),
+        (end_date + gap).date_naive(),
+    );
+    // Календаря еще нет - пересекаться не с чем
+    let events = match store.query_range(Some(&event.calendar), from, to).await {
+        Ok(events) => events,
+        Err(StoreError::CalendarNotFound(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut overlaps: Vec<Event> = events
+        .iter()
+        .flat_map(|other| occurrences(other, from, to, Tz::UTC, MidnightBoundary::Start))
+        // Заменяемое событие (mode=upsert) с самим собой не пересекается
+        .filter(|other| other.id != event.id)
+        .filter(|other| {
+            other.date < end_date + gap && event.date < other.end_date.unwrap_or(other.date) + gap
+        })
+        .collect();
+    // Несколько повторений одного события дают один и тот же id подряд
+    overlaps.dedup_by_key(|other| other.id);
+    Ok(overlaps)
+}
+
+// Функция для извлечения из json старого и нового значения события для /update_event
+async fn event_update_parse(body: Value, trim: bool) -> Result<EventUpdateReq, Response> {
+    let mut validator = Validator::new(&body);
+    let calendar = validator.non_empty_string("calendar", false);
+    let date = validator.date_time("date_time");
+    let event_name = validator.non_empty_string("event_name", trim);
+    let new_date = validator.date_time("new_date_time");
+    let new_event_name = validator.non_empty_string("new_event_name", trim);
+    let version = validator.field("version");
+    match (
+        calendar,
+        date,
+        event_name,
+        new_date,
+        new_event_name,
+        version,
+    ) {
+        (
+            Some(calendar),
+            Some(date),
+            Some(event_name),
+            Some(new_date),
+            Some(new_event_name),
+            Some(version),
+        ) => Ok(EventUpdateReq {
+            calendar,
+            date,
+            event_name,
+            new_date,
+            new_event_name,
+            version,
+        }),
+        _ => Err(validation::errors_response(validator.into_errors())),
+    }
+}
+
+// Старое и новое значение изменяемого события
+pub struct EventUpdateReq {
+    pub calendar: String,
+    pub date: DateTime<Utc>,
+    pub event_name: String,
+    pub new_date: DateTime<Utc>,
+    pub new_event_name: String,
+    // Версия события, которую видел клиент
+    pub version: u64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UndoDeleteReq {
+    pub id: u64,
+}
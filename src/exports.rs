@@ -0,0 +1,258 @@
+// Обработчики выгрузки событий в форматах RSS, iCalendar и CSV
+use std::convert::Infallible;
+
+use axum::{
+    body::StreamBody,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{SecondsFormat, Utc};
+use chrono_tz::Tz;
+use futures_util::{stream, StreamExt};
+use serde_json::Value;
+
+use crate::{
+    ical,
+    model::{occurrences, upcoming_occurrences, Event},
+    openapi,
+    params::{calendar_parse, categories_match, categories_parse, range_parse},
+    responses::{
+        export_etag, export_response, partial_response, range_header, store_error_response,
+    },
+    AppState, MAX_PAGE_LIMIT,
+};
+
+const ICS_CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+const CSV_CONTENT_TYPE: &str = "text/csv; charset=utf-8";
+
+// Обработчик, возвращающий предстоящие события в виде RSS-ленты
+// Если указан календарь - только события этого календаря, иначе события всех календарей
+#[utoipa::path(
+    get,
+    path = "/events.rss",
+    params(openapi::ExportParam),
+    responses(
+        (status = 200, description = "Лента RSS", content_type = "application/rss+xml", body = String),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_rss_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    // Оставляем только вхождения, которые еще не наступили, как в /events/upcoming
+    // Повторяющееся событие дает бесконечно много вхождений, поэтому лента ограничена MAX_PAGE_LIMIT элементами
+    let now = Utc::now();
+    let mut upcoming: Vec<Event> = events
+        .iter()
+        .flat_map(|event| upcoming_occurrences(event, now, MAX_PAGE_LIMIT))
+        .collect();
+    upcoming.sort_by_key(|event| event.date);
+    upcoming.truncate(MAX_PAGE_LIMIT);
+
+    let link = format!("http://{}:{}/", settings.address, settings.port);
+    let mut rss = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    rss.push_str("<rss version=\"2.0\">\n<channel>\n");
+    rss.push_str("<title>Upcoming events</title>\n");
+    rss.push_str(&format!("<link>{}</link>\n", xml_escape(&link)));
+    rss.push_str("<description>Upcoming calendar events</description>\n");
+    for event in upcoming {
+        rss.push_str("<item>\n");
+        rss.push_str(&format!("<title>{}</title>\n", xml_escape(&event.name)));
+        // Описание события, а у событий без описания - название и дата
+        let description = event
+            .description
+            .clone()
+            .unwrap_or_else(|| format!("Event '{}' for date {}", event.name, event.date));
+        rss.push_str(&format!(
+            "<description>{}</description>\n",
+            xml_escape(&description)
+        ));
+        rss.push_str(&format!("<pubDate>{}</pubDate>\n", event.date.to_rfc2822()));
+        rss.push_str("</item>\n");
+    }
+    rss.push_str("</channel>\n</rss>\n");
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "application/rss+xml")],
+        rss,
+    )
+        .into_response()
+}
+
+// Обработчик, возвращающий события в формате iCalendar для подписки из других календарей
+// Если указан календарь - только события этого календаря, иначе события всех календарей
+// Если указан диапазон from/to - только события, у которых есть вхождения в этом диапазоне
+// Если указаны категории (теги) через запятую - только события хотя бы с одной из них
+#[utoipa::path(
+    get,
+    path = "/events.ics",
+    params(openapi::IcsExportParam),
+    responses(
+        (status = 200, description = "События в формате iCalendar", content_type = "text/calendar", body = String),
+        (status = 206, description = "Часть выгрузки по заголовку Range", content_type = "text/calendar", body = String),
+        (status = 416, description = "Запрошенный диапазон за концом выгрузки"),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_ics_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let categories = match categories_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let range = if param.get("from").is_some() || param.get("to").is_some() {
+        match range_parse(param, settings.max_range_days).await {
+            Ok(value) => Some(value),
+            Err(e) => {
+                return e;
+            }
+        }
+    } else {
+        None
+    };
+    let mut events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    events.retain(|event| categories_match(event, categories.as_deref()));
+    if let Some((from, to)) = range {
+        events.retain(|event| {
+            !occurrences(event, from, to, Tz::UTC, settings.midnight_boundary).is_empty()
+        });
+    }
+    events.sort_by_key(|event| event.date);
+
+    let etag = export_etag(&events);
+    let ics = ical::write_calendar(&events);
+    match range_header(&headers, &etag) {
+        Some(range) => partial_response(range, ICS_CONTENT_TYPE, etag, ics.into_bytes()),
+        None => export_response(ICS_CONTENT_TYPE, etag, ics),
+    }
+}
+
+// Обработчик, отдающий события потоком в формате CSV: строка заголовка id,date,name и по строке на событие
+// Если указан календарь - только события этого календаря, иначе события всех календарей
+// Если указан диапазон from/to - вхождения событий в этом диапазоне, как в /events_in_range
+#[utoipa::path(
+    get,
+    path = "/events.csv",
+    params(openapi::ExportParam),
+    responses(
+        (status = 200, description = "События в формате CSV", content_type = "text/csv", body = String),
+        (status = 206, description = "Часть выгрузки по заголовку Range", content_type = "text/csv", body = String),
+        (status = 416, description = "Запрошенный диапазон за концом выгрузки"),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_csv_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let range = if param.get("from").is_some() || param.get("to").is_some() {
+        match range_parse(param, settings.max_range_days).await {
+            Ok(value) => Some(value),
+            Err(e) => {
+                return e;
+            }
+        }
+    } else {
+        None
+    };
+    let events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    let mut events: Vec<Event> = match range {
+        Some((from, to)) => events
+            .iter()
+            .flat_map(|event| occurrences(event, from, to, Tz::UTC, settings.midnight_boundary))
+            .collect(),
+        None => events,
+    };
+    events.sort_by_key(|event| event.date);
+
+    let etag = export_etag(&events);
+    // Строки формируются по мере отправки, а не собираются в один большой ответ
+    // Только для запроса части выгрузки она собирается целиком, чтобы знать ее длину
+    let rows =
+        std::iter::once("id,date,name\r\n".to_string()).chain(events.into_iter().map(|event| {
+            // У события на весь день выводится только дата
+            let date = if event.all_day {
+                event.date.date_naive().to_string()
+            } else {
+                event.date.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            };
+            format!("{},{},{}\r\n", event.id, date, csv_escape(&event.name))
+        }));
+    if let Some(range) = range_header(&headers, &etag) {
+        let csv: String = rows.collect();
+        return partial_response(range, CSV_CONTENT_TYPE, etag, csv.into_bytes());
+    }
+    let stream = stream::iter(rows).map(Ok::<_, Infallible>);
+    export_response(CSV_CONTENT_TYPE, etag, StreamBody::new(stream))
+}
+
+// Функция для экранирования значения CSV
+// Значение с запятой, кавычкой или переводом строки берется в кавычки, кавычки внутри удваиваются
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Функция для экранирования спецсимволов XML
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,302 @@
+// Формат iCalendar (RFC 5545) для обмена событиями с другими календарями
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::{Event, Recurrence};
+
+// Формат даты и времени в UTC, например 20240601T100000Z
+const DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+// Формат местного времени для даты с параметром TZID, например 20240601T100000
+const LOCAL_DATE_TIME_FORMAT: &str = "%Y%m%dT%H%M%S";
+// Формат даты без времени, например 20240601
+const DATE_FORMAT: &str = "%Y%m%d";
+// Максимальная длина строки в октетах, более длинные строки переносятся
+const MAX_LINE_OCTETS: usize = 75;
+
+// Функция для записи событий в формате iCalendar
+// Повторяющееся событие записывается одним VEVENT с правилом RRULE
+pub fn write_calendar(events: &[Event]) -> String {
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//l2.11//calendar//EN");
+    for event in events {
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(&mut ics, &format!("UID:{}", event.id));
+        // Повторения в часовом поясе события сохраняют местное время, поэтому и даты записываются в нем с TZID
+        let repeat_tz = event.tz.filter(|_| event.recurrence.is_some());
+        // Метка берется из времени изменения события, чтобы одна и та же выгрузка совпадала байт в байт
+        // и ее можно было докачивать по частям
+        push_line(
+            &mut ics,
+            &format!("DTSTAMP:{}", event.updated_at.format(DATE_TIME_FORMAT)),
+        );
+        // Событие на весь день записывается датой без времени
+        if event.all_day {
+            push_line(
+                &mut ics,
+                &format!("DTSTART;VALUE=DATE:{}", event.date.format(DATE_FORMAT)),
+            );
+        } else {
+            push_line(&mut ics, &date_time_line("DTSTART", event.date, repeat_tz));
+        }
+        if let Some(end_date) = event.end_date {
+            push_line(&mut ics, &date_time_line("DTEND", end_date, repeat_tz));
+        }
+        push_line(&mut ics, &format!("SUMMARY:{}", escape_text(&event.name)));
+        if let Some(description) = &event.description {
+            push_line(
+                &mut ics,
+                &format!("DESCRIPTION:{}", escape_text(description)),
+            );
+        }
+        if let Some(location) = &event.location {
+            push_line(&mut ics, &format!("LOCATION:{}", escape_text(location)));
+        }
+        if let Some(recurrence) = event.recurrence {
+            push_line(&mut ics, &rrule(recurrence, event.recurrence_until));
+        }
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+// Строка даты и времени: в UTC либо местное время с параметром TZID, если задан часовой пояс
+fn date_time_line(name: &str, date: DateTime<Utc>, tz: Option<Tz>) -> String {
+    match tz {
+        Some(tz) => format!(
+            "{};TZID={}:{}",
+            name,
+            tz.name(),
+            date.with_timezone(&tz).format(LOCAL_DATE_TIME_FORMAT)
+        ),
+        None => format!("{}:{}", name, date.format(DATE_TIME_FORMAT)),
+    }
+}
+
+// Правило повторения события в формате RRULE
+fn rrule(recurrence: Recurrence, until: Option<DateTime<Utc>>) -> String {
+    let freq = match recurrence {
+        Recurrence::Daily => "DAILY",
+        Recurrence::Weekly => "WEEKLY",
+        Recurrence::Monthly => "MONTHLY",
+    };
+    match until {
+        Some(until) => format!(
+            "RRULE:FREQ={};UNTIL={}",
+            freq,
+            until.format(DATE_TIME_FORMAT)
+        ),
+        None => format!("RRULE:FREQ={}", freq),
+    }
+}
+
+// Экранирование текстового значения: обратная косая черта, запятая, точка с запятой и перевод строки
+fn escape_text(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ',' => escaped.push_str("\\,"),
+            ';' => escaped.push_str("\\;"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Добавляем строку с окончанием CRLF
+// Строки длиннее 75 октетов переносятся: продолжение начинается с пробела, символы UTF-8 не разрываются
+fn push_line(ics: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            ics.push_str("\r\n ");
+            octets = 1;
+        }
+        ics.push(c);
+        octets += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+// Событие, прочитанное из файла iCalendar
+pub struct IcsEvent {
+    pub date: DateTime<Utc>,
+    pub end_date: Option<DateTime<Utc>>,
+    pub summary: String,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    // Событие на весь день: DTSTART без времени и окончание не позже следующего дня
+    pub all_day: bool,
+}
+
+// Функция для чтения событий из файла iCalendar
+// Из каждого VEVENT берутся DTSTART, SUMMARY и необязательные DTEND, DESCRIPTION и LOCATION, вложенные компоненты (например VALARM) пропускаются
+// Если файл не является календарем или у события нет даты начала или названия - возвращается ошибка
+pub fn parse_calendar(ics: &str) -> Result<Vec<IcsEvent>, String> {
+    let lines = unfold_lines(ics);
+    let mut lines = lines.iter().filter(|line| !line.is_empty());
+    if lines.next().map(|line| line.to_ascii_uppercase()) != Some("BEGIN:VCALENDAR".to_string()) {
+        return Err("expected BEGIN:VCALENDAR".to_string());
+    }
+    let mut events = Vec::new();
+    // Свойства текущего VEVENT и глубина вложенных в него компонентов
+    let mut current: Option<VEvent> = None;
+    let mut nested = 0;
+    let mut closed = false;
+    for line in lines {
+        let (name, params, value) = split_line(line)?;
+        match (name.as_str(), current.as_mut()) {
+            ("BEGIN", None) if value.eq_ignore_ascii_case("VEVENT") => {
+                current = Some(VEvent::default())
+            }
+            ("BEGIN", Some(_)) => nested += 1,
+            ("END", Some(_)) if nested > 0 => nested -= 1,
+            ("END", Some(vevent)) if value.eq_ignore_ascii_case("VEVENT") => {
+                let date = vevent.date.ok_or("VEVENT without DTSTART")?;
+                let summary = vevent.summary.take().ok_or("VEVENT without SUMMARY")?;
+                // Окончание не позже начала не считаем длительностью
+                let end_date = vevent.end_date.filter(|end_date| *end_date > date);
+                // У однодневного события на весь день DTEND - следующий день, длительностью его не считаем
+                let all_day = vevent.all_day
+                    && end_date.is_none_or(|end_date| end_date <= date + Duration::days(1));
+                events.push(IcsEvent {
+                    date,
+                    end_date: if all_day { None } else { end_date },
+                    summary,
+                    description: vevent.description.take(),
+                    location: vevent.location.take(),
+                    all_day,
+                });
+                current = None;
+            }
+            ("END", None) if value.eq_ignore_ascii_case("VCALENDAR") => {
+                closed = true;
+                break;
+            }
+            (_, Some(_)) if nested > 0 => {}
+            ("DTSTART", Some(vevent)) => {
+                vevent.date = Some(parse_date_time(&params, value)?);
+                vevent.all_day = is_date(&params, value);
+            }
+            ("DTEND", Some(vevent)) => vevent.end_date = Some(parse_date_time(&params, value)?),
+            ("SUMMARY", Some(vevent)) => vevent.summary = Some(unescape_text(value)),
+            ("DESCRIPTION", Some(vevent)) => vevent.description = Some(unescape_text(value)),
+            ("LOCATION", Some(vevent)) => vevent.location = Some(unescape_text(value)),
+            _ => {}
+        }
+    }
+    if current.is_some() || !closed {
+        return Err("unexpected end of calendar".to_string());
+    }
+    Ok(events)
+}
+
+// Свойства читаемого VEVENT
+#[derive(Default)]
+struct VEvent {
+    date: Option<DateTime<Utc>>,
+    end_date: Option<DateTime<Utc>>,
+    summary: Option<String>,
+    description: Option<String>,
+    location: Option<String>,
+    all_day: bool,
+}
+
+// Склеиваем перенесенные строки: строка, начинающаяся с пробела или табуляции, продолжает предыдущую
+fn unfold_lines(ics: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in ics.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+// Параметры свойства: пары (название, значение), например (TZID, Europe/Moscow)
+type Params = Vec<(String, String)>;
+
+// Разбираем строку вида NAME;PARAM=VALUE:значение на название свойства, параметры и значение
+fn split_line(line: &str) -> Result<(String, Params, &str), String> {
+    let (head, value) = line
+        .split_once(':')
+        .ok_or_else(|| format!("invalid content line '{}'", line))?;
+    let mut parts = head.split(';');
+    let name = parts.next().unwrap_or_default().to_ascii_uppercase();
+    let params = parts
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| {
+            (
+                key.to_ascii_uppercase(),
+                value.trim_matches('"').to_string(),
+            )
+        })
+        .collect();
+    Ok((name, params, value))
+}
+
+// Дата начала или окончания события: в UTC (20240601T100000Z), в часовом поясе TZID, без пояса (считаем UTC)
+// либо дата без времени (VALUE=DATE) - такое событие на весь день сохраняется на полночь UTC
+fn parse_date_time(params: &Params, value: &str) -> Result<DateTime<Utc>, String> {
+    let param = |name: &str| {
+        params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let invalid = |e: chrono::ParseError| format!("invalid date '{}': {}", value, e);
+    if is_date(params, value) {
+        let date = NaiveDate::parse_from_str(value, DATE_FORMAT).map_err(invalid)?;
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    if let Some(value) = value.strip_suffix('Z') {
+        let date = NaiveDateTime::parse_from_str(value, LOCAL_DATE_TIME_FORMAT).map_err(invalid)?;
+        return Ok(date.and_utc());
+    }
+    let date = NaiveDateTime::parse_from_str(value, LOCAL_DATE_TIME_FORMAT).map_err(invalid)?;
+    match param("TZID") {
+        Some(tzid) => {
+            let tz: Tz = tzid
+                .parse()
+                .map_err(|e| format!("invalid TZID '{}': {}", tzid, e))?;
+            tz.from_local_datetime(&date)
+                .earliest()
+                .map(|date| date.with_timezone(&Utc))
+                .ok_or_else(|| format!("date '{}' does not exist in {}", value, tzid))
+        }
+        None => Ok(date.and_utc()),
+    }
+}
+
+// Функция для проверки, что значение - дата без времени (VALUE=DATE)
+fn is_date(params: &Params, value: &str) -> bool {
+    params
+        .iter()
+        .any(|(key, param)| key == "VALUE" && param == "DATE")
+        || value.len() == 8
+}
+
+// Обратное экранирование текстового значения
+fn unescape_text(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => unescaped.push('\n'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
@@ -0,0 +1,122 @@
+// Ключи идемпотентности запросов создания события
+// Ключ резервируется до выполнения запроса, поэтому одновременные повторы одного запроса не создают событие дважды:
+// они дожидаются ответа первого запроса и получают его копию
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use axum::http::StatusCode;
+use serde_json::Value;
+use tokio::sync::watch;
+
+// Ключ вместе с ключом API клиента: клиент не может получить ответ на запрос другого клиента
+type Key = (Option<String>, String);
+// Ответ, сохраненный для повторов
+type Saved = (StatusCode, Value);
+
+enum Entry {
+    // Запрос с этим ключом еще выполняется, по каналу придет его ответ
+    InFlight(watch::Receiver<Option<Saved>>),
+    // Ответ получен в указанное время
+    Done(Instant, StatusCode, Value),
+}
+
+#[derive(Default)]
+pub struct IdempotencyKeys {
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+// Результат резервирования ключа
+pub enum Reservation {
+    // Запрос с этим ключом уже выполнен - нужно вернуть его ответ
+    Replay(StatusCode, Value),
+    // Ключ зарезервирован за текущим запросом
+    Reserved(ReservedKey),
+}
+
+// Зарезервированный ключ: если запрос завершился без сохранения ответа (ошибкой или был прерван),
+// резерв снимается, и ожидающие повторы выполняются заново
+pub struct ReservedKey {
+    keys: Arc<IdempotencyKeys>,
+    key: Key,
+    sender: watch::Sender<Option<Saved>>,
+    completed: bool,
+}
+
+impl IdempotencyKeys {
+    // Паника во время удержания блокировки не должна делать ключи недоступными
+    fn lock(&self) -> MutexGuard<'_, HashMap<Key, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| {
+            tracing::warn!("recovered poisoned idempotency keys lock");
+            self.entries.clear_poison();
+            e.into_inner()
+        })
+    }
+
+    // Резервируем ключ за запросом либо возвращаем сохраненный ответ
+    // Если запрос с тем же ключом еще выполняется - дожидаемся его завершения
+    pub async fn reserve(
+        self: &Arc<Self>,
+        api_key: Option<&str>,
+        key: &str,
+        ttl: Duration,
+    ) -> Reservation {
+        let key: Key = (api_key.map(str::to_string), key.to_string());
+        loop {
+            let mut receiver = {
+                let mut entries = self.lock();
+                // Заодно забываем ключи, срок хранения которых истек
+                entries.retain(|_, entry| match entry {
+                    Entry::InFlight(_) => true,
+                    Entry::Done(created, _, _) => created.elapsed() < ttl,
+                });
+                match entries.get(&key) {
+                    Some(Entry::Done(_, status, res)) => {
+                        return Reservation::Replay(*status, res.clone());
+                    }
+                    Some(Entry::InFlight(receiver)) => receiver.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        entries.insert(key.clone(), Entry::InFlight(receiver));
+                        return Reservation::Reserved(ReservedKey {
+                            keys: self.clone(),
+                            key,
+                            sender,
+                            completed: false,
+                        });
+                    }
+                }
+            };
+            // Первый запрос либо сохранит ответ, либо снимет резерв - тогда пробуем зарезервировать ключ снова
+            let saved = match receiver.wait_for(Option::is_some).await {
+                Ok(saved) => saved.clone(),
+                Err(_) => None,
+            };
+            if let Some((status, res)) = saved {
+                return Reservation::Replay(status, res);
+            }
+        }
+    }
+}
+
+impl ReservedKey {
+    // Сохраняем ответ для повторов и передаем его ожидающим запросам
+    pub fn complete(mut self, status: StatusCode, res: Value) {
+        self.keys.lock().insert(
+            self.key.clone(),
+            Entry::Done(Instant::now(), status, res.clone()),
+        );
+        self.completed = true;
+        let _ = self.sender.send(Some((status, res)));
+    }
+}
+
+impl Drop for ReservedKey {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.keys.lock().remove(&self.key);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// Обработчики импорта событий из Google Calendar и iCalendar
+use std::{collections::HashSet, str::FromStr};
+
+use axum::{
+    extract::{Json, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+use crate::{
+    audit::{Actor, AuditOp},
+    changes::ChangeKind,
+    ical,
+    model::Event,
+    openapi,
+    params::{calendar_parse, optional_text},
+    responses::{store_error_message, store_error_response},
+    store::BulkInsertError,
+    AppState,
+};
+
+// Обработчик импорта событий из JSON-выгрузки Google Calendar
+// Принимает объект с массивом items (как в выгрузке) либо просто массив событий
+// Из события берутся summary (название) и start (dateTime либо date для событий на весь день)
+#[utoipa::path(
+    post,
+    path = "/import/google",
+    params(openapi::ImportParam),
+    request_body = openapi::GoogleImportReq,
+    responses(
+        (status = 200, description = "Результат импорта по каждому событию", body = openapi::GoogleImportResponse),
+        (status = 400, description = "Некорректное тело запроса", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn import_google_handler(
+    State(AppState {
+        store,
+        settings,
+        changes,
+        audit,
+        ..
+    }): State<AppState>,
+    actor: Actor,
+    Query(param): Query<Value>,
+    Json(body): Json<Value>,
+) -> Response {
+    // Календарь, в который импортируются события
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    let items = match body.get("items").unwrap_or(&body).as_array() {
+        Some(items) => items.clone(),
+        None => {
+            let res = json!({
+                "error": format!("expected an array of events or an object with 'items'"),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+    };
+    let mut imported = 0;
+    let mut results = Vec::with_capacity(items.len());
+    for (index, item) in items.into_iter().enumerate() {
+        let event = match google_event_parse(item, &calendar, settings.trim_strings) {
+            Ok(event) => event,
+            Err(e) => {
+                results.push(json!({ "index": index, "status": "error", "error": e }));
+                continue;
+            }
+        };
+        // Уже существующие события (с теми же датой и названием) пропускаем, чтобы повторный импорт не создавал копий
+        match store.find_by_name(&calendar, event.date, &event.name).await {
+            Ok(found) if !found.is_empty() => {
+                results.push(json!({ "index": index, "status": "skipped" }));
+                continue;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                results.push(
+                    json!({ "index": index, "status": "error", "error": store_error_message(&e) }),
+                );
+                continue;
+            }
+        }
+        match audit
+            .record_events(
+                &actor,
+                AuditOp::Create,
+                std::slice::from_ref(&event),
+                store.insert(event.clone()),
+            )
+            .await
+        {
+            Ok(()) => {
+                changes.publish(ChangeKind::Created, &event);
+                results.push(json!({ "index": index, "status": "imported" }));
+                imported += 1;
+            }
+            Err(e) => results.push(
+                json!({ "index": index, "status": "error", "error": store_error_message(&e) }),
+            ),
+        }
+    }
+    let res = json!({
+        "imported": imported,
+        "result": results,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик импорта событий из файла iCalendar (.ics)
+// Из каждого VEVENT берутся DTSTART, DTEND, SUMMARY, DESCRIPTION и LOCATION, уже существующие события пропускаются
+// Файл импортируется целиком: если он некорректен или не помещается в лимит событий - ничего не добавляется
+#[utoipa::path(
+    post,
+    path = "/import/ics",
+    params(openapi::ImportParam),
+    request_body(content = String, content_type = "text/calendar"),
+    responses(
+        (status = 200, description = "Количество импортированных и пропущенных событий", body = openapi::IcsImportResponse),
+        (status = 400, description = "Некорректный файл iCalendar", body = openapi::ErrorResponse),
+        (status = 429, description = "Достигнут лимит событий", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn import_ics_handler(
+    State(AppState {
+        store,
+        settings,
+        changes,
+        audit,
+        ..
+    }): State<AppState>,
+    actor: Actor,
+    Query(param): Query<Value>,
+    body: String,
+) -> Response {
+    // Календарь, в который импортируются события
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    let items = match ical::parse_calendar(&body) {
+        Ok(items) => items,
+        Err(e) => {
+            let res = json!({
+                "error": format!("invalid iCalendar: {}", e),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+    };
+    let mut events = Vec::with_capacity(items.len());
+    for item in items {
+        let name = if settings.trim_strings {
+            item.summary.trim().to_string()
+        } else {
+            item.summary
+        };
+        if name.trim().is_empty() {
+            let res = json!({
+                "error": format!("invalid iCalendar: event_name must not be empty"),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+        let mut event = Event::new(calendar.clone(), item.date, name);
+        event.end_date = item.end_date;
+        event.all_day = item.all_day;
+        event.description = optional_text(item.description, settings.trim_strings);
+        event.location = optional_text(item.location, settings.trim_strings);
+        events.push(event);
+    }
+    // Уже существующие события и повторы внутри файла пропускаем
+    let mut seen = HashSet::new();
+    let total = events.len();
+    let mut new_events = Vec::with_capacity(events.len());
+    for event in events {
+        let exists = match store.find_by_name(&calendar, event.date, &event.name).await {
+            Ok(found) => !found.is_empty(),
+            Err(e) => return store_error_response(e),
+        };
+        if !exists && seen.insert((event.date, event.name.clone())) {
+            new_events.push(event);
+        }
+    }
+    let skipped = total - new_events.len();
+    let imported = new_events.len();
+    // Хранилище добавляет события только все вместе, поэтому файл сверх лимита не импортируется совсем
+    if let Err(e) = audit
+        .record_events(
+            &actor,
+            AuditOp::Create,
+            &new_events,
+            store.insert_all(new_events.clone()),
+        )
+        .await
+    {
+        let e = match e {
+            BulkInsertError::Rejected(mut errors) => errors.remove(0).1,
+            BulkInsertError::Failed(e) => e,
+        };
+        return store_error_response(e);
+    }
+    for event in &new_events {
+        changes.publish(ChangeKind::Created, event);
+    }
+    let res = json!({
+        "imported": imported,
+        "skipped": skipped,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Функция для преобразования события Google Calendar в событие календаря
+// Событие на весь день (start.date) сохраняется на полночь UTC и помечается как событие на весь день
+// Окончание берется из end.dateTime, а у события на весь день - из end.date: это день после последнего дня события
+fn google_event_parse(item: Value, calendar: &str, trim: bool) -> Result<Event, String> {
+    let item: GoogleEvent = serde_json::from_value(item).map_err(|e| e.to_string())?;
+    let name = match item.summary {
+        Some(summary) if trim => summary.trim().to_string(),
+        Some(summary) => summary,
+        None => return Err("missing field `summary`".to_string()),
+    };
+    if name.trim().is_empty() {
+        return Err("event_name must not be empty".to_string());
+    }
+    let all_day = item.start.date_time.is_none();
+    let date = match (item.start.date_time, item.start.date) {
+        (Some(date_time), _) => DateTime::parse_from_rfc3339(&date_time)
+            .map_err(|e| e.to_string())?
+            .with_timezone(&Utc),
+        (None, Some(date)) => NaiveDate::from_str(&date)
+            .map_err(|e| e.to_string())?
+            .and_time(NaiveTime::MIN)
+            .and_utc(),
+        (None, None) => return Err("missing `start.dateTime` or `start.date`".to_string()),
+    };
+    let end_date = match item.end {
+        Some(GoogleEventTime {
+            date_time: Some(date_time),
+            ..
+        }) => Some(
+            DateTime::parse_from_rfc3339(&date_time)
+                .map_err(|e| e.to_string())?
+                .with_timezone(&Utc),
+        ),
+        Some(GoogleEventTime {
+            date: Some(date), ..
+        }) => Some(
+            NaiveDate::from_str(&date)
+                .map_err(|e| e.to_string())?
+                .and_time(NaiveTime::MIN)
+                .and_utc(),
+        ),
+        _ => None,
+    };
+    if end_date.is_some_and(|end_date| end_date < date) {
+        return Err("end must not be before start".to_string());
+    }
+    // Как и при импорте iCalendar: окончание не позже начала длительностью не считаем,
+    // однодневное событие на весь день остается без окончания, а многодневное сохраняется с окончанием
+    let end_date = end_date.filter(|end_date| *end_date > date);
+    let all_day = all_day && end_date.is_none_or(|end_date| end_date <= date + Duration::days(1));
+    let mut event = Event::new(calendar.to_string(), date, name);
+    event.end_date = if all_day { None } else { end_date };
+    event.all_day = all_day;
+    event.description = optional_text(item.description, trim);
+    event.location = optional_text(item.location, trim);
+    Ok(event)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct GoogleEvent {
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub start: GoogleEventTime,
+    pub end: Option<GoogleEventTime>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GoogleEventTime {
+    pub date_time: Option<String>,
+    pub date: Option<String>,
+}
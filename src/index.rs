@@ -0,0 +1,401 @@
+// Хранилище событий с индексом по дате
+// События лежат по порядковому номеру добавления, а BTreeMap по дню начала позволяет выбирать
+// только события нужного диапазона дат вместо прохода по всему списку
+// Номер события не меняется, пока оно лежит в хранилище, поэтому удаление затрагивает только его день
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::Hash,
+};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+use uuid::Uuid;
+
+use crate::Event;
+
+// Календари по названию, у каждого календаря свое хранилище событий
+pub(crate) type Calendars = HashMap<String, IndexedEvents>;
+
+// Раскладываем события по календарям
+pub fn group_by_calendar(events: Vec<Event>) -> Calendars {
+    let mut grouped: HashMap<String, Vec<Event>> = HashMap::new();
+    for event in events {
+        grouped
+            .entry(event.calendar.clone())
+            .or_default()
+            .push(event);
+    }
+    grouped
+        .into_iter()
+        .map(|(name, events)| (name, IndexedEvents::new(events)))
+        .collect()
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct IndexedEvents {
+    // События по порядковому номеру добавления
+    events: BTreeMap<u64, Event>,
+    // Номер, который получит следующее добавленное событие
+    next: u64,
+    // Номера событий по идентификатору
+    ids: HashMap<Uuid, u64>,
+    // Номера разовых событий по дню начала (UTC)
+    by_day: BTreeMap<NaiveDate, Vec<u64>>,
+    // Номера повторяющихся событий: их повторения могут попасть в любой диапазон, поэтому проверяются всегда
+    recurring: BTreeSet<u64>,
+    // Пары (дата, название) для быстрой проверки существования события
+    // Храним количество, а не просто множество: обновление может сделать два события одинаковыми
+    keys: HashMap<(DateTime<Utc>, String), usize>,
+    // Наибольшая длительность события с временем окончания в днях
+    // Событие, начавшееся раньше диапазона, может в него попасть, поэтому диапазон расширяется назад на столько дней
+    // При удалении не уменьшается: слишком широкий диапазон только добавляет кандидатов, точную проверку делает вызывающий код
+    max_span_days: i64,
+    // Мягко удаленные события: не попадают в индекс и выборки, но сохраняются и могут быть восстановлены
+    deleted: Vec<Event>,
+}
+
+impl IndexedEvents {
+    pub fn new(events: Vec<Event>) -> Self {
+        let (deleted, events): (Vec<Event>, Vec<Event>) = events
+            .into_iter()
+            .partition(|event| event.deleted_at.is_some());
+        let mut indexed = IndexedEvents {
+            deleted,
+            ..IndexedEvents::default()
+        };
+        for event in events {
+            indexed.push(event);
+        }
+        indexed
+    }
+
+    // Добавляем событие в конец списка
+    pub fn push(&mut self, event: Event) {
+        let seq = self.next;
+        self.next += 1;
+        self.ids.insert(event.id, seq);
+        self.events.insert(seq, event);
+        self.index(seq);
+    }
+
+    // Событие по идентификатору
+    pub fn get(&self, id: Uuid) -> Option<&Event> {
+        self.ids.get(&id).map(|seq| &self.events[seq])
+    }
+
+    // Порядковый номер события по идентификатору
+    pub fn seq(&self, id: Uuid) -> Option<u64> {
+        self.ids.get(&id).copied()
+    }
+
+    // Возвращаем удаленное событие на место с прежним номером
+    pub fn put_back(&mut self, seq: u64, event: Event) {
+        // Календарь мог быть пересоздан: следующее событие все равно должно получить номер больше
+        self.next = self.next.max(seq + 1);
+        self.ids.insert(event.id, seq);
+        self.events.insert(seq, event);
+        self.index(seq);
+    }
+
+    // Удаляем событие по идентификатору
+    pub fn remove(&mut self, id: Uuid) -> Option<Event> {
+        let seq = self.ids.remove(&id)?;
+        self.unindex(seq);
+        self.events.remove(&seq)
+    }
+
+    // Мягко удаляем событие по идентификатору: помечаем временем удаления и убираем из выборок
+    pub fn soft_remove(&mut self, id: Uuid, now: DateTime<Utc>) -> Option<Event> {
+        let mut event = self.remove(id)?;
+        event.deleted_at = Some(now);
+        self.deleted.push(event.clone());
+        Some(event)
+    }
+
+    // Восстанавливаем мягко удаленное событие по идентификатору
+    pub fn restore(&mut self, id: Uuid) -> Option<Event> {
+        let i = self.deleted.iter().position(|event| event.id == id)?;
+        let mut event = self.deleted.remove(i);
+        event.deleted_at = None;
+        self.push(event.clone());
+        Some(event)
+    }
+
+    // Мягко удаленные события
+    pub fn deleted(&self) -> &[Event] {
+        &self.deleted
+    }
+
+    // Мягко удаленные события для изменения: они не индексируются, поэтому список можно менять напрямую
+    pub fn deleted_mut(&mut self) -> &mut Vec<Event> {
+        &mut self.deleted
+    }
+
+    // Окончательно удаляем события, мягко удаленные раньше указанного момента, возвращаем их количество
+    pub fn purge(&mut self, before: DateTime<Utc>) -> usize {
+        let total = self.deleted.len();
+        self.deleted.retain(|event| {
+            event
+                .deleted_at
+                .is_some_and(|deleted_at| deleted_at >= before)
+        });
+        total - self.deleted.len()
+    }
+
+    // В хранилище нет ни обычных, ни мягко удаленных событий
+    pub fn is_unused(&self) -> bool {
+        self.events.is_empty() && self.deleted.is_empty()
+    }
+
+    // События в порядке добавления
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        self.events.values()
+    }
+
+    // Количество событий без мягко удаленных
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // Изменяем событие по идентификатору, дата события может измениться, идентификатор - нет
+    pub fn update<R>(&mut self, id: Uuid, f: impl FnOnce(&mut Event) -> R) -> Option<R> {
+        let seq = *self.ids.get(&id)?;
+        self.unindex(seq);
+        let result = f(self.events.get_mut(&seq)?);
+        self.index(seq);
+        Some(result)
+    }
+
+    // Есть ли событие с указанными датой и названием
+    pub fn contains(&self, date: DateTime<Utc>, name: &str) -> bool {
+        self.keys.contains_key(&(date, name.to_string()))
+    }
+
+    // События с указанными датой и названием в порядке добавления
+    // Ищем только среди событий того же дня и повторяющихся, а если такой пары нет - не ищем вовсе
+    pub fn find_by_name(&self, date: DateTime<Utc>, name: &str) -> Vec<&Event> {
+        if !self.contains(date, name) {
+            return Vec::new();
+        }
+        let mut seqs: Vec<u64> = self
+            .by_day
+            .get(&date.date_naive())
+            .into_iter()
+            .flatten()
+            .chain(self.recurring.iter())
+            .copied()
+            .filter(|seq| self.events[seq].date == date && self.events[seq].name == name)
+            .collect();
+        seqs.sort_unstable();
+        seqs.into_iter().map(|seq| &self.events[&seq]).collect()
+    }
+
+    // События, которые могут попасть в диапазон дней [from, to], в порядке добавления
+    // Диапазон расширяется на день в обе стороны: из-за часового пояса запроса и правила полуночи
+    // день события может отличаться от дня по UTC, точную проверку делает вызывающий код
+    pub fn in_window(&self, from: NaiveDate, to: NaiveDate) -> impl Iterator<Item = &Event> {
+        let from = from
+            .checked_sub_signed(Duration::days(1 + self.max_span_days))
+            .unwrap_or(NaiveDate::MIN);
+        let to = to.checked_add_signed(Duration::days(1)).unwrap_or(to);
+        let mut seqs: Vec<u64> = self
+            .by_day
+            .range(from..=to)
+            .flat_map(|(_, seqs)| seqs.iter().copied())
+            .chain(self.recurring.iter().copied())
+            .collect();
+        seqs.sort_unstable();
+        seqs.into_iter().map(|seq| &self.events[&seq])
+    }
+
+    // Количество записей в каждом индексе
+    pub fn index_sizes(&self) -> [(&'static str, usize); 4] {
+        [
+            ("ids", self.ids.len()),
+            ("by_day", self.by_day.values().map(Vec::len).sum()),
+            ("recurring", self.recurring.len()),
+            ("keys", self.keys.len()),
+        ]
+    }
+
+    // Перестраиваем индексы по самим событиям, порядковые номера событий не меняются
+    // Возвращаем количество записей индексов, которые разошлись с событиями и были исправлены
+    pub fn reindex(&mut self) -> usize {
+        let mut rebuilt = IndexedEvents {
+            events: std::mem::take(&mut self.events),
+            deleted: std::mem::take(&mut self.deleted),
+            ..IndexedEvents::default()
+        };
+        let seqs: Vec<u64> = rebuilt.events.keys().copied().collect();
+        for &seq in &seqs {
+            rebuilt.ids.insert(rebuilt.events[&seq].id, seq);
+            rebuilt.index(seq);
+        }
+        rebuilt.next = self.next.max(seqs.last().map_or(0, |seq| seq + 1));
+        let fixed = differences(&self.ids, &rebuilt.ids)
+            + differences(&day_entries(&self.by_day), &day_entries(&rebuilt.by_day))
+            + differences(&self.recurring, &rebuilt.recurring)
+            + differences(&self.keys, &rebuilt.keys);
+        *self = rebuilt;
+        fixed
+    }
+
+    fn index(&mut self, seq: u64) {
+        let event = &self.events[&seq];
+        if let Some(end) = event.end_date {
+            let span = (end.date_naive() - event.date.date_naive()).num_days();
+            self.max_span_days = self.max_span_days.max(span);
+        }
+        *self
+            .keys
+            .entry((event.date, event.name.clone()))
+            .or_default() += 1;
+        if event.recurrence.is_some() {
+            self.recurring.insert(seq);
+        } else {
+            self.by_day
+                .entry(event.date.date_naive())
+                .or_default()
+                .push(seq);
+        }
+    }
+
+    fn unindex(&mut self, seq: u64) {
+        let event = &self.events[&seq];
+        let key = (event.date, event.name.clone());
+        if let Some(count) = self.keys.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                self.keys.remove(&key);
+            }
+        }
+        if event.recurrence.is_some() {
+            self.recurring.remove(&seq);
+            return;
+        }
+        let day = event.date.date_naive();
+        if let Some(seqs) = self.by_day.get_mut(&day) {
+            seqs.retain(|&other| other != seq);
+            if seqs.is_empty() {
+                self.by_day.remove(&day);
+            }
+        }
+    }
+}
+
+// Пары (день, номер) индекса по дню, чтобы сравнивать индексы без учета порядка номеров внутри дня
+fn day_entries(by_day: &BTreeMap<NaiveDate, Vec<u64>>) -> HashSet<(NaiveDate, u64)> {
+    by_day
+        .iter()
+        .flat_map(|(&day, seqs)| seqs.iter().map(move |&seq| (day, seq)))
+        .collect()
+}
+
+// Количество записей, которые есть только в одном из двух индексов или отличаются в них
+fn differences<'a, C, T>(old: &'a C, new: &'a C) -> usize
+where
+    &'a C: IntoIterator<Item = T>,
+    T: Eq + Hash,
+{
+    let old: HashSet<T> = old.into_iter().collect();
+    let new: HashSet<T> = new.into_iter().collect();
+    old.symmetric_difference(&new).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 000 разовых событий по одному в час, начиная с 1 января 2024 года
+    fn hourly_events() -> IndexedEvents {
+        let start: DateTime<Utc> = "2024-01-01T00:00:00Z".parse().unwrap();
+        let events = (0..10_000)
+            .map(|hour| {
+                Event::new(
+                    "work".to_string(),
+                    start + Duration::hours(hour),
+                    format!("event {hour}"),
+                )
+            })
+            .collect();
+        IndexedEvents::new(events)
+    }
+
+    #[test]
+    fn day_query_reads_only_its_own_days() {
+        let events = hourly_events();
+        let day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let found: Vec<&Event> = events.in_window(day, day).collect();
+        // Диапазон расширяется на день в обе стороны, остальные дни не просматриваются
+        assert_eq!(found.len(), 72);
+        assert!(found.iter().all(|event| {
+            let date = event.date.date_naive();
+            date >= day.pred_opt().unwrap() && date <= day.succ_opt().unwrap()
+        }));
+        assert!(found.windows(2).all(|pair| pair[0].date < pair[1].date));
+    }
+
+    #[test]
+    fn remove_keeps_other_events_indexed() {
+        let mut events = hourly_events();
+        let day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let removed = events.in_window(day, day).nth(30).unwrap().clone();
+        let kept = events.iter().nth(5_000).unwrap().clone();
+
+        assert_eq!(events.remove(removed.id).unwrap().id, removed.id);
+        assert!(events.get(removed.id).is_none());
+        assert!(events.remove(removed.id).is_none());
+        assert!(!events.contains(removed.date, &removed.name));
+        assert_eq!(events.len(), 9_999);
+        assert_eq!(events.in_window(day, day).count(), 71);
+
+        assert_eq!(events.get(kept.id).unwrap().name, kept.name);
+        assert_eq!(events.find_by_name(kept.date, &kept.name).len(), 1);
+    }
+
+    #[test]
+    fn update_moves_event_to_new_day() {
+        let mut events = hourly_events();
+        let id = events.iter().next().unwrap().id;
+        let date: DateTime<Utc> = "2025-03-01T12:00:00Z".parse().unwrap();
+        events.update(id, |event| event.date = date).unwrap();
+
+        let old_day = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let new_day = date.date_naive();
+        assert!(events
+            .in_window(old_day, old_day)
+            .all(|event| event.id != id));
+        assert!(events
+            .in_window(new_day, new_day)
+            .any(|event| event.id == id));
+        // Порядок добавления сохраняется
+        assert_eq!(events.iter().next().unwrap().id, id);
+    }
+
+    #[test]
+    fn reindex_repairs_damaged_indexes() {
+        let mut events = hourly_events();
+        let lost = events.iter().nth(100).unwrap().clone();
+        let moved = events.iter().nth(200).unwrap().clone();
+        let day = moved.date.date_naive();
+        // Портим индексы: одно событие теряется по идентификатору, другое пропадает из своего дня
+        events.ids.remove(&lost.id);
+        events
+            .by_day
+            .get_mut(&day)
+            .unwrap()
+            .retain(|&seq| seq != 200);
+        assert!(events.get(lost.id).is_none());
+
+        assert_eq!(events.reindex(), 2);
+        assert_eq!(events.get(lost.id).unwrap().name, lost.name);
+        assert!(events.in_window(day, day).any(|event| event.id == moved.id));
+        assert_eq!(events.seq(moved.id), Some(200));
+        // Целые индексы перестраиваются без исправлений
+        assert_eq!(events.reindex(), 0);
+    }
+}
@@ -0,0 +1,217 @@
+// Middleware: логирование, ключ API, ограничение частоты и времени обработки запросов
+use std::{any::Any, time::Instant};
+
+use axum::{
+    extract::{Json, State},
+    http::{header, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{model::INCLUDE_NULLS, AppState, API_KEY_HEADER};
+
+// Функция для логирования через middleware
+// Время обработки считается от получения запроса до готового ответа, включая работу обработчика
+// latency_ms - дробное число, чтобы быстрые запросы не округлялись до нуля
+pub async fn log_request<B>(req: Request<B>, next: Next<B>) -> Response {
+    let start = Instant::now();
+    let request_id = Uuid::new_v4();
+    let method = req.method().clone();
+    let path = req.uri().to_string();
+
+    let res = next.run(req).await;
+
+    tracing::info!(
+        %request_id,
+        status = res.status().as_u16(),
+        %method,
+        %path,
+        latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+        "log_request"
+    );
+    res
+}
+
+// Функция для формирования ответа на панику в обработчике, текст паники пишется в лог
+pub fn panic_response(panic: Box<dyn Any + Send + 'static>) -> Response {
+    let message = panic
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| panic.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    tracing::error!(panic = message, "handler panicked");
+    let res = json!({
+        "error": format!("internal server error"),
+    });
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(res)).into_response()
+}
+
+// Функция для пометки ответа устаревшего маршрута заголовком Deprecation
+pub async fn mark_deprecated(mut res: Response) -> Response {
+    res.headers_mut()
+        .insert("deprecation", HeaderValue::from_static("true"));
+    res
+}
+
+// Middleware, предупреждающий клиента, что изменения не записаны на диск
+// В режиме persistence_failure_mode = keep изменение, которое не удалось записать, остается в памяти и выполняется успешно,
+// поэтому к успешному ответу на изменяющий запрос добавляется заголовок Warning
+pub async fn warn_unsaved_changes<B>(
+    State(AppState { store, .. }): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let modifying = !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let mut res = next.run(req).await;
+    if modifying && res.status().is_success() && store.has_unsaved_changes() {
+        res.headers_mut().insert(
+            header::WARNING,
+            HeaderValue::from_static("199 - \"changes are not saved to disk\""),
+        );
+    }
+    res
+}
+
+// Middleware, проверяющий ключ API в заголовке X-API-Key
+// Если ключ в настройках не задан - пропускаем все запросы
+pub async fn require_api_key<B>(
+    State(AppState { settings, .. }): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(api_key) = &settings.api_key {
+        let provided = req
+            .headers()
+            .get(API_KEY_HEADER)
+            .and_then(|value| value.to_str().ok());
+        if provided != Some(api_key.as_str()) {
+            let res = json!({
+                "error": format!("unauthorized"),
+            });
+            return (StatusCode::UNAUTHORIZED, Json(res)).into_response();
+        }
+    }
+    next.run(req).await
+}
+
+// Middleware для служебных маршрутов: без ключа API в настройках они отключены
+// Сам ключ проверяет require_api_key, который выполняется раньше
+pub async fn require_admin<B>(
+    State(AppState { settings, .. }): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if settings.api_key.is_none() {
+        let res = json!({
+            "error": format!("admin routes require api_key to be configured"),
+        });
+        return (StatusCode::FORBIDDEN, Json(res)).into_response();
+    }
+    next.run(req).await
+}
+
+// Middleware, задающий на время запроса, выводятся ли незаданные поля событий как null
+// События сериализуются в обработчике, поэтому настройка действует на все ответы с событиями
+pub async fn null_fields<B>(
+    State(AppState { settings, .. }): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    INCLUDE_NULLS
+        .scope(settings.include_null_fields, next.run(req))
+        .await
+}
+
+// Middleware, восстанавливающий блокировки после паники обработчика
+// Паника во время удержания блокировки отравляет ее, и без восстановления все следующие запросы завершались бы ошибкой
+pub async fn recover_poisoned_locks<B>(
+    State(AppState {
+        pending_deletes, ..
+    }): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if pending_deletes.is_poisoned() {
+        tracing::warn!("recovered poisoned pending deletes lock");
+        pending_deletes.clear_poison();
+    }
+    next.run(req).await
+}
+
+// Middleware, ограничивающий время обработки запроса
+// Если обработчик не уложился в отведенное время, его работа прерывается и клиент получает 408
+pub async fn request_timeout<B>(
+    State(AppState { settings, .. }): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let timeout = std::time::Duration::from_millis(settings.request_timeout_ms);
+    match tokio::time::timeout(timeout, next.run(req)).await {
+        Ok(res) => res,
+        Err(_) => {
+            let res = json!({
+                "error": format!("request timed out"),
+            });
+            (StatusCode::REQUEST_TIMEOUT, Json(res)).into_response()
+        }
+    }
+}
+
+// Middleware, ограничивающий общую частоту запросов от всех клиентов
+pub async fn global_rate_limit<B>(
+    State(AppState { rate_limiter, .. }): State<AppState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(rate_limiter) = rate_limiter {
+        // Паника другого запроса во время проверки не должна останавливать все следующие запросы
+        let allowed = rate_limiter
+            .lock()
+            .unwrap_or_else(|e| {
+                tracing::warn!("recovered poisoned rate limiter lock");
+                rate_limiter.clear_poison();
+                e.into_inner()
+            })
+            .try_acquire();
+        if !allowed {
+            let res = json!({
+                "error": format!("too many requests"),
+            });
+            return (StatusCode::TOO_MANY_REQUESTS, Json(res)).into_response();
+        }
+    }
+    next.run(req).await
+}
+
+// Корзина токенов: пополняется со скоростью rate токенов в секунду, вмещает не более rate токенов
+pub struct TokenBucket {
+    pub rate: f64,
+    pub tokens: f64,
+    pub last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: u32) -> Self {
+        TokenBucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Забираем один токен, если он есть
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
@@ -1,429 +1,510 @@
 use axum::{
-    extract::{Json, Query, State},
-    http::{Method, StatusCode, Uri},
+    extract::{DefaultBodyLimit, Json, State},
+    http::{header, Extensions, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version},
     middleware,
-    response::IntoResponse,
-    response::Response,
+    response::{IntoResponse, Response},
     routing::{get, post},
     Router,
 };
 use std::{
+    collections::HashMap,
     error::Error,
+    future::Future,
     net::{IpAddr, SocketAddr},
-    str::FromStr,
-    sync::{Arc, Mutex},
-    time::{SystemTime, UNIX_EPOCH},
+    sync::{atomic::AtomicU64, Arc, Mutex},
 };
 
-use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
-use config::{Config, File};
-use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use chrono::{Duration, Utc};
+use serde_json::json;
+use tokio::net::TcpSocket;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    compression::{
+        predicate::{DefaultPredicate, NotForContentType, Predicate},
+        CompressionLayer,
+    },
+    cors::{AllowOrigin, CorsLayer},
+};
+use tracing_subscriber::filter::LevelFilter;
+
+mod admin;
+mod audit;
+mod changes;
+mod dates;
+mod events;
+mod exports;
+mod ical;
+mod idempotency;
+mod import;
+mod index;
+mod layers;
+mod metrics;
+mod model;
+mod openapi;
+mod params;
+mod queries;
+mod responses;
+mod settings;
+mod sqlite;
+mod storage;
+mod store;
+mod validation;
+mod xml;
+
+#[cfg(test)]
+mod tests;
+
+use audit::AuditLog;
+use changes::ChangeFeed;
+use events::{
+    bulk_create_handler, create_event_handler, delete_event_by_id_handler, delete_event_handler,
+    get_event_handler, replace_event_handler, restore_event_handler, undo_delete_handler,
+    update_event_handler,
+};
+use exports::{events_csv_handler, events_ics_handler, events_rss_handler};
+use idempotency::IdempotencyKeys;
+use import::{import_google_handler, import_ics_handler, GoogleEvent};
+use layers::{
+    global_rate_limit, log_request, mark_deprecated, null_fields, panic_response,
+    recover_poisoned_locks, request_timeout, require_admin, require_api_key, warn_unsaved_changes,
+    TokenBucket,
+};
+use metrics::Metrics;
+use model::{CreateMode, Event, Recurrence, SortOrder};
+use queries::{
+    audit_handler, busy_time_handler, calendars_handler, events_by_duration_handler,
+    events_by_location_handler, events_for_day_handler, events_for_days_handler,
+    events_for_month_handler, events_for_week_handler, events_for_year_handler,
+    events_in_range_handler, free_slots_handler, list_events_handler, preview_recurrence_handler,
+    search_events_handler, stats_handler, tags_handler, upcoming_events_handler, week_grid_handler,
+};
+use responses::store_error_message;
+use settings::{load_settings, Settings, StorageBackend};
+use sqlite::SqliteStore;
+use store::{EventStore, InMemoryStore};
+
+const API_KEY_HEADER: &str = "x-api-key";
+// Заголовок с ключом идемпотентности запроса создания события
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+// Заголовок с предпочтением клиента к содержимому ответа (RFC 7240), например return=minimal
+const PREFER_HEADER: &str = "prefer";
+// Календарь, в который попадают события, сохраненные до появления календарей
+const DEFAULT_CALENDAR: &str = "default";
+// Размер страницы по умолчанию и максимальный размер страницы для /events
+const DEFAULT_PAGE_LIMIT: usize = 50;
+const MAX_PAGE_LIMIT: usize = 500;
+// Количество ближайших событий по умолчанию для /events/upcoming
+const DEFAULT_UPCOMING_LIMIT: usize = 10;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // Извлекаем настройки, при ошибке в конфигурации сразу завершаем работу с понятным сообщением
+    let settings = match load_settings() {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("invalid config: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // Среду выполнения создаем сами, чтобы количество рабочих потоков задавалось в настройках
+    runtime_builder(&settings).build()?.block_on(run(settings))
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    // Создаем новый конфиг
-    let config = Config::builder()
-        .set_default("address", "127.0.0.1")? // Устанавливаем значение по умолчанию
-        .set_default("port", 8080)? // Устанавливаем значение по умолчанию
-        .add_source(File::with_name("config")) // Указываем путь к файлу конфигурации
-        .build()?; // Создаем конфигурацию
+// Функция для настройки многопоточной среды выполнения tokio
+// Если worker_threads не задан, потоков столько же, сколько ядер процессора
+fn runtime_builder(settings: &Settings) -> tokio::runtime::Builder {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(worker_threads) = settings.worker_threads {
+        builder.worker_threads(worker_threads);
+    }
+    builder
+}
 
-    // Извлекаем настройки
-    let settings: Settings = config.try_deserialize()?;
+// Функция для открытия сокета на адресе сервера с заданной длиной очереди входящих соединений
+fn bind_listener(addr: SocketAddr, backlog: u32) -> std::io::Result<std::net::TcpListener> {
+    let socket = if addr.is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    // Как и при обычном bind, после перезапуска адрес можно занять сразу, не дожидаясь закрытия старых соединений
+    #[cfg(unix)]
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(backlog)?.into_std()
+}
+
+// Запуск сервера с загруженными настройками
+async fn run(settings: Settings) -> Result<(), Box<dyn Error>> {
+    // Настраиваем вывод логов в формате JSON
+    tracing_subscriber::fmt()
+        .json()
+        .with_max_level(settings.log_level.parse::<LevelFilter>()?)
+        .init();
 
     // Используем настройки
     let ip: IpAddr = settings.address.parse()?;
     let addr: SocketAddr = SocketAddr::new(ip, settings.port);
-    // Здесь храним даты и события
-    let dates: Arc<Mutex<Vec<Event>>> = Arc::new(Mutex::new(Vec::new()));
+    let tcp_backlog = settings.tcp_backlog;
+    // Здесь храним события по календарям, загружая сохраненные ранее
+    let store = open_store(&settings)?;
+    let state = app_state(settings, store);
+    // Изменения записываются на диск фоновой задачей не чаще раза в flush_interval_ms
+    if state.settings.flush_interval_ms > 0 {
+        tokio::spawn(flush_periodically(state.clone()));
+    }
+    // В режиме мягкого удаления запускаем окончательную очистку удаленных событий
+    if state.settings.soft_delete {
+        tokio::spawn(purge_deleted(state.clone()));
+    }
+    // Сохраняем ссылку на хранилище, чтобы записать события на диск при остановке
+    let store = state.store.clone();
     // Создаем роутеры
-    let app = Router::new()
-        .route("/create_event", post(create_event_handler))
-        .route("/update_event", post(update_event_handler))
-        .route("/delete_event", post(delete_event_handler))
-        .route("/events_for_day", get(events_for_day_handler))
-        .route("/events_for_week", get(events_for_week_handler))
-        .route("/events_for_month", get(events_for_month_handler))
-        .with_state(dates)
-        .layer(middleware::map_response(log_request));
-    println!("LISTENING on {addr}\n");
-    // Запускаем сервер
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await?;
+    let app = app(state)?;
+    tracing::info!(%addr, "listening");
+    // Запускаем сервер до получения Ctrl-C или SIGTERM
+    serve(bind_listener(addr, tcp_backlog)?, app, shutdown_signal()).await?;
+    // Записываем на диск изменения, которые фоновая задача еще не успела записать
+    if let Err(e) = store.flush().await {
+        tracing::warn!(error = ?e, "failed to save events on shutdown");
+    }
     Ok(())
 }
 
-// Функция для логирования через middleware
-pub async fn log_request(req_method: Method, uri: Uri, res: Response) -> Response {
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_millis();
-
-    let log_line = RequestLogLine {
-        status: res.status().to_string(),
-        timestamp: timestamp.to_string(),
-        req_path: uri.to_string(),
-        req_method: req_method.to_string(),
+// Функция для открытия хранилища, выбранного в настройках
+fn open_store(settings: &Settings) -> Result<Arc<dyn EventStore>, Box<dyn Error>> {
+    let store: Arc<dyn EventStore> = match settings.storage_backend {
+        StorageBackend::Memory => Arc::new(InMemoryStore::open(
+            settings.storage_path.clone(),
+            settings.max_events,
+            settings.soft_delete,
+            settings.flush_interval_ms > 0,
+            settings.persistence_failure_mode,
+        )?),
+        StorageBackend::Sqlite => Arc::new(SqliteStore::open(
+            &settings.sqlite_path,
+            settings.max_events,
+            settings.soft_delete,
+        )?),
     };
+    Ok(store)
+}
 
-    println!("   ->> log_request: \n{}", json!(log_line));
-    res
+// Функция для создания общего состояния сервера
+fn app_state(settings: Settings, store: Arc<dyn EventStore>) -> AppState {
+    // Общий ограничитель частоты запросов создаем только если лимит задан
+    let rate_limiter = settings
+        .global_rate_limit_per_sec
+        .map(|rate| Arc::new(Mutex::new(TokenBucket::new(rate))));
+    // Журнал изменений, загруженный из файла, если он задан
+    let audit = Arc::new(AuditLog::open(settings.audit_log_path.clone()));
+    let changes = ChangeFeed::new(settings.change_feed_capacity);
+    AppState {
+        store,
+        settings: Arc::new(settings),
+        pending_deletes: Arc::new(Mutex::new(HashMap::new())),
+        next_pending_id: Arc::new(AtomicU64::new(1)),
+        idempotency_keys: Arc::new(IdempotencyKeys::default()),
+        metrics: Arc::new(Metrics::default()),
+        changes,
+        audit,
+        rate_limiter,
+    }
 }
 
-#[derive(Serialize)]
-struct RequestLogLine {
-    status: String,
-    timestamp: String,
-    req_path: String,
-    req_method: String,
+// Функция для создания роутера со всеми маршрутами и middleware
+fn app(state: AppState) -> Result<Router, Box<dyn Error>> {
+    // Разрешенные источники для запросов из браузера, пустой список запрещает все чужие источники
+    let cors_origins = state
+        .settings
+        .cors_allowed_origins
+        .iter()
+        .map(|origin| HeaderValue::from_str(origin))
+        .collect::<Result<Vec<_>, _>>()?;
+    let cors = CorsLayer::new()
+        .allow_origin(AllowOrigin::list(cors_origins))
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::ACCEPT,
+            header::IF_NONE_MATCH,
+            header::IF_MATCH,
+            HeaderName::from_static(API_KEY_HEADER),
+            HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+        ])
+        // ETag нужен скрипту в браузере, чтобы передать его в If-None-Match и If-Match
+        .expose_headers([header::ETAG]);
+    let metrics = state.metrics.clone();
+    let compression = state.settings.compression;
+    let app = Router::new()
+        .route("/create_event", post(create_event_handler))
+        // Устаревшие маршруты: вместо них следует использовать PUT и DELETE /event/:id
+        .route(
+            "/update_event",
+            post(update_event_handler).layer(middleware::map_response(mark_deprecated)),
+        )
+        .route(
+            "/delete_event",
+            post(delete_event_handler).layer(middleware::map_response(mark_deprecated)),
+        )
+        .route("/undo_delete", post(undo_delete_handler))
+        .route("/import/google", post(import_google_handler))
+        .route("/import/ics", post(import_ics_handler))
+        .route(
+            "/event/:id",
+            get(get_event_handler)
+                .put(replace_event_handler)
+                .delete(delete_event_by_id_handler),
+        )
+        .route("/event/:id/restore", post(restore_event_handler))
+        .route("/events", get(list_events_handler))
+        .route("/events/bulk", post(bulk_create_handler))
+        .route("/events/stream", get(changes::sse_handler))
+        .route("/events/upcoming", get(upcoming_events_handler))
+        .route("/events/by_duration", get(events_by_duration_handler))
+        .route("/events/by_location", get(events_by_location_handler))
+        .route("/events_for_day", get(events_for_day_handler))
+        .route("/events_for_days", get(events_for_days_handler))
+        .route("/events_for_week", get(events_for_week_handler))
+        .route("/events_for_month", get(events_for_month_handler))
+        .route("/events_for_year", get(events_for_year_handler))
+        .route("/events_in_range", get(events_in_range_handler))
+        .route("/week_grid", get(week_grid_handler))
+        .route("/free_slots", get(free_slots_handler))
+        .route("/busy_time", get(busy_time_handler))
+        .route("/preview_recurrence", post(preview_recurrence_handler))
+        .route("/search", get(search_events_handler))
+        .route("/events.rss", get(events_rss_handler))
+        .route("/events.ics", get(events_ics_handler))
+        .route("/events.csv", get(events_csv_handler))
+        .route("/calendars", get(calendars_handler))
+        .route("/tags", get(tags_handler))
+        .route("/stats", get(stats_handler))
+        .route("/audit", get(audit_handler))
+        .route("/ws", get(changes::ws_handler))
+        .merge(admin_routes(&state))
+        // Тело запроса больше max_body_bytes не читается в память, клиент получает 413
+        .layer(DefaultBodyLimit::max(state.settings.max_body_bytes))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            global_rate_limit,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_api_key,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_timeout,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            warn_unsaved_changes,
+        ))
+        // Проверки состояния для балансировщика и метрики не попадают под ограничение частоты запросов и не требуют ключа
+        .route("/health", get(health_handler))
+        .route("/ready", get(ready_handler))
+        .route("/metrics", get(metrics_handler))
+        // Описание API и страница документации доступны без ключа
+        .route("/openapi.json", get(openapi::openapi_handler))
+        .route("/docs", get(openapi::docs_handler))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            recover_poisoned_locks,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), null_fields))
+        .with_state(state)
+        // Паника в обработчике превращается в ответ 500 вместо разрыва соединения
+        .layer(CatchPanicLayer::custom(panic_response))
+        // Ответы сжимаются gzip или brotli, если клиент передал Accept-Encoding и сжатие включено
+        // Поток SSE не сжимается: сжатые сообщения могут задерживаться в буферах до прихода следующих
+        // Выгрузки, которые можно запрашивать по частям, не сжимаются: диапазоны байтов относятся к несжатому телу
+        .layer(
+            CompressionLayer::new()
+                .gzip(compression)
+                .br(compression)
+                .compress_when(
+                    DefaultPredicate::new()
+                        .and(NotForContentType::const_new("text/event-stream"))
+                        .and(
+                            |_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+                                !headers.contains_key(header::ACCEPT_RANGES)
+                            },
+                        ),
+                ),
+        )
+        // Предварительные OPTIONS-запросы браузера обрабатываются здесь и не доходят до обработчиков
+        .layer(cors)
+        // Запросы учитываются в метриках с кодом ответа, который получил клиент
+        .layer(middleware::from_fn_with_state(
+            metrics,
+            metrics::record_metrics,
+        ))
+        .layer(middleware::from_fn(log_request));
+    Ok(app)
 }
 
-// Обработчик создания события
-async fn create_event_handler(
-    State(dates): State<Arc<Mutex<Vec<Event>>>>,
-    Json(body): Json<Value>,
-) -> Response {
-    // Проверяем на валидность входные данные
-    let event = match json_body_parse(body).await {
-        Ok(value) => value,
-        Err(e) => {
-            return e;
-        }
-    };
-    // Проверяем что указанное событие не было добавлено ранее
-    if let Some(_) = check_event(&dates, &event).await {
-        let res = json!({
-            "error": format!("Data already exist")
-        });
-        return (axum::http::StatusCode::SERVICE_UNAVAILABLE, Json(res)).into_response();
-    } else {
-        let dates = dates.lock();
-        match dates {
-            Ok(mut dates) => {
-                let res = json!({
-                    "result": format!("Added event: '{}' for date {}", event.name, event.date),
-                });
-                // Сохраняем полученные данные
-                dates.push(event);
-                return (StatusCode::CREATED, Json(res)).into_response();
-            }
-            Err(_) => {
-                return (axum::http::StatusCode::INTERNAL_SERVER_ERROR).into_response();
-            }
-        }
+// Функция для создания служебных маршрутов, без ключа API в настройках они отвечают 403
+// Отладочные маршруты не регистрируются вовсе, пока не включен debug_endpoints
+fn admin_routes(state: &AppState) -> Router<AppState> {
+    let mut routes = Router::new()
+        .route("/admin/reindex", post(admin::reindex_handler))
+        .route("/admin/replay", post(admin::replay_handler));
+    if state.settings.debug_endpoints {
+        routes = routes.route("/debug/dump", get(admin::debug_dump_handler));
     }
+    routes.route_layer(middleware::from_fn_with_state(state.clone(), require_admin))
 }
 
-// Функция для обновления данных о событии
-async fn update_event_handler(
-    State(dates): State<Arc<Mutex<Vec<Event>>>>,
-    Json(body): Json<Value>,
-) -> Response {
-    // Десериализация данных
-    let body: Result<EventUpdateReq, _> = serde_json::from_value(body);
-
-    match body {
-        Ok(body) => {
-            // Успешная десериализация
-            let date = match DateTime::parse_from_rfc3339(&body.date_time) {
-                Ok(value) => value,
-                Err(e) => {
-                    let res = json!({
-                        "error": format!("{}",e),
-                    });
-                    return (StatusCode::BAD_REQUEST, Json(res)).into_response();
-                }
-            };
-            let event = Event {
-                date: date.with_timezone(&Utc),
-                name: body.event_name.clone(),
-            };
-            // Проверяем что указанное событие пристутствует в памяти
-            if let Some(i) = check_event(&dates, &event).await {
-                let date = match DateTime::parse_from_rfc3339(&body.new_date_time) {
-                    Ok(value) => value,
-                    Err(e) => {
-                        let res = json!({
-                            "error": format!("{}",e),
-                        });
-                        return (StatusCode::BAD_REQUEST, Json(res)).into_response();
-                    }
-                };
-                let dates = dates.lock();
-                match dates {
-                    // Изменяем данные
-                    Ok(mut dates) => {
-                        dates[i].date = date.with_timezone(&Utc);
-                        dates[i].name = body.new_event_name.clone();
-                        let res = json!({
-                            "result": format!("Update event: '{}' for date {}, on event: '{}' for date {}", body.event_name,body.date_time,body.new_event_name,body.new_date_time),
-                        });
-                        return (StatusCode::OK, Json(res)).into_response();
-                    }
-                    Err(_) => {
-                        return (axum::http::StatusCode::INTERNAL_SERVER_ERROR).into_response();
-                    }
-                }
-            } else {
-                let res = json!({
-                    "error": format!("The data does not exist"),
-                });
-                return (axum::http::StatusCode::SERVICE_UNAVAILABLE, Json(res)).into_response();
-            }
-        }
-        Err(e) => {
-            let res = json!({
-                "error": format!("{}",e),
-            });
-            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
-            // Ошибка десериализации
-        }
-    }
+// Функция для обработки запросов на открытом сокете до завершения shutdown
+// После этого сервер перестает принимать новые соединения и дожидается завершения текущих запросов
+async fn serve(
+    listener: std::net::TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), Box<dyn Error>> {
+    axum::Server::from_tcp(listener)?
+        // Адрес клиента нужен журналу изменений
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
 }
 
-// Обработчик для удаления событий
-async fn delete_event_handler(
-    State(dates): State<Arc<Mutex<Vec<Event>>>>,
-    Json(body): Json<Value>,
-) -> Response {
-    // Проверяем на валидность входные данные
-    let event = match json_body_parse(body).await {
-        Ok(value) => value,
-        Err(e) => {
-            return e;
+// Функция, завершающаяся при получении Ctrl-C или SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!(error = %e, "failed to listen for Ctrl-C");
+            std::future::pending::<()>().await;
         }
     };
-    // Проверяем что указанное событие не было добавлено ранее
-    if let Some(i) = check_event(&dates, &event).await {
-        let dates = dates.lock();
-        match dates {
-            Ok(mut dates) => {
-                let res = json!({
-                    "result": format!("Removed event: '{}' for date {}",event.name,event.date),
-                });
-                // Удаляем найденное событие
-                dates.remove(i);
-                return (StatusCode::OK, Json(res)).into_response();
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
             }
-            Err(_) => {
-                return (axum::http::StatusCode::INTERNAL_SERVER_ERROR).into_response();
+            Err(e) => {
+                tracing::warn!(error = %e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
             }
         }
-    }
-    // Если указанное событие не было найдено - возвращаем  HTTP 503s
-    else {
-        let res = json!({
-            "error": format!("The data does not exist"),
-        });
-        return (axum::http::StatusCode::SERVICE_UNAVAILABLE, Json(res)).into_response();
-    }
-}
-
-// Обработчик, возващающий все события дня для указанной даты
-async fn events_for_day_handler(
-    State(dates): State<Arc<Mutex<Vec<Event>>>>,
-    Query(param): Query<Value>,
-) -> Response {
-    // Проверяем на валидность входные данные
-    let desired_date = match query_parse(param).await {
-        Ok(value) => value,
-        Err(e) => {
-            return e;
-        }
     };
-    let dates = dates.lock();
-    match dates {
-        Ok(dates) => {
-            // Проходим по всем имеющимся событиям и оставляем те, день, месяц и год которых соответствуют указанному событию
-            let filtered_dates: Vec<&Event> = dates
-                .iter()
-                .filter(|event| {
-                    event.date.year() == desired_date.year()
-                        && event.date.month() == desired_date.month()
-                        && event.date.day() == desired_date.day()
-                })
-                .collect();
 
-            let res = json!({
-                "result": filtered_dates,
-            });
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-            return (StatusCode::OK, Json(res)).into_response();
-        }
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
-        }
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
+    tracing::info!("shutting down");
 }
 
-// Обработчик, возващающий все события недели для указанной даты
-async fn events_for_week_handler(
-    State(dates): State<Arc<Mutex<Vec<Event>>>>,
-    Query(param): Query<Value>,
-) -> Response {
-    // Проверяем на валидность входные данные
-    let desired_date = match query_parse(param).await {
-        Ok(value) => value,
-        Err(e) => {
-            return e;
-        }
-    };
-    let dates = dates.lock();
-    match dates {
-        Ok(dates) => {
-            // Проходим по всем имеющимся событиям и оставляем те, начала недели у кооторых совпадают с указанным событием
-            let filtered_dates: Vec<&Event> = dates
-                .iter()
-                .filter(|event| {
-                    let start_week_1 = start_of_week(event.date.date_naive());
-                    let start_week_2 = start_of_week(desired_date);
-                    start_week_1 == start_week_2
-                })
-                .collect();
-
-            let res = json!({
-                "result": filtered_dates,
-            });
-
-            return (StatusCode::OK, Json(res)).into_response();
-        }
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
-        }
-    }
+// Обработчик проверки работоспособности: отвечает всегда, пока сервер запущен
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "Сервер запущен", body = openapi::StatusResponse),
+    )
+)]
+async fn health_handler() -> Response {
+    let res = json!({
+        "status": "ok",
+    });
+    (StatusCode::OK, Json(res)).into_response()
 }
 
-// Обработчик, возващающий все события месяца для указанной даты
-async fn events_for_month_handler(
-    State(dates): State<Arc<Mutex<Vec<Event>>>>,
-    Query(param): Query<Value>,
-) -> Response {
-    let desired_date = match query_parse(param).await {
-        Ok(value) => value,
-        Err(e) => {
-            return e;
-        }
-    };
-    let dates = dates.lock();
-    match dates {
-        Ok(dates) => {
-            // Проходим по всем имеющимся событиям и оставляем те, месяц и год которых соответствуют указанному событию
-            let filtered_dates: Vec<&Event> = dates
-                .iter()
-                .filter(|event| {
-                    event.date.year() == desired_date.year()
-                        && event.date.month() == desired_date.month()
-                })
-                .collect();
-
-            let res = json!({
-                "result": filtered_dates,
-            });
+// Обработчик, возвращающий метрики запросов в текстовом формате Prometheus
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Количество запросов по маршрутам и кодам ответа и гистограмма времени обработки", body = String, content_type = "text/plain"),
+    )
+)]
+async fn metrics_handler(State(AppState { metrics, .. }): State<AppState>) -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+        .into_response()
+}
 
-            return (StatusCode::OK, Json(res)).into_response();
-        }
-        Err(_) => {
-            return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
-        }
+// Обработчик проверки готовности: хранилище доступно и в файл событий можно писать
+#[utoipa::path(
+    get,
+    path = "/ready",
+    responses(
+        (status = 200, description = "Сервер готов обслуживать запросы", body = openapi::StatusResponse),
+        (status = 503, description = "Хранилище недоступно", body = openapi::StatusResponse),
+    )
+)]
+async fn ready_handler(State(AppState { store, .. }): State<AppState>) -> Response {
+    if let Err(e) = store.check_ready().await {
+        let res = json!({
+            "status": "unavailable",
+            "error": store_error_message(&e),
+        });
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(res)).into_response();
     }
+    let res = json!({
+        "status": "ok",
+    });
+    (StatusCode::OK, Json(res)).into_response()
 }
-// Функция для определения начала недели для указанной даты
-fn start_of_week(date: NaiveDate) -> NaiveDate {
-    let diff = date.weekday().num_days_from_monday();
-    date - Duration::days(diff as i64)
-}
-// Функция для нахождения указанного события в массиве событий
-async fn check_event(events: &Arc<Mutex<Vec<Event>>>, desired_event: &Event) -> Option<usize> {
-    let events = events.lock().unwrap();
-    for (i, event) in events.iter().enumerate() {
-        if event.date == desired_event.date && event.name == desired_event.name {
-            return Some(i);
+
+// Фоновая задача, записывающая накопившиеся изменения на диск
+// Частые изменения подряд дают одну запись за интервал, а не запись на каждое изменение
+async fn flush_periodically(state: AppState) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_millis(
+        state.settings.flush_interval_ms,
+    ));
+    loop {
+        interval.tick().await;
+        if let Err(e) = state.store.flush().await {
+            tracing::warn!(error = ?e, "failed to flush events");
         }
     }
-    return None;
 }
 
-// Функция для извлечения даты из query-строки
-async fn query_parse(param: Value) -> Result<NaiveDate, Response> {
-    let query: DateParam = match serde_json::from_value(param) {
-        Ok(query) => query,
-        Err(e) => {
-            let res = json!({
-                "error": format!("{}",e),
-            });
-            return Err((StatusCode::BAD_REQUEST, Json(res)).into_response());
-        }
-    };
-    match NaiveDate::from_str(&query.date) {
-        Ok(value) => {
-            return Ok(value);
-        }
-        Err(e) => {
-            let res = json!({
-                "error": format!("{}",e),
-            });
-            return Err((StatusCode::BAD_REQUEST, Json(res)).into_response());
-        }
-    };
-}
-// Функция для извлечения даты и названия события из json
-async fn json_body_parse(body: Value) -> Result<Event, Response> {
-    let body: Result<EventReq, _> = serde_json::from_value(body);
-    match body {
-        Ok(body) => {
-            match DateTime::parse_from_rfc3339(&body.date_time) {
-                Ok(value) => {
-                    return Ok(Event {
-                        date: value.with_timezone(&Utc),
-                        name: body.event_name,
-                    })
-                }
-                Err(e) => {
-                    let res = json!({
-                        "error": format!("{}",e),
-                    });
-                    return Err((StatusCode::BAD_REQUEST, Json(res)).into_response());
-                }
-            };
-        }
-        Err(e) => {
-            let res = json!({
-                "error": format!("{}",e),
-            });
-            return Err((StatusCode::BAD_REQUEST, Json(res)).into_response());
+// Фоновая задача, окончательно удаляющая события, мягко удаленные дольше срока хранения
+async fn purge_deleted(state: AppState) {
+    let retention = state.settings.soft_delete_retention_secs;
+    // Проверяем не реже раза в минуту, а при коротком сроке хранения - чаще
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(retention.clamp(1, 60)));
+    loop {
+        interval.tick().await;
+        let before = Utc::now() - Duration::seconds(retention as i64);
+        match state.store.purge(before).await {
+            Ok(purged) if purged > 0 => tracing::info!(purged, "purged soft-deleted events"),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(error = ?e, "failed to purge soft-deleted events"),
         }
     }
 }
-#[derive(Deserialize)]
-struct DateParam {
-    date: String,
-}
-
-#[derive(Deserialize)]
-struct EventReq {
-    date_time: String,
-    event_name: String,
-}
-
-#[derive(Deserialize)]
-struct EventUpdateReq {
-    date_time: String,
-    event_name: String,
-    new_date_time: String,
-    new_event_name: String,
-}
-
-#[derive(Deserialize, Serialize)]
-struct Event {
-    date: DateTime<Utc>,
-    name: String,
-}
 
-#[derive(Debug, Deserialize)]
-struct Settings {
-    address: String,
-    port: u16,
+// Общее состояние сервера, передаваемое в обработчики
+#[derive(Clone)]
+struct AppState {
+    // Здесь храним даты и события по календарям
+    store: Arc<dyn EventStore>,
+    settings: Arc<Settings>,
+    // События, ожидающие удаления, по идентификатору отложенного удаления
+    pending_deletes: Arc<Mutex<HashMap<u64, Event>>>,
+    next_pending_id: Arc<AtomicU64>,
+    // Ответы на запросы создания по ключу идемпотентности, включая еще выполняющиеся запросы
+    idempotency_keys: Arc<IdempotencyKeys>,
+    // Счетчики запросов и время их обработки для /metrics
+    metrics: Arc<Metrics>,
+    // Канал изменений событий для подписчиков /ws
+    changes: ChangeFeed,
+    // Журнал изменений для /audit
+    audit: Arc<AuditLog>,
+    rate_limiter: Option<Arc<Mutex<TokenBucket>>>,
 }
@@ -0,0 +1,196 @@
+// Метрики запросов в текстовом формате Prometheus
+// Для каждого маршрута считаются запросы по коду ответа и распределение времени обработки
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{MatchedPath, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+
+// Маршрут самого /metrics: его запросы не учитываются, чтобы опрос метрик не искажал их
+const METRICS_ROUTE: &str = "/metrics";
+// Метка для запросов, не попавших ни в один маршрут
+const UNMATCHED_ROUTE: &str = "unmatched";
+// Верхние границы корзин гистограммы времени обработки в секундах
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// Хранилище метрик, общее для всех запросов
+#[derive(Default)]
+pub struct Metrics {
+    registry: Mutex<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    // Количество запросов по (метод, маршрут, код ответа)
+    requests: BTreeMap<(String, String, u16), u64>,
+    // Время обработки по (метод, маршрут)
+    durations: BTreeMap<(String, String), Histogram>,
+    // Сколько раз подписчики канала изменений отставали и сколько изменений пропустили, по виду подписки (ws, sse)
+    lagged: BTreeMap<&'static str, (u64, u64)>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    // Количество наблюдений в каждой корзине, не накопленное
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Metrics {
+    // Учитываем обработанный запрос
+    pub fn observe(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        let mut registry = self.registry.lock().unwrap_or_else(|e| {
+            self.registry.clear_poison();
+            e.into_inner()
+        });
+        *registry
+            .requests
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+        let histogram = registry
+            .durations
+            .entry((method.to_string(), route.to_string()))
+            .or_default();
+        if let Some(i) = DURATION_BUCKETS.iter().position(|le| seconds <= *le) {
+            histogram.buckets[i] += 1;
+        }
+        histogram.sum += seconds;
+        histogram.count += 1;
+    }
+
+    // Учитываем подписчика канала изменений, который отстал и пропустил skipped изменений
+    pub fn observe_lagged(&self, transport: &'static str, skipped: u64) {
+        let mut registry = self.registry.lock().unwrap_or_else(|e| {
+            self.registry.clear_poison();
+            e.into_inner()
+        });
+        let (lagged, total_skipped) = registry.lagged.entry(transport).or_default();
+        *lagged += 1;
+        *total_skipped += skipped;
+    }
+
+    // Метрики в текстовом формате Prometheus
+    pub fn render(&self) -> String {
+        let registry = self.registry.lock().unwrap_or_else(|e| {
+            self.registry.clear_poison();
+            e.into_inner()
+        });
+        let mut out = String::new();
+        out.push_str("# HELP http_requests_total Total number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route, status), count) in &registry.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape_label(method),
+                escape_label(route),
+                status,
+                count
+            );
+        }
+        out.push_str("# HELP http_request_duration_seconds HTTP request latency in seconds.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in &registry.durations {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\"",
+                escape_label(method),
+                escape_label(route)
+            );
+            // Корзины в формате Prometheus накопленные: le="0.1" включает все запросы быстрее 0.1 секунды
+            let mut cumulative = 0;
+            for (le, count) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+        out.push_str(
+            "# HELP change_feed_lagged_total Times a change feed subscriber fell behind the channel.\n",
+        );
+        out.push_str("# TYPE change_feed_lagged_total counter\n");
+        for (transport, (lagged, _)) in &registry.lagged {
+            let _ = writeln!(
+                out,
+                "change_feed_lagged_total{{transport=\"{}\"}} {}",
+                transport, lagged
+            );
+        }
+        out.push_str(
+            "# HELP change_feed_skipped_total Changes skipped by lagging change feed subscribers.\n",
+        );
+        out.push_str("# TYPE change_feed_skipped_total counter\n");
+        for (transport, (_, skipped)) in &registry.lagged {
+            let _ = writeln!(
+                out,
+                "change_feed_skipped_total{{transport=\"{}\"}} {}",
+                transport, skipped
+            );
+        }
+        out
+    }
+}
+
+// Экранирование значения метки: обратная косая черта, кавычка и перевод строки
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Middleware, учитывающий запрос в метриках
+// Маршрут берется шаблоном (/event/:id), а не фактическим путем, чтобы число меток не росло с каждым id
+pub async fn record_metrics<B>(
+    State(metrics): State<Arc<Metrics>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    if route.as_deref() == Some(METRICS_ROUTE) {
+        return next.run(req).await;
+    }
+    let start = Instant::now();
+    let method = req.method().clone();
+
+    let res = next.run(req).await;
+
+    metrics.observe(
+        method.as_str(),
+        route.as_deref().unwrap_or(UNMATCHED_ROUTE),
+        res.status().as_u16(),
+        start.elapsed().as_secs_f64(),
+    );
+    res
+}
@@ -0,0 +1,347 @@
+// Событие календаря и правила его повторения
+use std::str::FromStr;
+
+use chrono::{
+    DateTime, Datelike, Duration, LocalResult, Months, NaiveDate, NaiveTime, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+use serde::{ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{dates::event_days, settings::MidnightBoundary, DEFAULT_CALENDAR};
+
+// Функция для получения вхождений события, попадающих в окно дат [from, to] в часовом поясе tz
+// Событие с временем окончания попадает в окно, если его интервал пересекается с окном
+// Разовое событие возвращается как есть, если попадает в окно
+// Повторения вычисляются на лету и не хранятся в памяти, у всех повторений тот же id, что и у исходного события
+pub fn occurrences(
+    event: &Event,
+    from: NaiveDate,
+    to: NaiveDate,
+    tz: Tz,
+    boundary: MidnightBoundary,
+) -> Vec<Event> {
+    // День события на весь день - его дата, независимо от часового пояса запроса и правила для полуночи
+    let (tz, boundary) = if event.all_day {
+        (Tz::UTC, MidnightBoundary::Start)
+    } else {
+        (tz, boundary)
+    };
+    let recurrence = match event.recurrence {
+        Some(recurrence) => recurrence,
+        None => {
+            let (first_day, last_day) = event_days(event.date, event.end_date, tz, boundary);
+            return if first_day <= to && from <= last_day {
+                vec![event.clone()]
+            } else {
+                Vec::new()
+            };
+        }
+    };
+    // Повторения длятся столько же, сколько исходное событие
+    let duration = event.end_date.map(|end| end - event.date);
+    let span_days = duration
+        .map(|duration| duration.num_days() + 1)
+        .unwrap_or(0);
+    let mut result = Vec::new();
+    let mut n = recurrence.skip_before(
+        event.date.date_naive(),
+        from.checked_sub_signed(Duration::days(span_days))
+            .unwrap_or(NaiveDate::MIN),
+    );
+    while let Some(date) = recurrence.nth(event.date, n, event.tz.unwrap_or(Tz::UTC)) {
+        if event.recurrence_until.is_some_and(|until| date > until) {
+            break;
+        }
+        let end_date = duration.map(|duration| date + duration);
+        let (first_day, last_day) = event_days(date, end_date, tz, boundary);
+        if first_day > to {
+            break;
+        }
+        if last_day >= from {
+            result.push(Event {
+                date,
+                end_date,
+                ..event.clone()
+            });
+        }
+        n += 1;
+    }
+    result
+}
+
+// Функция для получения не более limit ближайших вхождений события, начинающихся не раньше now
+pub fn upcoming_occurrences(event: &Event, now: DateTime<Utc>, limit: usize) -> Vec<Event> {
+    let recurrence = match event.recurrence {
+        Some(recurrence) => recurrence,
+        None if event.date >= now => return vec![event.clone()],
+        None => return Vec::new(),
+    };
+    let duration = event.end_date.map(|end| end - event.date);
+    let mut result = Vec::new();
+    let mut n = recurrence.skip_before(event.date.date_naive(), now.date_naive());
+    while result.len() < limit {
+        let Some(date) = recurrence.nth(event.date, n, event.tz.unwrap_or(Tz::UTC)) else {
+            break;
+        };
+        if event.recurrence_until.is_some_and(|until| date > until) {
+            break;
+        }
+        if date >= now {
+            result.push(Event {
+                date,
+                end_date: duration.map(|duration| date + duration),
+                ..event.clone()
+            });
+        }
+        n += 1;
+    }
+    result
+}
+
+tokio::task_local! {
+    // Выводить ли незаданные необязательные поля события как null, а не пропускать их
+    // Задается на время обработки запроса из настроек (см. layers::null_fields), вне запроса поля пропускаются
+    pub static INCLUDE_NULLS: bool;
+}
+
+// Функция для записи незаданного необязательного поля события
+fn absent_field<S: SerializeStruct>(state: &mut S, key: &'static str) -> Result<(), S::Error> {
+    if INCLUDE_NULLS.try_with(|include| *include).unwrap_or(false) {
+        state.serialize_field(key, &None::<()>)
+    } else {
+        state.skip_field(key)
+    }
+}
+
+// Событие сериализуется вручную (см. impl Serialize ниже), чтобы у события на весь день выводилась дата без времени
+#[derive(Clone, Deserialize, ToSchema)]
+pub struct Event {
+    pub id: Uuid,
+    // Название календаря, к которому относится событие
+    #[serde(default = "default_calendar")]
+    pub calendar: String,
+    // У события на весь день - полночь UTC его даты
+    #[serde(deserialize_with = "date_or_date_time")]
+    pub date: DateTime<Utc>,
+    // Время окончания события, если не задано - событие без длительности
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_date: Option<DateTime<Utc>>,
+    pub name: String,
+    // Правило повторения, если не задано - событие разовое
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Recurrence>,
+    // Дата, после которой событие больше не повторяется, если не задана - повторяется бесконечно
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recurrence_until: Option<DateTime<Utc>>,
+    // Часовой пояс (IANA), в котором повторения сохраняют местное время начала, например 09:00 и после перехода на летнее время
+    // Если не задан - повторения идут с шагом в сутки, неделю или месяц по UTC
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub tz: Option<Tz>,
+    // Время создания и последнего изменения события
+    // У событий из файла, сохраненного до появления этих полей, - время загрузки
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+    // Время мягкого удаления, у не удаленного события не задано
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Версия события, увеличивается при каждом изменении
+    // У событий из файла, сохраненного до появления версий, - первая версия
+    #[serde(default = "first_version")]
+    pub version: u64,
+    // Теги события в нижнем регистре, без повторов
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // Событие на весь день: у него есть только дата, время и часовой пояс не учитываются
+    #[serde(default)]
+    pub all_day: bool,
+    // Описание и место проведения, необязательны
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Event", 16)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("calendar", &self.calendar)?;
+        if self.all_day {
+            state.serialize_field("date", &self.date.date_naive())?;
+        } else {
+            state.serialize_field("date", &self.date)?;
+        }
+        match &self.end_date {
+            Some(end_date) => state.serialize_field("end_date", end_date)?,
+            None => absent_field(&mut state, "end_date")?,
+        }
+        state.serialize_field("name", &self.name)?;
+        match &self.recurrence {
+            Some(recurrence) => state.serialize_field("recurrence", recurrence)?,
+            None => absent_field(&mut state, "recurrence")?,
+        }
+        match &self.recurrence_until {
+            Some(until) => state.serialize_field("recurrence_until", until)?,
+            None => absent_field(&mut state, "recurrence_until")?,
+        }
+        match &self.tz {
+            Some(tz) => state.serialize_field("tz", tz)?,
+            None => absent_field(&mut state, "tz")?,
+        }
+        state.serialize_field("created_at", &self.created_at)?;
+        state.serialize_field("updated_at", &self.updated_at)?;
+        match &self.deleted_at {
+            Some(deleted_at) => state.serialize_field("deleted_at", deleted_at)?,
+            None => absent_field(&mut state, "deleted_at")?,
+        }
+        state.serialize_field("version", &self.version)?;
+        if self.tags.is_empty() {
+            state.skip_field("tags")?;
+        } else {
+            state.serialize_field("tags", &self.tags)?;
+        }
+        if self.all_day {
+            state.serialize_field("all_day", &self.all_day)?;
+        } else {
+            state.skip_field("all_day")?;
+        }
+        match &self.description {
+            Some(description) => state.serialize_field("description", description)?,
+            None => absent_field(&mut state, "description")?,
+        }
+        match &self.location {
+            Some(location) => state.serialize_field("location", location)?,
+            None => absent_field(&mut state, "location")?,
+        }
+        state.end()
+    }
+}
+
+// Функция для чтения даты события: дата и время либо дата без времени у события на весь день
+fn date_or_date_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DateTime<Utc>, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    if let Ok(date) = NaiveDate::from_str(&value) {
+        return Ok(date.and_time(NaiveTime::MIN).and_utc());
+    }
+    value.parse().map_err(serde::de::Error::custom)
+}
+
+impl Event {
+    // Создаем разовое событие с новым уникальным идентификатором
+    pub fn new(calendar: String, date: DateTime<Utc>, name: String) -> Self {
+        let now = Utc::now();
+        Event {
+            id: Uuid::new_v4(),
+            calendar,
+            date,
+            end_date: None,
+            name,
+            recurrence: None,
+            recurrence_until: None,
+            tz: None,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            version: 1,
+            tags: Vec::new(),
+            all_day: false,
+            description: None,
+            location: None,
+        }
+    }
+}
+
+// Версия нового события
+fn first_version() -> u64 {
+    1
+}
+
+// Календарь для событий из файла, сохраненного до появления календарей
+fn default_calendar() -> String {
+    DEFAULT_CALENDAR.to_string()
+}
+
+// Правило повторения события
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Recurrence {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl Recurrence {
+    // Дата n-го повторения события
+    // Считаем от исходной даты, а не от предыдущего повторения, чтобы не накапливать сдвиг в конце месяца
+    // Шаг отсчитывается по местному времени в часовом поясе tz, поэтому при переходе на летнее время
+    // повторение сохраняет время начала по часам, а его время по UTC сдвигается
+    pub fn nth(self, start: DateTime<Utc>, n: u32, tz: Tz) -> Option<DateTime<Utc>> {
+        let start = start.with_timezone(&tz).naive_local();
+        let local = match self {
+            Recurrence::Daily => start.checked_add_signed(Duration::days(n as i64)),
+            Recurrence::Weekly => start.checked_add_signed(Duration::weeks(n as i64)),
+            Recurrence::Monthly => start.checked_add_months(Months::new(n)),
+        }?;
+        match tz.from_local_datetime(&local) {
+            // Время, повторяющееся при переходе на зимнее время, - берем первое
+            LocalResult::Single(date) | LocalResult::Ambiguous(date, _) => {
+                Some(date.with_timezone(&Utc))
+            }
+            // Времени, пропущенного при переходе на летнее время, нет - повторение сдвигается на час позже
+            LocalResult::None => tz
+                .from_local_datetime(&(local + Duration::hours(1)))
+                .earliest()
+                .map(|date| date.with_timezone(&Utc)),
+        }
+    }
+
+    // Количество повторений, которые заведомо закончились до указанной даты
+    // Берем с запасом в одно повторение, чтобы не потерять событие в полночь на границе
+    pub fn skip_before(self, start: NaiveDate, from: NaiveDate) -> u32 {
+        if from <= start {
+            return 0;
+        }
+        let n = match self {
+            Recurrence::Daily => (from - start).num_days(),
+            Recurrence::Weekly => (from - start).num_days() / 7,
+            Recurrence::Monthly => {
+                (from.year() - start.year()) as i64 * 12 + from.month() as i64
+                    - start.month() as i64
+            }
+        };
+        (n - 1).max(0) as u32
+    }
+}
+
+// Период, содержащий указанную дату: день, неделя, месяц или год
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+// Порядок сортировки событий в ответе
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    DateAsc,
+    DateDesc,
+}
+
+// Режим создания события: create - событие с уже существующим id отклоняется, upsert - заменяется
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CreateMode {
+    Create,
+    Upsert,
+}
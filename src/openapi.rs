@@ -0,0 +1,610 @@
+// Описание API в формате OpenAPI 3 и страница Swagger UI для его просмотра
+// Описание маршрутов собирается из аннотаций utoipa::path на обработчиках
+// Структуры ниже только описывают тела запросов и ответов для документации и не создаются в коде
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use axum::{
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+use uuid::Uuid;
+
+use crate::{
+    admin::ReplayReport,
+    audit::{AuditEntry, AuditOp},
+    model::Period,
+    store::{ReindexReport, StoreDump},
+    validation::ValidationError,
+    CreateMode, Event, GoogleEvent, Recurrence, SortOrder,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Календарь",
+        description = "HTTP API для работы с календарем событий"
+    ),
+    paths(
+        crate::events::create_event_handler,
+        crate::events::bulk_create_handler,
+        crate::events::update_event_handler,
+        crate::events::delete_event_handler,
+        crate::events::undo_delete_handler,
+        crate::import::import_google_handler,
+        crate::import::import_ics_handler,
+        crate::events::get_event_handler,
+        crate::events::replace_event_handler,
+        crate::events::delete_event_by_id_handler,
+        crate::events::restore_event_handler,
+        crate::queries::list_events_handler,
+        crate::queries::events_for_day_handler,
+        crate::queries::events_for_days_handler,
+        crate::queries::events_for_week_handler,
+        crate::queries::events_for_month_handler,
+        crate::queries::events_for_year_handler,
+        crate::queries::events_in_range_handler,
+        crate::queries::week_grid_handler,
+        crate::queries::free_slots_handler,
+        crate::queries::busy_time_handler,
+        crate::queries::preview_recurrence_handler,
+        crate::queries::search_events_handler,
+        crate::queries::upcoming_events_handler,
+        crate::queries::events_by_duration_handler,
+        crate::queries::events_by_location_handler,
+        crate::exports::events_rss_handler,
+        crate::exports::events_ics_handler,
+        crate::exports::events_csv_handler,
+        crate::queries::calendars_handler,
+        crate::queries::tags_handler,
+        crate::queries::audit_handler,
+        crate::queries::stats_handler,
+        crate::changes::ws_handler,
+        crate::admin::reindex_handler,
+        crate::admin::replay_handler,
+        crate::admin::debug_dump_handler,
+        crate::changes::sse_handler,
+        crate::health_handler,
+        crate::ready_handler,
+        crate::metrics_handler,
+    ),
+    components(schemas(
+        Event,
+        Recurrence,
+        SortOrder,
+        CreateMode,
+        Period,
+        GoogleEvent,
+        crate::import::GoogleEventTime,
+        crate::events::UndoDeleteReq,
+        ValidationError,
+        EventReq,
+        EventUpdateReq,
+        EventReplaceReq,
+        GoogleImportReq,
+        ErrorResponse,
+        ValidationErrors,
+        VersionConflict,
+        MessageResponse,
+        StatusResponse,
+        CreatedResponse,
+        ScheduledDeleteResponse,
+        BulkResponse,
+        GoogleImportResponse,
+        IcsImportResponse,
+        EventResponse,
+        EventsResponse,
+        EventsPage,
+        EventsByDateResponse,
+        WeekGridResponse,
+        WeekGridDay,
+        FreeSlotsResponse,
+        BusyTimeResponse,
+        RecurrencePreviewReq,
+        RecurrencePreviewResponse,
+        FreeSlot,
+        CalendarsResponse,
+        TagsResponse,
+        TagCount,
+        StatsResponse,
+        AuditPage,
+        AuditEntry,
+        AuditOp,
+        UpcomingResponse,
+        ReindexResponse,
+        ReindexReport,
+        ReplayResponse,
+        ReplayReport,
+        DebugDumpResponse,
+        DebugDump,
+        StoreDump,
+    ))
+)]
+pub struct ApiDoc;
+
+// Обработчик, возвращающий описание API
+pub async fn openapi_handler() -> Response {
+    (StatusCode::OK, Json(ApiDoc::openapi())).into_response()
+}
+
+// Обработчик страницы Swagger UI, сама страница загружает скрипты и стили Swagger UI с CDN
+pub async fn docs_handler() -> Response {
+    (StatusCode::OK, Html(DOCS_PAGE)).into_response()
+}
+
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="ru">
+<head>
+  <meta charset="utf-8">
+  <title>Календарь - API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
+
+// Тело запроса создания, удаления и замены события, id учитывается только при создании
+// Даты - строки rfc3339 либо число секунд с начала эпохи Unix, у события на весь день (all_day) date_time - дата "год-месяц-день"
+#[derive(ToSchema)]
+pub struct EventReq {
+    id: Option<Uuid>,
+    calendar: String,
+    date_time: DateTime<Utc>,
+    all_day: Option<bool>,
+    end_date_time: Option<DateTime<Utc>>,
+    event_name: String,
+    recurrence: Option<Recurrence>,
+    recurrence_until: Option<DateTime<Utc>>,
+    tz: Option<String>,
+    tags: Option<Vec<String>>,
+    description: Option<String>,
+    location: Option<String>,
+}
+
+// Тело запроса /preview_recurrence: правило повторения как при создании события и окно дат [from, to]
+#[derive(ToSchema)]
+pub struct RecurrencePreviewReq {
+    date_time: DateTime<Utc>,
+    end_date_time: Option<DateTime<Utc>>,
+    recurrence: Recurrence,
+    recurrence_until: Option<DateTime<Utc>>,
+    tz: Option<String>,
+    from: NaiveDate,
+    to: NaiveDate,
+}
+
+// Даты начала вхождений в окне по возрастанию и их количество
+#[derive(ToSchema)]
+pub struct RecurrencePreviewResponse {
+    result: Vec<DateTime<Utc>>,
+    count: usize,
+}
+
+// Тело запроса PUT /event/{id}: событие как при создании и версия, которую видел клиент
+// Вместо version можно передать ETag события в заголовке If-Match
+#[derive(ToSchema)]
+pub struct EventReplaceReq {
+    calendar: String,
+    date_time: DateTime<Utc>,
+    all_day: Option<bool>,
+    end_date_time: Option<DateTime<Utc>>,
+    event_name: String,
+    recurrence: Option<Recurrence>,
+    recurrence_until: Option<DateTime<Utc>>,
+    tz: Option<String>,
+    tags: Option<Vec<String>>,
+    description: Option<String>,
+    location: Option<String>,
+    version: Option<u64>,
+}
+
+// Тело запроса /update_event: старое и новое значение события и версия, которую видел клиент
+#[derive(ToSchema)]
+pub struct EventUpdateReq {
+    calendar: String,
+    date_time: DateTime<Utc>,
+    event_name: String,
+    new_date_time: DateTime<Utc>,
+    new_event_name: String,
+    version: u64,
+}
+
+// Тело запроса импорта из Google Calendar, вместо объекта можно передать просто массив событий
+#[derive(ToSchema)]
+pub struct GoogleImportReq {
+    items: Vec<GoogleEvent>,
+}
+
+// Параметры выборки за день, неделю, месяц или год
+// Вместо даты можно передать today, yesterday или tomorrow
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DateParam {
+    calendar: String,
+    date: String,
+    tz: Option<String>,
+    sort: Option<SortOrder>,
+    limit: Option<usize>,
+    name_contains: Option<String>,
+    tag: Option<String>,
+    exclude_tags: Option<String>,
+    exclude_categories: Option<String>,
+}
+
+// Параметры выборки за несколько дней, dates - даты через запятую, например 2024-01-01,2024-01-02
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DaysParam {
+    calendar: String,
+    dates: String,
+    tz: Option<String>,
+    sort: Option<SortOrder>,
+    name_contains: Option<String>,
+    tag: Option<String>,
+    exclude_tags: Option<String>,
+    exclude_categories: Option<String>,
+}
+
+// Параметры выборки за диапазон дат
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RangeParam {
+    calendar: String,
+    from: NaiveDate,
+    to: NaiveDate,
+    sort: Option<SortOrder>,
+    limit: Option<usize>,
+    exclude_tags: Option<String>,
+    exclude_categories: Option<String>,
+}
+
+// Параметры постраничного списка событий
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListParam {
+    calendar: Option<String>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    sort: Option<SortOrder>,
+}
+
+// Параметры поиска по названию
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParam {
+    q: String,
+    calendar: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    limit: Option<usize>,
+    sort: Option<SortOrder>,
+}
+
+// Параметры поиска событий по длительности в минутах
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DurationParam {
+    min: Option<usize>,
+    max: Option<usize>,
+    calendar: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    limit: Option<usize>,
+    sort: Option<SortOrder>,
+}
+
+// Параметры выборки ближайших событий
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UpcomingParam {
+    calendar: Option<String>,
+    limit: Option<usize>,
+    tz: Option<String>,
+}
+
+// Параметры поиска свободного времени
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FreeSlotsParam {
+    calendar: String,
+    date: String,
+    duration_minutes: usize,
+    tz: Option<String>,
+}
+
+// Параметры подсчета занятого времени: period - day, week, month или year, overlaps - merge или sum
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BusyTimeParam {
+    calendar: String,
+    date: String,
+    period: Period,
+    overlaps: Option<String>,
+    tz: Option<String>,
+}
+
+// Параметры сетки недели
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WeekGridParam {
+    calendar: String,
+    date: String,
+    tz: Option<String>,
+}
+
+// Параметры выгрузки событий
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportParam {
+    calendar: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+}
+
+// Параметры экспорта в iCalendar: как у остальных экспортов и категории (теги) через запятую, например work,home
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IcsExportParam {
+    calendar: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    category: Option<String>,
+}
+
+// Параметр календаря, теги которого нужно вернуть
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TagsParam {
+    calendar: Option<String>,
+}
+
+// Параметры статистики по месяцам
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsParam {
+    calendar: Option<String>,
+    year: Option<usize>,
+}
+
+// Параметры страницы журнала изменений
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditParam {
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+// Параметр календаря, в который импортируются события
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportParam {
+    calendar: String,
+}
+
+// Параметры создания события
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CreateParam {
+    mode: Option<CreateMode>,
+    verbose: Option<bool>,
+    reject_overlap: Option<bool>,
+}
+
+// Параметры создания нескольких событий
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BulkParam {
+    atomic: Option<bool>,
+    rollback_on_error: Option<bool>,
+}
+
+#[derive(ToSchema)]
+pub struct ErrorResponse {
+    error: String,
+}
+
+#[derive(ToSchema)]
+pub struct ValidationErrors {
+    errors: Vec<ValidationError>,
+}
+
+#[derive(ToSchema)]
+pub struct MessageResponse {
+    result: String,
+}
+
+// Событие изменено другим клиентом: version - текущая версия события, в current - само событие (нет с Prefer: return=minimal)
+#[derive(ToSchema)]
+pub struct VersionConflict {
+    error: String,
+    version: u64,
+    current: Option<Event>,
+}
+
+#[derive(ToSchema)]
+pub struct StatusResponse {
+    status: String,
+    error: Option<String>,
+}
+
+// Ответ на создание события: result - созданное событие, а с verbose=true в summary - его описание
+#[derive(ToSchema)]
+pub struct CreatedResponse {
+    id: Uuid,
+    result: Event,
+    summary: Option<String>,
+    warnings: Option<Vec<String>>,
+}
+
+#[derive(ToSchema)]
+pub struct ScheduledDeleteResponse {
+    result: String,
+    undo_id: u64,
+    delay_secs: u64,
+}
+
+// Ошибки в bulk-ответе содержат номер события в массиве (index)
+#[derive(ToSchema)]
+pub struct BulkResponse {
+    // atomic или best_effort
+    mode: String,
+    created: Vec<Uuid>,
+    errors: Vec<Value>,
+}
+
+#[derive(ToSchema)]
+pub struct GoogleImportResponse {
+    imported: usize,
+    result: Vec<Value>,
+}
+
+#[derive(ToSchema)]
+pub struct IcsImportResponse {
+    imported: usize,
+    skipped: usize,
+}
+
+#[derive(ToSchema)]
+pub struct EventResponse {
+    result: Event,
+}
+
+// Если список был обрезан параметром limit - truncated и total содержат общее количество найденных событий
+#[derive(ToSchema)]
+pub struct EventsResponse {
+    result: Vec<Event>,
+    truncated: Option<bool>,
+    total: Option<usize>,
+}
+
+// События по датам, ключи вида "2024-01-01"
+#[derive(ToSchema)]
+pub struct EventsByDateResponse {
+    result: HashMap<String, Vec<Event>>,
+}
+
+#[derive(ToSchema)]
+pub struct EventsPage {
+    result: Vec<Event>,
+    total: usize,
+    limit: usize,
+    offset: usize,
+}
+
+#[derive(ToSchema)]
+pub struct WeekGridResponse {
+    week_start: NaiveDate,
+    result: Vec<WeekGridDay>,
+}
+
+// События дня по часу начала
+#[derive(ToSchema)]
+pub struct WeekGridDay {
+    date: NaiveDate,
+    hours: HashMap<String, Vec<Event>>,
+}
+
+#[derive(ToSchema)]
+pub struct FreeSlotsResponse {
+    result: Vec<FreeSlot>,
+}
+
+// Занятое время за период: from и to - первый и последний день периода, events - количество учтенных вхождений
+#[derive(ToSchema)]
+pub struct BusyTimeResponse {
+    period: Period,
+    from: NaiveDate,
+    to: NaiveDate,
+    overlaps: String,
+    events: usize,
+    busy_minutes: i64,
+}
+
+// Отчет о перестроении индексов хранилища
+#[derive(ToSchema)]
+pub struct ReindexResponse {
+    result: ReindexReport,
+}
+
+// Параметры воспроизведения журнала изменений
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReplayParam {
+    // true - привести хранилище к состоянию по журналу, по умолчанию только сравнить
+    apply: Option<bool>,
+}
+
+// Отчет о воспроизведении журнала изменений
+#[derive(ToSchema)]
+pub struct ReplayResponse {
+    result: ReplayReport,
+}
+
+// Внутреннее состояние сервера: settings - действующие настройки со скрытым ключом API
+#[derive(ToSchema)]
+pub struct DebugDumpResponse {
+    result: DebugDump,
+}
+
+#[derive(ToSchema)]
+pub struct DebugDump {
+    store: StoreDump,
+    unsaved_changes: bool,
+    settings: HashMap<String, Value>,
+    recent_audit: Vec<AuditEntry>,
+}
+
+#[derive(ToSchema)]
+pub struct FreeSlot {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+}
+
+#[derive(ToSchema)]
+pub struct CalendarsResponse {
+    result: Vec<String>,
+}
+
+#[derive(ToSchema)]
+pub struct TagsResponse {
+    result: Vec<TagCount>,
+}
+
+#[derive(ToSchema)]
+pub struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+// Количество событий по месяцам, ключи вида "2024-01"
+#[derive(ToSchema)]
+pub struct StatsResponse {
+    result: HashMap<String, usize>,
+}
+
+// Записи журнала изменений от старых к новым
+#[derive(ToSchema)]
+pub struct AuditPage {
+    result: Vec<AuditEntry>,
+    total: usize,
+    limit: usize,
+    offset: usize,
+}
+
+// Текущее время в часовом поясе запроса и ближайшие события
+#[derive(ToSchema)]
+pub struct UpcomingResponse {
+    now: String,
+    result: Vec<Event>,
+}
@@ -0,0 +1,455 @@
+// Разбор параметров запроса и тел событий
+use std::str::FromStr;
+
+use axum::{
+    extract::Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::{NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::{
+    model::{CreateMode, Event, Recurrence, SortOrder},
+    validation,
+    validation::{ValidationError, Validator},
+    DEFAULT_CALENDAR,
+};
+
+// Функция для извлечения даты из query-строки
+// Кроме даты "год-месяц-день" принимаются today, yesterday и tomorrow - относительно текущей даты в часовом поясе tz
+pub async fn query_parse(param: Value) -> Result<NaiveDate, Response> {
+    // Некорректный часовой пояс отклоняют обработчики, которые его принимают, здесь считаем его UTC
+    let tz = param
+        .get("tz")
+        .and_then(|value| value.as_str())
+        .and_then(|value| value.parse::<Tz>().ok())
+        .unwrap_or(Tz::UTC);
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    let relative = match param.get("date").and_then(|value| value.as_str()) {
+        Some("today") => Some(today),
+        Some("yesterday") => today.pred_opt(),
+        Some("tomorrow") => today.succ_opt(),
+        _ => None,
+    };
+    if let Some(date) = relative {
+        return Ok(date);
+    }
+    let mut validator = Validator::new(&param);
+    match validator.date("date") {
+        Some(date) => Ok(date),
+        None => Err(validation::errors_response(validator.into_errors())),
+    }
+}
+
+// Функция для извлечения списка дат "год-месяц-день" через запятую из query-строки
+// Ошибка хотя бы в одной дате или больше max_dates дат отклоняют весь запрос
+pub async fn dates_parse(param: &Value, max_dates: usize) -> Result<Vec<NaiveDate>, Response> {
+    let mut validator = Validator::new(param);
+    let Some(value) = validator.field::<String>("dates") else {
+        return Err(validation::errors_response(validator.into_errors()));
+    };
+    let items: Vec<&str> = value.split(',').map(|item| item.trim()).collect();
+    if items.len() > max_dates {
+        validator.error("dates", format!("at most {} dates are allowed", max_dates));
+        return Err(validation::errors_response(validator.into_errors()));
+    }
+    let mut dates = Vec::with_capacity(items.len());
+    for item in items {
+        match NaiveDate::from_str(item) {
+            Ok(date) => dates.push(date),
+            Err(e) => validator.error("dates", format!("'{}': {}", item, e)),
+        }
+    }
+    validator.finish().map_err(validation::errors_response)?;
+    Ok(dates)
+}
+
+// Функция для извлечения диапазона дат из query-строки
+pub async fn range_parse(param: Value, max_days: i64) -> Result<(NaiveDate, NaiveDate), Response> {
+    let mut validator = Validator::new(&param);
+    let from = validator.date("from");
+    let to = validator.date("to");
+    let (Some(from), Some(to)) = (from, to) else {
+        return Err(validation::errors_response(validator.into_errors()));
+    };
+    if from > to {
+        validator.error("from", "must not be after to");
+        return Err(validation::errors_response(validator.into_errors()));
+    }
+    // Повторяющиеся события разворачиваются во вхождения внутри диапазона,
+    // поэтому слишком широкий диапазон дал бы миллионы вхождений одного события
+    if (to - from).num_days() >= max_days {
+        validator.error("to", format!("range must not exceed {} days", max_days));
+        return Err(validation::errors_response(validator.into_errors()));
+    }
+    Ok((from, to))
+}
+
+// Функция для проверки флага в query-строке, флаг включен только значением "true"
+pub fn query_flag(param: &Value, name: &str) -> bool {
+    param.get(name).and_then(|value| value.as_str()) == Some("true")
+}
+
+// Функция для извлечения необязательного неотрицательного числа (limit, offset) из query-строки
+pub async fn usize_param_parse(param: &Value, name: &str) -> Result<Option<usize>, Response> {
+    match param.get(name) {
+        None => Ok(None),
+        Some(value) => match value.as_str().unwrap_or_default().parse::<usize>() {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                let res = json!({
+                    "error": format!("{}: {}", name, e),
+                });
+                Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+            }
+        },
+    }
+}
+
+// Функция для извлечения строки поиска q из query-строки в нижнем регистре
+// Пустая строка отклоняется: она нашла бы все события
+pub async fn search_text_parse(param: &Value) -> Result<String, Response> {
+    match param.get("q").and_then(|value| value.as_str()) {
+        Some(query) if !query.trim().is_empty() => Ok(query.to_lowercase()),
+        _ => {
+            let res = json!({
+                "error": format!("q must not be empty"),
+            });
+            Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+        }
+    }
+}
+
+// Функция для извлечения необязательного порядка сортировки из query-строки
+pub async fn sort_parse(param: &Value) -> Result<Option<SortOrder>, Response> {
+    match param.get("sort") {
+        None => Ok(None),
+        Some(value) => match serde_json::from_value(value.clone()) {
+            Ok(sort) => Ok(Some(sort)),
+            Err(e) => {
+                let res = json!({
+                    "error": format!("sort: {}", e),
+                });
+                Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+            }
+        },
+    }
+}
+
+// Функция для извлечения значения перечисления (например, period или overlaps) из query-строки
+pub async fn enum_param_parse<T: DeserializeOwned>(
+    param: &Value,
+    name: &str,
+) -> Result<Option<T>, Response> {
+    match param.get(name) {
+        None => Ok(None),
+        Some(value) => match serde_json::from_value(value.clone()) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                let res = json!({
+                    "error": format!("{}: {}", name, e),
+                });
+                Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+            }
+        },
+    }
+}
+
+// Функция для извлечения режима создания события из query-строки, по умолчанию create
+pub async fn create_mode_parse(param: &Value) -> Result<CreateMode, Response> {
+    match param.get("mode") {
+        None => Ok(CreateMode::Create),
+        Some(value) => match serde_json::from_value(value.clone()) {
+            Ok(mode) => Ok(mode),
+            Err(e) => {
+                let res = json!({
+                    "error": format!("mode: {}", e),
+                });
+                Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+            }
+        },
+    }
+}
+
+// Функция для извлечения необязательного часового пояса (IANA, например America/New_York) из query-строки
+pub async fn tz_parse(param: &Value) -> Result<Tz, Response> {
+    match param.get("tz") {
+        None => Ok(Tz::UTC),
+        Some(value) => match value.as_str().unwrap_or_default().parse::<Tz>() {
+            Ok(tz) => Ok(tz),
+            Err(e) => {
+                let res = json!({
+                    "error": format!("tz: {}", e),
+                });
+                Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+            }
+        },
+    }
+}
+
+// Функция для извлечения подстроки названия из query-строки, подстрока приводится к нижнему регистру
+pub fn name_contains_parse(param: &Value) -> Option<String> {
+    param
+        .get("name_contains")
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_lowercase())
+}
+
+// Функция для проверки, содержит ли название события подстроку без учета регистра
+// Если подстрока не задана - подходит любое событие
+pub fn name_matches(event: &Event, name_contains: Option<&str>) -> bool {
+    name_contains.is_none_or(|name_contains| event.name.to_lowercase().contains(name_contains))
+}
+
+// Функция для извлечения тега из query-строки, тег приводится к нижнему регистру, как и теги событий
+pub fn tag_parse(param: &Value) -> Option<String> {
+    param
+        .get("tag")
+        .and_then(|value| value.as_str())
+        .map(|value| value.trim().to_lowercase())
+}
+
+// Функция для извлечения исключаемых тегов из query-строки: exclude_tags и exclude_categories через запятую
+// Категории событий - это их теги, поэтому оба параметра объединяются в один список
+pub fn excluded_tags_parse(param: &Value) -> Vec<String> {
+    ["exclude_tags", "exclude_categories"]
+        .into_iter()
+        .filter_map(|name| param.get(name).and_then(|value| value.as_str()))
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+// Функция для проверки, что у события нет ни одного из исключенных тегов
+pub fn tags_not_excluded(event: &Event, excluded_tags: &[String]) -> bool {
+    !event.tags.iter().any(|tag| excluded_tags.contains(tag))
+}
+
+// Функция для проверки, есть ли у события тег, если тег не задан - подходит любое событие
+pub fn tag_matches(event: &Event, tag: Option<&str>) -> bool {
+    tag.is_none_or(|tag| event.tags.iter().any(|event_tag| event_tag == tag))
+}
+
+// Функция для извлечения категорий (тегов) через запятую из query-строки, например category=work,home
+// Категории приводятся к нижнему регистру, как и теги событий, пустой список отклоняется
+pub async fn categories_parse(param: &Value) -> Result<Option<Vec<String>>, Response> {
+    let Some(value) = param.get("category").and_then(|value| value.as_str()) else {
+        return Ok(None);
+    };
+    let categories: Vec<String> = value
+        .split(',')
+        .map(|category| category.trim().to_lowercase())
+        .filter(|category| !category.is_empty())
+        .collect();
+    if categories.is_empty() {
+        let res = json!({
+            "error": format!("category must not be empty"),
+        });
+        return Err((StatusCode::BAD_REQUEST, Json(res)).into_response());
+    }
+    Ok(Some(categories))
+}
+
+// Функция для проверки, есть ли у события хотя бы одна из категорий, если категории не заданы - подходит любое событие
+pub fn categories_match(event: &Event, categories: Option<&[String]>) -> bool {
+    categories.is_none_or(|categories| event.tags.iter().any(|tag| categories.contains(tag)))
+}
+
+// Функция для приведения тегов к нижнему регистру без пробелов по краям и удаления повторов
+// Порядок тегов сохраняется, пустой тег считается ошибкой
+fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() {
+            return Err("must not contain empty tags".to_string());
+        }
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    Ok(normalized)
+}
+
+// Функция для извлечения названия календаря из query-строки
+// Если required - отсутствие календаря считается ошибкой, иначе возвращается None
+pub async fn calendar_parse(param: &Value, required: bool) -> Result<Option<String>, Response> {
+    match param.get("calendar").and_then(|value| value.as_str()) {
+        Some(calendar) if !calendar.trim().is_empty() => Ok(Some(calendar.to_string())),
+        Some(_) => {
+            let res = json!({
+                "error": format!("calendar must not be empty"),
+            });
+            Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+        }
+        None if required => {
+            let res = json!({
+                "error": format!("missing field `calendar`"),
+            });
+            Err((StatusCode::BAD_REQUEST, Json(res)).into_response())
+        }
+        None => Ok(None),
+    }
+}
+
+// Функция для сортировки событий по дате, без порядка сортировки события остаются в порядке добавления
+pub fn sort_events(events: &mut [Event], sort: Option<SortOrder>) {
+    match sort {
+        Some(SortOrder::DateAsc) => events.sort_by_key(|event| event.date),
+        Some(SortOrder::DateDesc) => events.sort_by_key(|event| std::cmp::Reverse(event.date)),
+        None => {}
+    }
+}
+
+// Функция для извлечения даты и названия события из json
+// При trim пробелы по краям названия обрезаются до любых проверок
+pub async fn json_body_parse(body: Value, trim: bool) -> Result<Event, Response> {
+    event_req_parse(body, trim).map_err(validation::errors_response)
+}
+
+// Функция для преобразования тела запроса в событие
+// Проверяются все поля, в ошибке - список всех некорректных полей
+pub fn event_req_parse(body: Value, trim: bool) -> Result<Event, Vec<ValidationError>> {
+    let mut validator = Validator::new(&body);
+    let calendar = validator.non_empty_string("calendar", false);
+    // Идентификатор необязателен, если не задан - создается новый
+    let id: Option<Option<Uuid>> = validator.field("id");
+    // Событие на весь день задается датой без времени и хранится на полночь UTC
+    let all_day: Option<Option<bool>> = validator.field("all_day");
+    let all_day = all_day.flatten().unwrap_or(false);
+    let date = if all_day {
+        let value: Option<String> = validator.field("date_time");
+        value.and_then(|value| match NaiveDate::from_str(&value) {
+            Ok(date) => Some(date.and_time(NaiveTime::MIN).and_utc()),
+            Err(_) => {
+                validator.error(
+                    "date_time",
+                    "must be a date YYYY-MM-DD for an all-day event",
+                );
+                None
+            }
+        })
+    } else {
+        validator.date_time("date_time")
+    };
+    // Время окончания необязательно, но если задано - должно быть позже начала
+    let end_date = validator.optional_date_time("end_date_time");
+    if all_day && end_date.is_some() {
+        validator.error("end_date_time", "must not be set for an all-day event");
+    }
+    // Название не может быть пустым или состоять из одних пробелов
+    let name = validator.non_empty_string("event_name", trim);
+    // Правило повторения и дата его окончания необязательны
+    let recurrence: Option<Option<Recurrence>> = validator.field("recurrence");
+    let recurrence_until = validator.optional_date_time("recurrence_until");
+    // Часовой пояс, в котором повторения сохраняют местное время начала, необязателен
+    let tz = event_tz_parse(&mut validator);
+    // Описание и место проведения необязательны, пустая строка означает, что поле не задано
+    let description: Option<Option<String>> = validator.field("description");
+    let location: Option<Option<String>> = validator.field("location");
+    // Теги необязательны
+    let tags: Option<Option<Vec<String>>> = validator.field("tags");
+    let tags = match tags.flatten().map(normalize_tags).transpose() {
+        Ok(tags) => tags.unwrap_or_default(),
+        Err(e) => {
+            validator.error("tags", e);
+            Vec::new()
+        }
+    };
+    if let Some(date) = date {
+        if end_date.is_some_and(|end_date| end_date <= date) {
+            validator.error("end_date_time", "must be after date_time");
+        }
+        if recurrence_until.is_some_and(|until| until < date) {
+            validator.error("recurrence_until", "must not be before date_time");
+        }
+    }
+    let (Some(calendar), Some(date), Some(name)) = (calendar, date, name) else {
+        return Err(validator.into_errors());
+    };
+    validator.finish()?;
+    let mut event = Event::new(calendar, date, name);
+    if let Some(id) = id.flatten() {
+        event.id = id;
+    }
+    event.end_date = end_date;
+    event.recurrence = recurrence.flatten();
+    event.recurrence_until = recurrence_until;
+    event.tz = tz;
+    event.tags = tags;
+    event.all_day = all_day;
+    event.description = optional_text(description.flatten(), trim);
+    event.location = optional_text(location.flatten(), trim);
+    Ok(event)
+}
+
+// Функция для преобразования тела запроса /preview_recurrence в событие с правилом повторения и окно дат [from, to]
+// Название и календарь для предпросмотра не нужны, окно ограничено max_days днями, как диапазон выборок
+pub fn recurrence_preview_parse(
+    body: &Value,
+    max_days: i64,
+) -> Result<(Event, NaiveDate, NaiveDate), Vec<ValidationError>> {
+    let mut validator = Validator::new(body);
+    let date = validator.date_time("date_time");
+    let end_date = validator.optional_date_time("end_date_time");
+    let recurrence: Option<Recurrence> = validator.field("recurrence");
+    let recurrence_until = validator.optional_date_time("recurrence_until");
+    let tz = event_tz_parse(&mut validator);
+    let from = validator.date("from");
+    let to = validator.date("to");
+    if let Some(date) = date {
+        if end_date.is_some_and(|end_date| end_date <= date) {
+            validator.error("end_date_time", "must be after date_time");
+        }
+        if recurrence_until.is_some_and(|until| until < date) {
+            validator.error("recurrence_until", "must not be before date_time");
+        }
+    }
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            validator.error("from", "must not be after to");
+        } else if (to - from).num_days() >= max_days {
+            validator.error("to", format!("range must not exceed {} days", max_days));
+        }
+    }
+    let (Some(date), Some(recurrence), Some(from), Some(to)) = (date, recurrence, from, to) else {
+        return Err(validator.into_errors());
+    };
+    validator.finish()?;
+    let mut event = Event::new(DEFAULT_CALENDAR.to_string(), date, String::new());
+    event.end_date = end_date;
+    event.recurrence = Some(recurrence);
+    event.recurrence_until = recurrence_until;
+    event.tz = tz;
+    Ok((event, from, to))
+}
+
+// Функция для чтения необязательного часового пояса события (IANA, например Europe/Berlin) из тела запроса
+fn event_tz_parse(validator: &mut Validator) -> Option<Tz> {
+    let value: Option<Option<String>> = validator.field("tz");
+    value.flatten().and_then(|value| match value.parse::<Tz>() {
+        Ok(tz) => Some(tz),
+        Err(e) => {
+            validator.error("tz", e.to_string());
+            None
+        }
+    })
+}
+
+// Функция для необязательного текстового поля: при trim пробелы по краям обрезаются, пустое значение не сохраняется
+pub fn optional_text(value: Option<String>, trim: bool) -> Option<String> {
+    value
+        .map(|value| {
+            if trim {
+                value.trim().to_string()
+            } else {
+                value
+            }
+        })
+        .filter(|value| !value.trim().is_empty())
+}
@@ -0,0 +1,1434 @@
+// Обработчики выборок событий: за период, поиск, ближайшие события, свободное время и сводки
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Json, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+
+use crate::{
+    dates::{grid_cells, local_midnight, month_bounds, period_bounds, start_of_week},
+    model::{occurrences, upcoming_occurrences, Event, Period, SortOrder},
+    openapi,
+    params::{
+        calendar_parse, dates_parse, enum_param_parse, excluded_tags_parse, name_contains_parse,
+        name_matches, query_parse, range_parse, recurrence_preview_parse, search_text_parse,
+        sort_events, sort_parse, tag_matches, tag_parse, tags_not_excluded, tz_parse,
+        usize_param_parse,
+    },
+    responses::{
+        filtered_response, ndjson_range_response, ndjson_response, response_format,
+        store_error_response, xml_response, Expand, ResponseFormat,
+    },
+    settings::{MidnightBoundary, OverlapMode},
+    store::EventStore,
+    validation, xml, AppState, DEFAULT_PAGE_LIMIT, DEFAULT_UPCOMING_LIMIT, MAX_PAGE_LIMIT,
+};
+
+// Обработчик, возвращающий все события постранично, отсортированные по дате (порядок - параметр sort или default_sort)
+// Если указан календарь - только события этого календаря, иначе события всех календарей
+#[utoipa::path(
+    get,
+    path = "/events",
+    params(openapi::ListParam),
+    responses(
+        (status = 200, description = "Страница событий, отсортированных по дате, с заголовком Accept: application/x-ndjson - по одному событию в строке, application/xml - в XML", body = openapi::EventsPage),
+        (status = 400, description = "Некорректные параметры", body = openapi::ErrorResponse),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn list_events_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Проверяем на валидность входные данные
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT),
+        Err(e) => {
+            return e;
+        }
+    };
+    let offset = match usize_param_parse(&param, "offset").await {
+        Ok(value) => value.unwrap_or(0),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан ни в запросе, ни в конфигурации - сортируем по возрастанию даты
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort).or(Some(SortOrder::DateAsc)),
+        Err(e) => {
+            return e;
+        }
+    };
+    let mut events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    // Сортируем по дате, чтобы страницы не зависели от порядка добавления
+    sort_events(&mut events, sort);
+    let total = events.len();
+    let page: Vec<Event> = events.into_iter().skip(offset).take(limit).collect();
+    // В NDJSON общее количество событий передается в заголовке x-total-count
+    match format {
+        ResponseFormat::Json => {}
+        ResponseFormat::Ndjson => return ndjson_response(page, Some(total)),
+        ResponseFormat::Xml => return xml_response(xml::write_page(&page, total, limit, offset)),
+    }
+
+    let res = json!({
+        "result": page,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик, возващающий все события дня для указанной даты
+#[utoipa::path(
+    get,
+    path = "/events_for_day",
+    params(openapi::DateParam),
+    responses(
+        (status = 200, description = "События дня, с заголовком Accept: application/x-ndjson - по одному событию в строке, application/xml - в XML", body = openapi::EventsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_for_day_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Проверяем на валидность входные данные
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан в запросе - используем порядок из конфигурации
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Часовой пояс, в котором определяются границы дня, по умолчанию UTC
+    let tz = match tz_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Необязательные подстрока названия и тег, по которым дополнительно фильтруются события
+    let name_contains = name_contains_parse(&param);
+    let tag = tag_parse(&param);
+    let excluded_tags = excluded_tags_parse(&param);
+    let desired_date = match query_parse(param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Выбираем по индексу события нужных дней и оставляем вхождения, день, месяц и год которых соответствуют указанному событию
+    let expand = period_expand(
+        name_contains,
+        tag,
+        excluded_tags,
+        tz,
+        settings.midnight_boundary,
+    );
+    period_response(
+        store,
+        calendar,
+        (desired_date, desired_date),
+        expand,
+        sort,
+        limit,
+        format,
+    )
+    .await
+}
+
+// Обработчик, возвращающий события нескольких дней за один запрос, например для сетки календаря
+// Для каждой даты возвращаются события так же, как в /events_for_day, дата без событий - с пустым списком
+#[utoipa::path(
+    get,
+    path = "/events_for_days",
+    params(openapi::DaysParam),
+    responses(
+        (status = 200, description = "События по датам, с заголовком Accept: application/x-ndjson - по одной дате в строке, application/xml - в XML", body = openapi::EventsByDateResponse),
+        (status = 400, description = "Некорректные параметры либо слишком много дат", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_for_days_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан в запросе - используем порядок из конфигурации
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Часовой пояс, в котором определяются границы дней, по умолчанию UTC
+    let tz = match tz_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Необязательные подстрока названия и тег, по которым дополнительно фильтруются события
+    let name_contains = name_contains_parse(&param);
+    let tag = tag_parse(&param);
+    let excluded_tags = excluded_tags_parse(&param);
+    let dates = match dates_parse(&param, settings.max_dates_per_query).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Повторяющиеся даты в запросе дают одну запись в ответе
+    let mut result: BTreeMap<NaiveDate, Vec<Event>> = BTreeMap::new();
+    for date in dates {
+        if result.contains_key(&date) {
+            continue;
+        }
+        let events = match store.query_range(Some(&calendar), date, date).await {
+            Ok(events) => events,
+            Err(e) => return store_error_response(e),
+        };
+        let mut day_events: Vec<Event> = events
+            .iter()
+            .filter(|event| name_matches(event, name_contains.as_deref()))
+            .filter(|event| tag_matches(event, tag.as_deref()))
+            .filter(|event| tags_not_excluded(event, &excluded_tags))
+            .flat_map(|event| occurrences(event, date, date, tz, settings.midnight_boundary))
+            .collect();
+        sort_events(&mut day_events, sort);
+        result.insert(date, day_events);
+    }
+    match format {
+        ResponseFormat::Json => {}
+        ResponseFormat::Ndjson => {
+            let lines = result
+                .into_iter()
+                .map(|(date, events)| json!({ "date": date, "result": events }))
+                .collect();
+            return ndjson_response::<Value>(lines, None);
+        }
+        ResponseFormat::Xml => return xml_response(xml::write_events_by_date(&result)),
+    }
+    let res = json!({
+        "result": result,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик, возващающий все события недели для указанной даты
+#[utoipa::path(
+    get,
+    path = "/events_for_week",
+    params(openapi::DateParam),
+    responses(
+        (status = 200, description = "События недели, с заголовком Accept: application/x-ndjson - по одному событию в строке, application/xml - в XML", body = openapi::EventsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_for_week_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Проверяем на валидность входные данные
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан в запросе - используем порядок из конфигурации
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Часовой пояс, в котором определяются дни недели, по умолчанию UTC
+    let tz = match tz_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Необязательные подстрока названия и тег, по которым дополнительно фильтруются события
+    let name_contains = name_contains_parse(&param);
+    let tag = tag_parse(&param);
+    let excluded_tags = excluded_tags_parse(&param);
+    let desired_date = match query_parse(param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Выбираем по индексу события нужных дней и оставляем вхождения, начала недели у кооторых совпадают с указанным событием
+    // Неделя - семь календарных дней в часовом поясе запроса, а день события определяется в том же поясе,
+    // поэтому неделя с переходом на летнее или зимнее время (167 или 169 часов) группируется верно
+    let week_start = start_of_week(desired_date, settings.week_start);
+    let week_end = week_start + Duration::days(6);
+    let expand = period_expand(
+        name_contains,
+        tag,
+        excluded_tags,
+        tz,
+        settings.midnight_boundary,
+    );
+    period_response(
+        store,
+        calendar,
+        (week_start, week_end),
+        expand,
+        sort,
+        limit,
+        format,
+    )
+    .await
+}
+
+// Обработчик, возващающий все события месяца для указанной даты
+#[utoipa::path(
+    get,
+    path = "/events_for_month",
+    params(openapi::DateParam),
+    responses(
+        (status = 200, description = "События месяца, с заголовком Accept: application/x-ndjson - по одному событию в строке, application/xml - в XML", body = openapi::EventsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_for_month_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан в запросе - используем порядок из конфигурации
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort),
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Необязательные подстрока названия и тег, по которым дополнительно фильтруются события
+    let name_contains = name_contains_parse(&param);
+    let tag = tag_parse(&param);
+    let excluded_tags = excluded_tags_parse(&param);
+    let desired_date = match query_parse(param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Выбираем по индексу события нужных дней и оставляем вхождения, месяц и год которых соответствуют указанному событию
+    let (month_start, month_end) = month_bounds(desired_date);
+    let expand = period_expand(
+        name_contains,
+        tag,
+        excluded_tags,
+        Tz::UTC,
+        settings.midnight_boundary,
+    );
+    period_response(
+        store,
+        calendar,
+        (month_start, month_end),
+        expand,
+        sort,
+        limit,
+        format,
+    )
+    .await
+}
+
+// Обработчик, возващающий все события года для указанной даты
+#[utoipa::path(
+    get,
+    path = "/events_for_year",
+    params(openapi::DateParam),
+    responses(
+        (status = 200, description = "События года, с заголовком Accept: application/x-ndjson - по одному событию в строке, application/xml - в XML", body = openapi::EventsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_for_year_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан ни в запросе, ни в конфигурации - сортируем по возрастанию даты
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort).or(Some(SortOrder::DateAsc)),
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Необязательные подстрока названия и тег, по которым дополнительно фильтруются события
+    let name_contains = name_contains_parse(&param);
+    let tag = tag_parse(&param);
+    let excluded_tags = excluded_tags_parse(&param);
+    let desired_date = match query_parse(param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Выбираем по индексу события нужных дней и оставляем вхождения, год которых соответствует указанному событию
+    let year_start = desired_date - Duration::days(desired_date.ordinal0() as i64);
+    let year_end = year_start
+        .with_year(year_start.year() + 1)
+        .map(|next| next - Duration::days(1))
+        .unwrap_or(NaiveDate::MAX);
+    let expand = period_expand(
+        name_contains,
+        tag,
+        excluded_tags,
+        Tz::UTC,
+        settings.midnight_boundary,
+    );
+    period_response(
+        store,
+        calendar,
+        (year_start, year_end),
+        expand,
+        sort,
+        limit,
+        format,
+    )
+    .await
+}
+
+// Обработчик, возващающий все события в диапазоне дат [from, to] включительно
+#[utoipa::path(
+    get,
+    path = "/events_in_range",
+    params(openapi::RangeParam),
+    responses(
+        (status = 200, description = "События диапазона дат, с заголовком Accept: application/xml - в XML", body = openapi::EventsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_in_range_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан ни в запросе, ни в конфигурации - сортируем по возрастанию даты
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort).or(Some(SortOrder::DateAsc)),
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    // Необязательные исключаемые теги
+    let excluded_tags = excluded_tags_parse(&param);
+    let (from, to) = match range_parse(param, settings.max_range_days).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Выбираем по индексу события нужных дней и оставляем вхождения, попадающие в диапазон
+    let expand = period_expand(
+        None,
+        None,
+        excluded_tags,
+        Tz::UTC,
+        settings.midnight_boundary,
+    );
+    period_response(store, calendar, (from, to), expand, sort, limit, format).await
+}
+
+// Функция, объединяющая фильтры запроса по названию и тегу с вычислением вхождений события в окне дат
+// Сначала применяются фильтры, оставляющие события, затем из оставшихся убираются события с исключенными тегами
+fn period_expand(
+    name_contains: Option<String>,
+    tag: Option<String>,
+    excluded_tags: Vec<String>,
+    tz: Tz,
+    boundary: MidnightBoundary,
+) -> Expand {
+    Arc::new(move |event, from, to| {
+        if !name_matches(event, name_contains.as_deref()) || !tag_matches(event, tag.as_deref()) {
+            return Vec::new();
+        }
+        if !tags_not_excluded(event, &excluded_tags) {
+            return Vec::new();
+        }
+        occurrences(event, from, to, tz, boundary)
+    })
+}
+
+// Функция для выборки вхождений событий календаря в диапазоне дат [from, to] и формирования ответа
+// С заголовком Accept: application/x-ndjson события читаются из хранилища по дням и отдаются потоком,
+// в остальных форматах вхождения собираются в список
+async fn period_response(
+    store: Arc<dyn EventStore>,
+    calendar: String,
+    (from, to): (NaiveDate, NaiveDate),
+    expand: Expand,
+    sort: Option<SortOrder>,
+    limit: Option<usize>,
+    format: ResponseFormat,
+) -> Response {
+    if let ResponseFormat::Ndjson = format {
+        return ndjson_range_response(store, calendar, (from, to), sort, limit, expand).await;
+    }
+    let events = match store.query_range(Some(&calendar), from, to).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    let filtered_dates: Vec<Event> = events
+        .iter()
+        .flat_map(|event| expand(event, from, to))
+        .collect();
+    filtered_response(filtered_dates, sort, limit, format)
+}
+
+// Обработчик поиска событий по части названия без учета регистра, события сортируются по дате (параметр sort или default_sort)
+// Если указан календарь - поиск только в нем, если указан диапазон from/to - среди вхождений в этом диапазоне
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(openapi::SearchParam),
+    responses(
+        (status = 200, description = "События, название которых содержит строку поиска", body = openapi::EventsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ErrorResponse),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn search_events_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let query = match search_text_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан ни в запросе, ни в конфигурации - сортируем по возрастанию даты
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort).or(Some(SortOrder::DateAsc)),
+        Err(e) => {
+            return e;
+        }
+    };
+    let range = if param.get("from").is_some() || param.get("to").is_some() {
+        match range_parse(param, settings.max_range_days).await {
+            Ok(value) => Some(value),
+            Err(e) => {
+                return e;
+            }
+        }
+    } else {
+        None
+    };
+    let events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    let found: Vec<Event> = events
+        .iter()
+        .filter(|event| event.name.to_lowercase().contains(&query))
+        .flat_map(|event| match range {
+            Some((from, to)) => occurrences(event, from, to, Tz::UTC, settings.midnight_boundary),
+            None => vec![event.clone()],
+        })
+        .collect();
+
+    filtered_response(found, sort, limit, format)
+}
+
+// Обработчик предпросмотра повторений: даты вхождений правила повторения в окне [from, to], ничего не сохраняется
+// Вхождения вычисляются так же, как в выборках, поэтому и окно ограничено max_range_days днями
+#[utoipa::path(
+    post,
+    path = "/preview_recurrence",
+    request_body = openapi::RecurrencePreviewReq,
+    responses(
+        (status = 200, description = "Даты вхождений в окне", body = openapi::RecurrencePreviewResponse),
+        (status = 400, description = "Некорректное тело запроса", body = openapi::ValidationErrors),
+    )
+)]
+pub async fn preview_recurrence_handler(
+    State(AppState { settings, .. }): State<AppState>,
+    Json(body): Json<Value>,
+) -> Response {
+    let (event, from, to) = match recurrence_preview_parse(&body, settings.max_range_days) {
+        Ok(value) => value,
+        Err(errors) => {
+            return validation::errors_response(errors);
+        }
+    };
+    let dates: Vec<DateTime<Utc>> =
+        occurrences(&event, from, to, Tz::UTC, settings.midnight_boundary)
+            .into_iter()
+            .map(|occurrence| occurrence.date)
+            .collect();
+    let res = json!({
+        "count": dates.len(),
+        "result": dates,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик поиска событий по части места проведения без учета регистра, события без места не находятся
+// Если указан календарь - поиск только в нем, если указан диапазон from/to - среди вхождений в этом диапазоне
+#[utoipa::path(
+    get,
+    path = "/events/by_location",
+    params(openapi::SearchParam),
+    responses(
+        (status = 200, description = "События, место проведения которых содержит строку поиска", body = openapi::EventsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ErrorResponse),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_by_location_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let query = match search_text_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан ни в запросе, ни в конфигурации - сортируем по возрастанию даты
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort).or(Some(SortOrder::DateAsc)),
+        Err(e) => {
+            return e;
+        }
+    };
+    let range = if param.get("from").is_some() || param.get("to").is_some() {
+        match range_parse(param, settings.max_range_days).await {
+            Ok(value) => Some(value),
+            Err(e) => {
+                return e;
+            }
+        }
+    } else {
+        None
+    };
+    let events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    let found: Vec<Event> = events
+        .iter()
+        .filter(|event| {
+            event
+                .location
+                .as_ref()
+                .is_some_and(|location| location.to_lowercase().contains(&query))
+        })
+        .flat_map(|event| match range {
+            Some((from, to)) => occurrences(event, from, to, Tz::UTC, settings.midnight_boundary),
+            None => vec![event.clone()],
+        })
+        .collect();
+
+    filtered_response(found, sort, limit, format)
+}
+
+// Обработчик поиска событий по длительности в минутах: min <= длительность <= max, обе границы необязательны
+// Событие без времени окончания имеет нулевую длительность, у повторений длительность та же, что у исходного события
+// Если указан календарь - поиск только в нем, если указан диапазон from/to - среди вхождений в этом диапазоне
+#[utoipa::path(
+    get,
+    path = "/events/by_duration",
+    params(openapi::DurationParam),
+    responses(
+        (status = 200, description = "События, длительность которых попадает в диапазон", body = openapi::EventsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ErrorResponse),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+        (status = 406, description = "Неподдерживаемый формат в Accept", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn events_by_duration_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+    headers: HeaderMap,
+) -> Response {
+    // Формат ответа по заголовку Accept
+    let format = match response_format(&headers).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let min = match usize_param_parse(&param, "min").await {
+        Ok(value) => value.map(|min| Duration::minutes(min as i64)),
+        Err(e) => {
+            return e;
+        }
+    };
+    let max = match usize_param_parse(&param, "max").await {
+        Ok(value) => value.map(|max| Duration::minutes(max as i64)),
+        Err(e) => {
+            return e;
+        }
+    };
+    if let (Some(min), Some(max)) = (min, max) {
+        if min > max {
+            let res = json!({
+                "error": format!("min must not be greater than max"),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+    }
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если порядок сортировки не указан ни в запросе, ни в конфигурации - сортируем по возрастанию даты
+    let sort = match sort_parse(&param).await {
+        Ok(value) => value.or(settings.default_sort).or(Some(SortOrder::DateAsc)),
+        Err(e) => {
+            return e;
+        }
+    };
+    let range = if param.get("from").is_some() || param.get("to").is_some() {
+        match range_parse(param, settings.max_range_days).await {
+            Ok(value) => Some(value),
+            Err(e) => {
+                return e;
+            }
+        }
+    } else {
+        None
+    };
+    let events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    let found: Vec<Event> = events
+        .iter()
+        .filter(|event| {
+            let duration = event
+                .end_date
+                .map_or(Duration::zero(), |end| end - event.date);
+            min.is_none_or(|min| duration >= min) && max.is_none_or(|max| duration <= max)
+        })
+        .flat_map(|event| match range {
+            Some((from, to)) => occurrences(event, from, to, Tz::UTC, settings.midnight_boundary),
+            None => vec![event.clone()],
+        })
+        .collect();
+
+    filtered_response(found, sort, limit, format)
+}
+
+// Обработчик, возвращающий ближайшие события, начинающиеся не раньше текущего момента
+// Сравниваются моменты времени, часовой пояс tz влияет только на то, в каком поясе возвращается текущее время
+// Для повторяющегося события возвращаются его ближайшие повторения
+#[utoipa::path(
+    get,
+    path = "/events/upcoming",
+    params(openapi::UpcomingParam),
+    responses(
+        (status = 200, description = "Ближайшие события по возрастанию даты", body = openapi::UpcomingResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ErrorResponse),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn upcoming_events_handler(
+    State(AppState { store, .. }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    // Проверяем на валидность входные данные
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value.unwrap_or(DEFAULT_UPCOMING_LIMIT).min(MAX_PAGE_LIMIT),
+        Err(e) => {
+            return e;
+        }
+    };
+    let tz = match tz_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let now = Utc::now();
+    let events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    let mut upcoming: Vec<Event> = events
+        .iter()
+        .flat_map(|event| upcoming_occurrences(event, now, limit))
+        .collect();
+    upcoming.sort_by_key(|event| event.date);
+    upcoming.truncate(limit);
+
+    let res = json!({
+        "now": now.with_timezone(&tz).to_rfc3339(),
+        "result": upcoming,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик, возвращающий свободные промежутки рабочего дня длиной не меньше duration_minutes
+// Рабочие часы берутся из конфигурации и отсчитываются в часовом поясе tz (по умолчанию UTC)
+// Событие без времени окончания занимает нулевой промежуток, но делит свободное время на части
+#[utoipa::path(
+    get,
+    path = "/free_slots",
+    params(openapi::FreeSlotsParam),
+    responses(
+        (status = 200, description = "Свободные промежутки в рабочие часы", body = openapi::FreeSlotsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn free_slots_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    let duration = match usize_param_parse(&param, "duration_minutes").await {
+        Ok(Some(value)) if value > 0 => Duration::minutes(value as i64),
+        Ok(Some(_)) => {
+            let res = json!({
+                "error": format!("duration_minutes must be positive"),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+        Ok(None) => {
+            let res = json!({
+                "error": format!("missing field `duration_minutes`"),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+        Err(e) => {
+            return e;
+        }
+    };
+    let tz = match tz_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    let desired_date = match query_parse(param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Границы рабочего дня в UTC
+    let local_time = |time: NaiveTime| {
+        let date_time = desired_date.and_time(time);
+        tz.from_local_datetime(&date_time)
+            .earliest()
+            .unwrap_or_else(|| tz.from_utc_datetime(&date_time))
+            .with_timezone(&Utc)
+    };
+    let day_start = local_time(settings.business_start);
+    let day_end = local_time(settings.business_end);
+    let events = match store
+        .query_range(Some(&calendar), desired_date, desired_date)
+        .await
+    {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    // Занятые промежутки, пересекающиеся с рабочим днем, по времени начала
+    // События на весь день (дни рождения, праздники) время не занимают
+    let mut busy: Vec<(DateTime<Utc>, DateTime<Utc>)> = events
+        .iter()
+        .filter(|event| !event.all_day)
+        .flat_map(|event| {
+            occurrences(
+                event,
+                desired_date,
+                desired_date,
+                tz,
+                settings.midnight_boundary,
+            )
+        })
+        .map(|event| (event.date, event.end_date.unwrap_or(event.date)))
+        .filter(|(start, end)| *start <= day_end && *end >= day_start)
+        .collect();
+    busy.sort();
+    let mut slots = Vec::new();
+    let mut cursor = day_start;
+    for (start, end) in busy {
+        let start = start.max(day_start);
+        if start - cursor >= duration {
+            slots.push((cursor, start));
+        }
+        cursor = cursor.max(end);
+    }
+    if day_end - cursor >= duration {
+        slots.push((cursor, day_end));
+    }
+    let slots: Vec<Value> = slots
+        .into_iter()
+        .map(|(from, to)| {
+            json!({
+                "from": from.with_timezone(&tz).to_rfc3339(),
+                "to": to.with_timezone(&tz).to_rfc3339(),
+            })
+        })
+        .collect();
+
+    let res = json!({
+        "result": slots,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик, возвращающий общее время событий с временем окончания за день, неделю, месяц или год, содержащие дату
+// Интервалы событий обрезаются границами периода в часовом поясе tz (по умолчанию UTC)
+// Событие без окончания и событие на весь день время не занимают
+// Пересекающиеся интервалы считаются один раз (overlaps=merge) либо складываются (overlaps=sum),
+// если параметр не указан - как задано в busy_time_overlaps в конфигурации
+#[utoipa::path(
+    get,
+    path = "/busy_time",
+    params(openapi::BusyTimeParam),
+    responses(
+        (status = 200, description = "Общее занятое время за период", body = openapi::BusyTimeResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn busy_time_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    let period = match enum_param_parse::<Period>(&param, "period").await {
+        Ok(Some(value)) => value,
+        Ok(None) => {
+            let res = json!({
+                "error": format!("missing field `period`"),
+            });
+            return (StatusCode::BAD_REQUEST, Json(res)).into_response();
+        }
+        Err(e) => {
+            return e;
+        }
+    };
+    let overlaps = match enum_param_parse(&param, "overlaps").await {
+        Ok(value) => value.unwrap_or(settings.busy_time_overlaps),
+        Err(e) => {
+            return e;
+        }
+    };
+    let tz = match tz_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    let desired_date = match query_parse(param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let (first_day, last_day) = period_bounds(period, desired_date, settings.week_start);
+    let from = local_midnight(first_day, tz);
+    let to = local_midnight(last_day + Duration::days(1), tz);
+    let events = match store
+        .query_range(Some(&calendar), first_day, last_day)
+        .await
+    {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    // Интервалы вхождений, обрезанные границами периода
+    let mut intervals: Vec<(DateTime<Utc>, DateTime<Utc>)> = events
+        .iter()
+        .filter(|event| !event.all_day)
+        .flat_map(|event| occurrences(event, first_day, last_day, tz, settings.midnight_boundary))
+        .filter_map(|event| {
+            let start = event.date.max(from);
+            let end = event.end_date?.min(to);
+            (start < end).then_some((start, end))
+        })
+        .collect();
+    let busy = match overlaps {
+        OverlapMode::Sum => intervals
+            .iter()
+            .fold(Duration::zero(), |total, (start, end)| {
+                total + (*end - *start)
+            }),
+        // Идем по интервалам в порядке начала и не считаем второй раз время до уже учтенного конца
+        OverlapMode::Merge => {
+            intervals.sort();
+            let mut total = Duration::zero();
+            let mut counted_until = from;
+            for (start, end) in &intervals {
+                let start = (*start).max(counted_until);
+                if *end > start {
+                    total += *end - start;
+                    counted_until = *end;
+                }
+            }
+            total
+        }
+    };
+
+    let res = json!({
+        "period": period,
+        "from": first_day,
+        "to": last_day,
+        "overlaps": overlaps,
+        "events": intervals.len(),
+        "busy_minutes": busy.num_minutes(),
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик, возвращающий события недели для указанной даты в виде сетки "день x час"
+// Событие попадает во все ячейки дней и часов, которые оно занимает в пределах недели, пустые ячейки не выводятся
+#[utoipa::path(
+    get,
+    path = "/week_grid",
+    params(openapi::WeekGridParam),
+    responses(
+        (status = 200, description = "События недели по дням и часам", body = openapi::WeekGridResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ValidationErrors),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn week_grid_handler(
+    State(AppState {
+        store, settings, ..
+    }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    // Проверяем на валидность входные данные
+    // Часовой пояс, в котором определяются дни и часы сетки, по умолчанию UTC
+    let tz = match tz_parse(&param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let calendar = match calendar_parse(&param, true).await {
+        Ok(value) => value.unwrap_or_default(),
+        Err(e) => {
+            return e;
+        }
+    };
+    let desired_date = match query_parse(param).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let week_start = start_of_week(desired_date, settings.week_start);
+    // Для каждого дня недели - события, сгруппированные по часам, которые они занимают
+    let week_end = week_start + Duration::days(6);
+    let mut grid: Vec<BTreeMap<u32, Vec<Event>>> = vec![BTreeMap::new(); 7];
+    let events = match store
+        .query_range(Some(&calendar), week_start, week_end)
+        .await
+    {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    for event in events
+        .iter()
+        .flat_map(|event| occurrences(event, week_start, week_end, tz, MidnightBoundary::Start))
+    {
+        // День события на весь день - его дата в UTC, как и в остальных выборках
+        let event_tz = if event.all_day { Tz::UTC } else { tz };
+        for (day, hour) in grid_cells(&event, week_start, event_tz) {
+            grid[day].entry(hour).or_default().push(event.clone());
+        }
+    }
+    let days: Vec<Value> = grid
+        .into_iter()
+        .enumerate()
+        .map(|(i, hours)| {
+            json!({
+                "date": week_start + Duration::days(i as i64),
+                "hours": hours,
+            })
+        })
+        .collect();
+
+    let res = json!({
+        "week_start": week_start,
+        "result": days,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик, возвращающий названия существующих календарей
+#[utoipa::path(
+    get,
+    path = "/calendars",
+    responses(
+        (status = 200, description = "Названия существующих календарей", body = openapi::CalendarsResponse),
+    )
+)]
+pub async fn calendars_handler(State(AppState { store, .. }): State<AppState>) -> Response {
+    match store.calendars().await {
+        Ok(names) => {
+            let res = json!({
+                "result": names,
+            });
+            (StatusCode::OK, Json(res)).into_response()
+        }
+        Err(e) => store_error_response(e),
+    }
+}
+
+// Обработчик, возвращающий количество событий по месяцам в формате "год-месяц", по возрастанию
+// Месяц определяется по дате начала события в UTC, повторяющееся событие учитывается один раз, месяцы без событий не выводятся
+#[utoipa::path(
+    get,
+    path = "/stats",
+    params(openapi::StatsParam),
+    responses(
+        (status = 200, description = "Количество событий по месяцам", body = openapi::StatsResponse),
+        (status = 400, description = "Некорректные параметры", body = openapi::ErrorResponse),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn stats_handler(
+    State(AppState { store, .. }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    // Если год указан - учитываются только его месяцы
+    let year = match usize_param_parse(&param, "year").await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    // Ключи вида 2024-01 в BTreeMap упорядочены по времени
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for event in events
+        .iter()
+        .filter(|event| year.is_none_or(|year| i64::from(event.date.year()) == year as i64))
+    {
+        *counts
+            .entry(event.date.format("%Y-%m").to_string())
+            .or_default() += 1;
+    }
+    let res = json!({
+        "result": counts,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик, возвращающий журнал изменений постранично, от старых записей к новым
+#[utoipa::path(
+    get,
+    path = "/audit",
+    params(openapi::AuditParam),
+    responses(
+        (status = 200, description = "Страница журнала изменений", body = openapi::AuditPage),
+        (status = 400, description = "Некорректные параметры", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn audit_handler(
+    State(AppState { audit, .. }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    let limit = match usize_param_parse(&param, "limit").await {
+        Ok(value) => value.unwrap_or(DEFAULT_PAGE_LIMIT).min(MAX_PAGE_LIMIT),
+        Err(e) => {
+            return e;
+        }
+    };
+    let offset = match usize_param_parse(&param, "offset").await {
+        Ok(value) => value.unwrap_or(0),
+        Err(e) => {
+            return e;
+        }
+    };
+    let (page, total) = audit.page(offset, limit).await;
+    let res = json!({
+        "result": page,
+        "total": total,
+        "limit": limit,
+        "offset": offset,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Обработчик, возвращающий все теги событий с количеством событий для каждого, по алфавиту
+// Если указан календарь - только теги событий этого календаря
+#[utoipa::path(
+    get,
+    path = "/tags",
+    params(openapi::TagsParam),
+    responses(
+        (status = 200, description = "Теги и количество событий с каждым тегом", body = openapi::TagsResponse),
+        (status = 404, description = "Календарь не найден", body = openapi::ErrorResponse),
+    )
+)]
+pub async fn tags_handler(
+    State(AppState { store, .. }): State<AppState>,
+    Query(param): Query<Value>,
+) -> Response {
+    let calendar = match calendar_parse(&param, false).await {
+        Ok(value) => value,
+        Err(e) => {
+            return e;
+        }
+    };
+    let events = match store.list(calendar.as_deref()).await {
+        Ok(events) => events,
+        Err(e) => return store_error_response(e),
+    };
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for tag in events.iter().flat_map(|event| event.tags.iter()) {
+        *counts.entry(tag).or_default() += 1;
+    }
+    let tags: Vec<Value> = counts
+        .into_iter()
+        .map(|(tag, count)| json!({ "tag": tag, "count": count }))
+        .collect();
+    let res = json!({
+        "result": tags,
+    });
+    (StatusCode::OK, Json(res)).into_response()
+}
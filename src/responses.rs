@@ -0,0 +1,485 @@
+// Формирование ответов: ошибки хранилища, формат ответа по Accept, XML и NDJSON
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+use axum::{
+    body::StreamBody,
+    extract::Json,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::{stream, StreamExt};
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    model::{Event, SortOrder},
+    params::sort_events,
+    store::{EventStore, StoreError},
+    xml,
+};
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+// Функция для вычисления ETag выгрузки: выгрузка меняется только вместе с составом, версиями или датами вхождений событий
+// По ETag клиент в If-Range проверяет, что докачивает ту же выгрузку, которую начал загружать
+pub fn export_etag(events: &[Event]) -> String {
+    let mut hasher = DefaultHasher::new();
+    for event in events {
+        (event.id, event.version, event.date).hash(&mut hasher);
+    }
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+// Функция для получения запрошенного диапазона байтов из заголовка Range
+// Диапазон не учитывается, если выгрузка изменилась с тех пор, как клиент получил ETag из If-Range
+pub fn range_header<'a>(headers: &'a HeaderMap, etag: &str) -> Option<&'a str> {
+    let range = headers.get(header::RANGE)?.to_str().ok()?;
+    match headers.get(header::IF_RANGE) {
+        Some(if_range) if if_range.to_str().ok()? != etag => None,
+        _ => Some(range),
+    }
+}
+
+// Функция для формирования ответа с частью выгрузки по заголовку Range: один диапазон bytes=начало-конец,
+// bytes=начало- или bytes=-длина_с_конца
+// Несколько диапазонов или непонятный заголовок игнорируются - возвращается вся выгрузка,
+// диапазон за концом выгрузки - 416
+pub fn partial_response(
+    range: &str,
+    content_type: &'static str,
+    etag: String,
+    body: Vec<u8>,
+) -> Response {
+    let len = body.len() as u64;
+    let bounds = range
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.trim().split_once('-'))
+        .and_then(|(start, end)| match (start, end) {
+            ("", suffix) => suffix
+                .parse::<u64>()
+                .ok()
+                .map(|suffix| (len.saturating_sub(suffix), len.saturating_sub(1))),
+            (start, "") => start
+                .parse::<u64>()
+                .ok()
+                .map(|start| (start, len.saturating_sub(1))),
+            (start, end) => match (start.parse::<u64>(), end.parse::<u64>()) {
+                (Ok(start), Ok(end)) if start <= end => {
+                    Some((start, end.min(len.saturating_sub(1))))
+                }
+                _ => None,
+            },
+        });
+    let Some((start, end)) = bounds else {
+        return export_response(content_type, etag, body);
+    };
+    if start >= len {
+        return (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [
+                (header::CONTENT_RANGE, format!("bytes */{}", len)),
+                (header::ACCEPT_RANGES, "bytes".to_string()),
+            ],
+        )
+            .into_response();
+    }
+    let part = body[start as usize..=end as usize].to_vec();
+    (
+        StatusCode::PARTIAL_CONTENT,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, len),
+            ),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::ETAG, etag),
+        ],
+        part,
+    )
+        .into_response()
+}
+
+// Функция для формирования ответа со всей выгрузкой, клиенту сообщается, что можно запрашивать ее части
+pub fn export_response(
+    content_type: &'static str,
+    etag: String,
+    body: impl IntoResponse,
+) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+            (header::ETAG, etag),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+// Функция для формирования ответа о неизвестном календаре
+fn calendar_not_found(calendar: &str) -> Response {
+    let res = json!({
+        "error": format!("calendar '{}' not found", calendar),
+    });
+    (StatusCode::NOT_FOUND, Json(res)).into_response()
+}
+
+// Функция для формирования ответа об ошибке хранилища
+pub fn store_error_response(e: StoreError) -> Response {
+    let status = match &e {
+        StoreError::Exists => StatusCode::CONFLICT,
+        StoreError::NotFound => StatusCode::NOT_FOUND,
+        StoreError::CalendarNotFound(calendar) => return calendar_not_found(calendar),
+        // Клиенту сообщаем текущую версию, чтобы он мог перечитать событие и повторить изменение
+        StoreError::VersionMismatch(version) => {
+            let res = json!({
+                "error": store_error_message(&e),
+                "version": version,
+            });
+            return (StatusCode::CONFLICT, Json(res)).into_response();
+        }
+        StoreError::LimitReached => StatusCode::TOO_MANY_REQUESTS,
+        StoreError::Unavailable(reason) => {
+            tracing::error!(%reason, "event store is unavailable");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    let res = json!({
+        "error": store_error_message(&e),
+    });
+    (status, Json(res)).into_response()
+}
+
+// Функция для формирования текста ошибки хранилища для клиента
+pub fn store_error_message(e: &StoreError) -> String {
+    match e {
+        StoreError::Exists => "event with this id already exists".to_string(),
+        StoreError::NotFound => "event not found".to_string(),
+        StoreError::CalendarNotFound(calendar) => format!("calendar '{}' not found", calendar),
+        StoreError::VersionMismatch(_) => "version mismatch".to_string(),
+        StoreError::LimitReached => "event limit reached".to_string(),
+        StoreError::Unavailable(reason) => reason.clone(),
+    }
+}
+
+// Функция для формирования ответа со списком событий с учетом сортировки и ограничения количества
+// Если список был обрезан - добавляем флаг truncated и общее количество найденных событий
+pub fn filtered_response(
+    mut events: Vec<Event>,
+    sort: Option<SortOrder>,
+    limit: Option<usize>,
+    format: ResponseFormat,
+) -> Response {
+    sort_events(&mut events, sort);
+    let total = events.len();
+    let truncated = matches!(limit, Some(limit) if total > limit);
+    if let Some(limit) = limit {
+        events.truncate(limit);
+    }
+    match format {
+        ResponseFormat::Json => {}
+        ResponseFormat::Ndjson => return ndjson_response(events, truncated.then_some(total)),
+        ResponseFormat::Xml => {
+            return xml_response(xml::write_events(&events, truncated.then_some(total)))
+        }
+    }
+    let res = if truncated {
+        json!({
+            "result": events,
+            "truncated": true,
+            "total": total,
+        })
+    } else {
+        json!({
+            "result": events,
+        })
+    };
+    (StatusCode::OK, Json(res)).into_response()
+}
+
+// Функция для выбора формата ответа со списком событий по заголовку Accept
+// Из перечисленных клиентом типов выбирается поддерживаемый с наибольшим весом q, при равных весах - первый
+// Без заголовка, а также для */* и application/* ответ в JSON; если ни один тип не поддерживается - 406
+pub async fn response_format(headers: &HeaderMap) -> Result<ResponseFormat, Response> {
+    let Some(accept) = headers.get(header::ACCEPT) else {
+        return Ok(ResponseFormat::Json);
+    };
+    let accept = accept.to_str().unwrap_or_default();
+    let mut best: Option<(f32, ResponseFormat)> = None;
+    for range in accept.split(',') {
+        let mut parts = range.split(';').map(|part| part.trim());
+        let media_type = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let weight = parts
+            .filter_map(|part| part.strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let format = match media_type.as_str() {
+            "application/json" | "application/*" | "*/*" => ResponseFormat::Json,
+            NDJSON_CONTENT_TYPE => ResponseFormat::Ndjson,
+            xml::XML_CONTENT_TYPE | "text/xml" => ResponseFormat::Xml,
+            _ => continue,
+        };
+        // Тип с весом 0 клиент явно не принимает
+        if weight > 0.0 && best.is_none_or(|(best_weight, _)| weight > best_weight) {
+            best = Some((weight, format));
+        }
+    }
+    match best {
+        Some((_, format)) => Ok(format),
+        None => {
+            let res = json!({
+                "error": format!(
+                    "unsupported Accept '{}', expected application/json, {} or {}",
+                    accept,
+                    NDJSON_CONTENT_TYPE,
+                    xml::XML_CONTENT_TYPE
+                ),
+            });
+            Err((StatusCode::NOT_ACCEPTABLE, Json(res)).into_response())
+        }
+    }
+}
+
+// Функция для отдачи ответа в формате XML с теми же полями, что и в JSON
+pub fn xml_response(body: Result<String, quick_xml::SeError>) -> Response {
+    match body {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, xml::XML_CONTENT_TYPE)],
+            body,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to serialize response to xml");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+// Функция для отдачи уже собранного списка событий (или других записей) в формате NDJSON: по одной записи в строке
+// Записи сериализуются по мере отправки, поэтому JSON всего ответа в памяти не собирается
+// Если список был обрезан - общее количество событий передается в заголовке x-total-count
+pub fn ndjson_response<T: Serialize + Send + 'static>(
+    events: Vec<T>,
+    total: Option<usize>,
+) -> Response {
+    let stream = stream::iter(events).map(|event| {
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+        Ok::<_, serde_json::Error>(line)
+    });
+    let mut res = (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        StreamBody::new(stream),
+    )
+        .into_response();
+    if let Some(total) = total {
+        res.headers_mut()
+            .insert("x-total-count", HeaderValue::from(total));
+    }
+    res
+}
+
+// Функция, возвращающая вхождения события в окне дат [from, to] с учетом фильтров запроса
+pub type Expand = Arc<dyn Fn(&Event, NaiveDate, NaiveDate) -> Vec<Event> + Send + Sync>;
+
+// Функция для потоковой отдачи в формате NDJSON вхождений событий календаря в диапазоне дат [from, to]
+// События читаются из хранилища окнами по одному дню, поэтому в памяти держатся вхождения не больше двух дней,
+// а не вся выборка; вхождение, попадающее в несколько дней, отдается один раз - в первом из них
+// Вхождения идут по дате в порядке sort (без sort - по возрастанию), при равных датах - в порядке добавления
+// Если указан limit - общее количество вхождений для x-total-count считается отдельным проходом по тем же окнам
+pub async fn ndjson_range_response(
+    store: Arc<dyn EventStore>,
+    calendar: String,
+    (from, to): (NaiveDate, NaiveDate),
+    sort: Option<SortOrder>,
+    limit: Option<usize>,
+    expand: Expand,
+) -> Response {
+    let sort = sort.unwrap_or(SortOrder::DateAsc);
+    let windows = || {
+        DayWindows::new(
+            store.clone(),
+            calendar.clone(),
+            from,
+            to,
+            sort,
+            expand.clone(),
+        )
+    };
+    let mut total = None;
+    if let Some(limit) = limit {
+        let mut count = 0;
+        let mut counting = windows();
+        while let Some(found) = counting.next().await {
+            match found {
+                Ok(found) => count += found.len(),
+                Err(e) => return store_error_response(e),
+            }
+        }
+        total = (count > limit).then_some(count);
+    }
+    // Первое окно читаем до начала ответа, чтобы ошибку (например, неизвестный календарь) вернуть с нужным статусом
+    let mut windows = windows();
+    let first = match windows.next().await {
+        Some(Ok(found)) => found,
+        Some(Err(e)) => return store_error_response(e),
+        None => Vec::new(),
+    };
+    let rest = stream::unfold(windows, |mut windows| async move {
+        windows.next().await.map(|found| (found, windows))
+    });
+    let events = stream::iter([Ok(first)])
+        .chain(rest)
+        .flat_map(|found| match found {
+            Ok(found) => stream::iter(found.into_iter().map(Ok).collect::<Vec<_>>()),
+            Err(e) => stream::iter(vec![Err(e)]),
+        })
+        .take(limit.unwrap_or(usize::MAX));
+    let stream = events.map(|event| {
+        // Ответ уже начат, поэтому при ошибке хранилища соединение просто обрывается
+        let event = event.map_err(|e| {
+            let message = store_error_message(&e);
+            tracing::error!(error = %message, "failed to read events while streaming");
+            std::io::Error::other(message)
+        })?;
+        let mut line = serde_json::to_vec(&event)?;
+        line.push(b'\n');
+        Ok::<_, std::io::Error>(line)
+    });
+    let mut res = (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        StreamBody::new(stream),
+    )
+        .into_response();
+    if let Some(total) = total {
+        res.headers_mut()
+            .insert("x-total-count", HeaderValue::from(total));
+    }
+    res
+}
+
+// Обход диапазона дат по одному дню для потоковой отдачи
+// Вхождение отдается в первом (самом раннем) дне, в котором оно есть, поэтому для каждого дня
+// достаточно сравнить его вхождения с вхождениями предыдущего дня: длинное событие занимает дни подряд
+struct DayWindows {
+    store: Arc<dyn EventStore>,
+    calendar: String,
+    from: NaiveDate,
+    to: NaiveDate,
+    sort: SortOrder,
+    expand: Expand,
+    // Следующий день обхода, None - обход закончен
+    day: Option<NaiveDate>,
+    // По возрастанию - вхождения предыдущего дня, по убыванию - уже прочитанные вхождения следующего дня обхода
+    neighbour: Vec<Event>,
+}
+
+impl DayWindows {
+    fn new(
+        store: Arc<dyn EventStore>,
+        calendar: String,
+        from: NaiveDate,
+        to: NaiveDate,
+        sort: SortOrder,
+        expand: Expand,
+    ) -> Self {
+        let day = match sort {
+            SortOrder::DateAsc => from,
+            SortOrder::DateDesc => to,
+        };
+        DayWindows {
+            store,
+            calendar,
+            from,
+            to,
+            sort,
+            expand,
+            day: (from <= to).then_some(day),
+            neighbour: Vec::new(),
+        }
+    }
+
+    // Вхождения одного дня
+    async fn window(&self, day: NaiveDate) -> Result<Vec<Event>, StoreError> {
+        let events = self
+            .store
+            .query_range(Some(&self.calendar), day, day)
+            .await?;
+        Ok(events
+            .iter()
+            .flat_map(|event| (self.expand)(event, day, day))
+            .collect())
+    }
+
+    // Вхождения следующего дня обхода, которых нет в предыдущем дне, в порядке сортировки
+    async fn next(&mut self) -> Option<Result<Vec<Event>, StoreError>> {
+        let day = self.day?;
+        let found = match self.sort {
+            SortOrder::DateAsc => self.window(day).await.map(|current| {
+                let earlier = std::mem::replace(&mut self.neighbour, current.clone());
+                self.day = day.succ_opt().filter(|next| *next <= self.to);
+                without(current, &earlier)
+            }),
+            SortOrder::DateDesc => {
+                let current = if day == self.to {
+                    self.window(day).await
+                } else {
+                    Ok(std::mem::take(&mut self.neighbour))
+                };
+                let earlier = match day.pred_opt().filter(|previous| *previous >= self.from) {
+                    Some(previous) => self.window(previous).await,
+                    None => Ok(Vec::new()),
+                };
+                current.and_then(|current| {
+                    let earlier = earlier?;
+                    let found = without(current, &earlier);
+                    self.neighbour = earlier;
+                    self.day = day.pred_opt().filter(|previous| *previous >= self.from);
+                    Ok(found)
+                })
+            }
+        };
+        match found {
+            Ok(mut found) => {
+                sort_events(&mut found, Some(self.sort));
+                Some(Ok(found))
+            }
+            // После ошибки обход не продолжается
+            Err(e) => {
+                self.day = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Функция, убирающая из вхождений дня те, что уже есть во вхождениях соседнего дня
+// Вхождение определяется идентификатором события и датой начала: у повторений один id
+fn without(current: Vec<Event>, earlier: &[Event]) -> Vec<Event> {
+    let seen: HashSet<(Uuid, DateTime<Utc>)> =
+        earlier.iter().map(|event| (event.id, event.date)).collect();
+    current
+        .into_iter()
+        .filter(|event| !seen.contains(&(event.id, event.date)))
+        .collect()
+}
+
+// Формат ответа со списком событий
+#[derive(Debug, Clone, Copy)]
+pub enum ResponseFormat {
+    Json,
+    Ndjson,
+    Xml,
+}
@@ -0,0 +1,347 @@
+// Настройки сервера: значения по умолчанию, чтение из config.json и переменных окружения, проверка
+use std::net::IpAddr;
+
+use axum::http::HeaderValue;
+use chrono::NaiveTime;
+use config::{
+    builder::{ConfigBuilder, DefaultState},
+    Config, ConfigError, Environment, File, Source,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tracing_subscriber::filter::LevelFilter;
+
+use crate::{model::SortOrder, store::PersistenceFailureMode};
+
+// Функция для задания значений настроек по умолчанию
+pub fn settings_defaults() -> Result<ConfigBuilder<DefaultState>, ConfigError> {
+    Config::builder()
+        .set_default("address", "127.0.0.1")? // Устанавливаем значение по умолчанию
+        .set_default("port", 8080)? // Устанавливаем значение по умолчанию
+        .set_default("delete_grace_period_secs", 0)? // По умолчанию удаляем сразу
+        .set_default("midnight_boundary", "start")? // Полночь относится к начинающемуся дню
+        .set_default("week_start", "monday")? // Неделя начинается с понедельника
+        .set_default("trim_strings", true)? // По умолчанию обрезаем пробелы в названиях
+        .set_default("storage_path", "events.json")? // Файл для сохранения событий
+        .set_default("flush_interval_ms", 0)? // События записываются на диск сразу после изменения
+        .set_default("storage_backend", "memory")? // По умолчанию события хранятся в памяти и в JSON-файле
+        .set_default("persistence_failure_mode", "rollback")? // Изменение, которое не удалось записать на диск, отменяется
+        .set_default("sqlite_path", "events.db")? // Файл базы для хранилища SQLite
+        .set_default("log_level", "info")? // Уровень логирования по умолчанию
+        .set_default("cors_allowed_origins", Vec::<String>::new())? // По умолчанию запросы с других источников запрещены
+        .set_default("min_gap_minutes", 0)? // По умолчанию события могут идти вплотную друг к другу
+        .set_default("business_start", "09:00")? // Начало рабочего дня для поиска свободного времени
+        .set_default("business_end", "18:00")? // Конец рабочего дня для поиска свободного времени
+        .set_default("busy_time_overlaps", "merge")? // Пересекающиеся события в /busy_time считаются один раз
+        .set_default("request_timeout_ms", 30_000)? // Запрос обрабатывается не дольше 30 секунд
+        .set_default("max_body_bytes", 1024 * 1024)? // Тело запроса не больше 1 МиБ
+        .set_default("compression", true)? // По умолчанию ответы сжимаются
+        .set_default("soft_delete", false)? // По умолчанию события удаляются окончательно
+        .set_default("soft_delete_retention_secs", 30 * 24 * 60 * 60)? // Мягко удаленные события хранятся 30 дней
+        .set_default("idempotency_key_ttl_secs", 24 * 60 * 60)? // Ключи идемпотентности хранятся сутки
+        .set_default("max_dates_per_query", 40)? // Не больше 40 дат в одном запросе /events_for_days
+        .set_default("max_range_days", 366)? // Диапазон дат - не больше года
+        .set_default("tcp_backlog", 1024)? // Очередь соединений такой же длины, как у TcpListener из tokio
+        .set_default("change_feed_capacity", 1024)? // Канал изменений хранит 1024 последних изменения
+        .set_default("debug_endpoints", false)? // Отладочные маршруты по умолчанию отключены
+        .set_default("include_null_fields", false) // Незаданные необязательные поля событий не выводятся
+}
+
+// Функция для чтения и проверки настроек
+// Приоритет: переменные окружения, затем файл config.json, затем значения по умолчанию
+pub fn load_settings() -> Result<Settings, ConfigError> {
+    // Указываем путь к файлу конфигурации и префикс переменных окружения
+    settings_from(File::with_name("config"), Environment::with_prefix("APP"))
+}
+
+// Функция для сборки настроек из файла конфигурации и переменных окружения
+fn settings_from<F>(file: F, environment: Environment) -> Result<Settings, ConfigError>
+where
+    F: Source + Send + Sync + 'static,
+{
+    // Создаем новый конфиг
+    let config = settings_defaults()?
+        .add_source(file)
+        // Переменные окружения вида APP_PORT переопределяют файл, списки задаются через запятую
+        .add_source(
+            environment
+                .try_parsing(true)
+                .list_separator(",")
+                .with_list_parse_key("cors_allowed_origins"),
+        )
+        .build()?; // Создаем конфигурацию
+    settings_check(config)
+}
+
+// Функция для разбора и проверки собранной конфигурации
+fn settings_check(config: Config) -> Result<Settings, ConfigError> {
+    // Порт проверяем до разбора настроек, чтобы сообщить допустимый диапазон
+    let port = config.get_int("port")?;
+    if !(1..=65535).contains(&port) {
+        return Err(ConfigError::Message("port must be 1-65535".to_string()));
+    }
+    // Извлекаем настройки
+    let settings: Settings = config.try_deserialize()?;
+    if let Err(e) = settings.address.parse::<IpAddr>() {
+        return Err(ConfigError::Message(format!(
+            "invalid address '{}': {}",
+            settings.address, e
+        )));
+    }
+    if let Err(e) = settings.log_level.parse::<LevelFilter>() {
+        return Err(ConfigError::Message(format!(
+            "invalid log_level '{}': {}",
+            settings.log_level, e
+        )));
+    }
+    if let Some(origin) = settings
+        .cors_allowed_origins
+        .iter()
+        .find(|origin| HeaderValue::from_str(origin).is_err())
+    {
+        return Err(ConfigError::Message(format!(
+            "invalid cors origin '{}'",
+            origin
+        )));
+    }
+    if settings.max_range_days < 1 {
+        return Err(ConfigError::Message(
+            "max_range_days must be positive".to_string(),
+        ));
+    }
+    if settings.change_feed_capacity == 0 {
+        return Err(ConfigError::Message(
+            "change_feed_capacity must be positive".to_string(),
+        ));
+    }
+    if settings.worker_threads == Some(0) {
+        return Err(ConfigError::Message(
+            "worker_threads must be positive".to_string(),
+        ));
+    }
+    // С отложенной записью изменение записывается уже после ответа клиенту, поэтому отменить его нельзя
+    if matches!(settings.storage_backend, StorageBackend::Memory)
+        && settings.flush_interval_ms > 0
+        && settings.persistence_failure_mode == PersistenceFailureMode::Rollback
+    {
+        return Err(ConfigError::Message(
+            "persistence_failure_mode 'rollback' requires flush_interval_ms = 0, use 'keep' with deferred writes".to_string(),
+        ));
+    }
+    if settings.business_start >= settings.business_end {
+        return Err(ConfigError::Message(
+            "business_start must be before business_end".to_string(),
+        ));
+    }
+    Ok(settings)
+}
+
+// К какому дню относится событие, назначенное ровно на полночь
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MidnightBoundary {
+    // К начинающемуся дню: 2024-06-01T00:00:00Z - это 1 июня
+    Start,
+    // К завершившемуся дню: 2024-06-01T00:00:00Z - это 31 мая
+    End,
+}
+
+// День, с которого начинается неделя
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeekStart {
+    Monday,
+    Sunday,
+}
+
+// Как считать пересекающиеся события в общем занятом времени
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlapMode {
+    // Время, занятое несколькими событиями сразу, считается один раз
+    Merge,
+    // Длительности событий складываются
+    Sum,
+}
+
+// Хранилище событий
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    Memory,
+    Sqlite,
+}
+
+// Настройки выводятся в /debug/dump, поэтому секреты при сериализации скрываются
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Settings {
+    pub address: String,
+    pub port: u16,
+    // Задержка перед удалением события в секундах, 0 - удаление сразу
+    pub delete_grace_period_secs: u64,
+    // Порядок сортировки по умолчанию, если не задан - события возвращаются в порядке добавления
+    pub default_sort: Option<SortOrder>,
+    // Правило отнесения событий в полночь к дню
+    pub midnight_boundary: MidnightBoundary,
+    // День начала недели для выборки за неделю и сетки недели
+    pub week_start: WeekStart,
+    // Обрезать пробелы по краям строковых полей запроса
+    pub trim_strings: bool,
+    // Общий лимит запросов в секунду для всех клиентов, если не задан - без ограничений
+    pub global_rate_limit_per_sec: Option<u32>,
+    // Где хранятся события: memory - в памяти с записью в JSON-файл, sqlite - в базе SQLite
+    pub storage_backend: StorageBackend,
+    // Путь к файлу, в котором сохраняются события
+    pub storage_path: String,
+    // Как часто записывать изменения в файл событий в миллисекундах, 0 - после каждого изменения
+    pub flush_interval_ms: u64,
+    // Путь к файлу базы SQLite
+    pub sqlite_path: String,
+    // Максимальное количество хранимых событий, если не задано - без ограничений
+    pub max_events: Option<usize>,
+    // Уровень логирования: error, warn, info, debug, trace или off
+    pub log_level: String,
+    // Источники, которым разрешено обращаться к API из браузера, например "http://localhost:3000"
+    pub cors_allowed_origins: Vec<String>,
+    // Ключ API, который клиенты передают в заголовке X-API-Key, если не задан - доступ открыт всем
+    #[serde(serialize_with = "redacted")]
+    pub api_key: Option<String>,
+    // Минимальный промежуток между событиями календаря в минутах: более близкие события считаются пересекающимися
+    pub min_gap_minutes: u64,
+    // Рабочие часы в формате "часы:минуты", в пределах которых ищется свободное время
+    #[serde(deserialize_with = "hour_minute")]
+    pub business_start: NaiveTime,
+    #[serde(deserialize_with = "hour_minute")]
+    pub business_end: NaiveTime,
+    // Как считать пересекающиеся события в /busy_time, если в запросе не указан параметр overlaps
+    pub busy_time_overlaps: OverlapMode,
+    // Максимальное время обработки запроса в миллисекундах
+    pub request_timeout_ms: u64,
+    // Максимальный размер тела запроса в байтах
+    pub max_body_bytes: usize,
+    // Сжимать ли ответы gzip или brotli
+    pub compression: bool,
+    // Что делать с изменением, которое не удалось записать на диск
+    pub persistence_failure_mode: PersistenceFailureMode,
+    // Помечать ли события удаленными вместо окончательного удаления
+    pub soft_delete: bool,
+    // Сколько секунд хранятся мягко удаленные события до окончательной очистки
+    pub soft_delete_retention_secs: u64,
+    // Сколько секунд запоминается ответ на запрос создания с заголовком Idempotency-Key
+    pub idempotency_key_ttl_secs: u64,
+    // Файл, в который дописывается журнал изменений, если не задан - журнал хранится только в памяти
+    pub audit_log_path: Option<String>,
+    // Максимальное количество дат в одном запросе /events_for_days
+    pub max_dates_per_query: usize,
+    // Максимальная длина диапазона from-to в днях, включая обе границы
+    pub max_range_days: i64,
+    // Количество рабочих потоков среды выполнения, если не задано - по числу ядер процессора
+    pub worker_threads: Option<usize>,
+    // Длина очереди соединений, ожидающих принятия сервером
+    pub tcp_backlog: u32,
+    // Сколько изменений хранит канал для WebSocket и SSE, отстающий сильнее подписчик пропускает изменения
+    pub change_feed_capacity: usize,
+    // Включить отладочные маршруты /debug/..., они требуют ключ API
+    pub debug_endpoints: bool,
+    // Выводить незаданные необязательные поля событий (description, location, end_date, ...) как null
+    // для клиентов, которым нужен постоянный набор полей
+    pub include_null_fields: bool,
+}
+
+// Функция для чтения времени в формате "часы:минуты", например "09:00"
+fn hour_minute<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&value, "%H:%M").map_err(serde::de::Error::custom)
+}
+
+// Функция для сериализации секрета: вместо значения выводится только признак, что оно задано
+fn redacted<S: Serializer>(value: &Option<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    value.as_ref().map(|_| "[redacted]").serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Проверяем настройки по умолчанию с указанными переопределениями
+    fn check(overrides: &[(&str, &str)]) -> Result<Settings, ConfigError> {
+        let mut builder = settings_defaults().unwrap();
+        for (key, value) in overrides {
+            builder = builder.set_override(*key, *value).unwrap();
+        }
+        settings_check(builder.build().unwrap())
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let settings = check(&[]).unwrap();
+        assert_eq!(settings.flush_interval_ms, 0);
+        assert_eq!(
+            settings.persistence_failure_mode,
+            PersistenceFailureMode::Rollback
+        );
+    }
+
+    #[test]
+    fn rollback_with_deferred_writes_is_rejected() {
+        let result = check(&[("flush_interval_ms", "1000")]);
+        assert!(result.unwrap_err().to_string().contains("rollback"));
+        check(&[
+            ("flush_interval_ms", "1000"),
+            ("persistence_failure_mode", "keep"),
+        ])
+        .unwrap();
+        // SQLite записывает изменение сразу, интервал записи на него не влияет
+        check(&[("flush_interval_ms", "1000"), ("storage_backend", "sqlite")]).unwrap();
+    }
+
+    #[test]
+    fn environment_overrides_file() {
+        let file = File::from_str(
+            r#"{"port": 9000, "log_level": "debug"}"#,
+            config::FileFormat::Json,
+        );
+        let environment = Environment::with_prefix("APP").source(Some(
+            [
+                ("APP_PORT".to_string(), "9100".to_string()),
+                (
+                    "APP_CORS_ALLOWED_ORIGINS".to_string(),
+                    "https://a.example,https://b.example".to_string(),
+                ),
+            ]
+            .into(),
+        ));
+        let settings = settings_from(file, environment).unwrap();
+        assert_eq!(settings.port, 9100);
+        // Значения, не заданные в окружении, берутся из файла
+        assert_eq!(settings.log_level, "debug");
+        assert_eq!(
+            settings.cors_allowed_origins,
+            ["https://a.example", "https://b.example"]
+        );
+    }
+
+    #[test]
+    fn invalid_address_is_rejected() {
+        let error = check(&[("address", "localhost:8080")]).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("invalid address 'localhost:8080'"));
+        check(&[("address", "::1")]).unwrap();
+    }
+
+    #[test]
+    fn unknown_week_start_is_rejected() {
+        let error = check(&[("week_start", "friday")]).unwrap_err();
+        assert!(error.to_string().contains("friday"));
+        let settings = check(&[("week_start", "sunday")]).unwrap();
+        assert!(matches!(settings.week_start, WeekStart::Sunday));
+    }
+
+    #[test]
+    fn worker_threads_reach_runtime() {
+        let settings = check(&[("worker_threads", "3")]).unwrap();
+        let runtime = crate::runtime_builder(&settings).build().unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 3);
+        assert!(check(&[("worker_threads", "0")]).is_err());
+    }
+}
@@ -0,0 +1,753 @@
+// Хранилище событий в файле SQLite
+// В отличие от хранилища в памяти, события не загружаются при запуске целиком:
+// выборки за день, неделю и месяц фильтруются по дате в самом запросе
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+use rusqlite::{params, Connection, DatabaseName, OptionalExtension, Row};
+use uuid::Uuid;
+
+use crate::{
+    store::{BulkInsertError, EventStore, LastMutation, ReindexReport, StoreDump, StoreError},
+    Event, Recurrence,
+};
+
+// Миграции схемы по порядку, номер последней примененной хранится в PRAGMA user_version
+// Новая миграция добавляется в конец списка, уже примененные не меняются
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE events (
+        id TEXT PRIMARY KEY,
+        calendar TEXT NOT NULL,
+        date TEXT NOT NULL,
+        end_date TEXT,
+        name TEXT NOT NULL,
+        recurrence TEXT,
+        recurrence_until TEXT,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL,
+        deleted_at TEXT
+    );
+    CREATE INDEX events_calendar_date ON events (calendar, date);",
+    "ALTER TABLE events ADD COLUMN version INTEGER NOT NULL DEFAULT 1;",
+    "ALTER TABLE events ADD COLUMN tags TEXT NOT NULL DEFAULT '[]';",
+    "ALTER TABLE events ADD COLUMN all_day INTEGER NOT NULL DEFAULT 0;",
+    // Начало и окончание в секундах с начала эпохи (UTC) для выборок по диапазону дат
+    "ALTER TABLE events ADD COLUMN date_ts INTEGER;
+    ALTER TABLE events ADD COLUMN end_ts INTEGER;
+    UPDATE events SET
+        date_ts = CAST(strftime('%s', substr(date, 1, 19)) AS INTEGER),
+        end_ts = CAST(strftime('%s', substr(end_date, 1, 19)) AS INTEGER);
+    CREATE INDEX events_calendar_date_ts ON events (calendar, date_ts);",
+    "ALTER TABLE events ADD COLUMN description TEXT;
+    ALTER TABLE events ADD COLUMN location TEXT;",
+    "ALTER TABLE events ADD COLUMN tz TEXT;",
+];
+
+// Столбцы события в порядке, в котором их читает event_from_row
+// Даты хранятся строками rfc3339 в UTC (их читает event_from_row) и дублируются в date_ts и end_ts:
+// по этим столбцам выборки сравнивают даты как числа, не завися от формата строк
+const COLUMNS: &str =
+    "id, calendar, date, end_date, name, recurrence, recurrence_until, created_at, updated_at, deleted_at, version, tags, all_day, description, location, tz";
+
+pub struct SqliteStore {
+    // Соединение используется из потоков пула блокирующих задач, поэтому хранится в Arc
+    connection: Arc<Mutex<Connection>>,
+    max_events: Option<usize>,
+    soft_delete: bool,
+    last_mutation: LastMutation,
+}
+
+impl SqliteStore {
+    // Открываем файл базы (создается, если его нет) и применяем недостающие миграции
+    pub fn open(
+        path: &str,
+        max_events: Option<usize>,
+        soft_delete: bool,
+    ) -> Result<Self, rusqlite::Error> {
+        let mut connection = Connection::open(path)?;
+        migrate(&mut connection)?;
+        Ok(SqliteStore {
+            connection: Arc::new(Mutex::new(connection)),
+            max_events,
+            soft_delete,
+            last_mutation: LastMutation::default(),
+        })
+    }
+
+    // Отмечаем время изменения, если оно выполнено
+    fn mutated<T, E>(&self, result: Result<T, E>) -> Result<T, E> {
+        if result.is_ok() {
+            self.last_mutation.touch();
+        }
+        result
+    }
+
+    // Выполняем работу с базой в пуле блокирующих задач: запросы rusqlite синхронные
+    // и не должны занимать потоки, на которых выполняются обработчики
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> T + Send + 'static,
+    ) -> Result<T, StoreError> {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || f(&mut lock(&connection)))
+            .await
+            .map_err(|e| StoreError::Unavailable(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl EventStore for SqliteStore {
+    async fn insert(&self, event: Event) -> Result<(), StoreError> {
+        let max_events = self.max_events;
+        let result = self
+            .blocking(move |connection| {
+                if exists(connection, event.id)? {
+                    return Err(StoreError::Exists);
+                }
+                if !has_room(connection, max_events, 1)? {
+                    return Err(StoreError::LimitReached);
+                }
+                insert_event(connection, &event)?;
+                Ok(())
+            })
+            .await?;
+        self.mutated(result)
+    }
+
+    async fn insert_all(&self, events: Vec<Event>) -> Result<(), BulkInsertError> {
+        let max_events = self.max_events;
+        let result = self
+            .blocking(move |connection| {
+                let transaction = connection
+                    .transaction()
+                    .map_err(|e| BulkInsertError::Failed(e.into()))?;
+                let mut errors = Vec::new();
+                for (i, event) in events.iter().enumerate() {
+                    // Уже добавленные в транзакции события видны следующим проверкам, поэтому повторы внутри списка тоже находятся
+                    let result = match exists(&transaction, event.id) {
+                        Ok(true) => Err(StoreError::Exists),
+                        Ok(false) => match has_room(&transaction, max_events, 1) {
+                            Ok(true) => insert_event(&transaction, event).map_err(StoreError::from),
+                            Ok(false) => Err(StoreError::LimitReached),
+                            Err(e) => Err(e.into()),
+                        },
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Err(e) = result {
+                        errors.push((i, e));
+                    }
+                }
+                if !errors.is_empty() {
+                    // Транзакция откатывается при удалении
+                    return Err(BulkInsertError::Rejected(errors));
+                }
+                transaction
+                    .commit()
+                    .map_err(|e| BulkInsertError::Failed(e.into()))
+            })
+            .await
+            .map_err(BulkInsertError::Failed)?;
+        self.mutated(result)
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<Event, StoreError> {
+        let soft_delete = self.soft_delete;
+        let result = self
+            .blocking(move |connection| {
+                let mut event = find_event(connection, id)?.ok_or(StoreError::NotFound)?;
+                if soft_delete {
+                    let now = Utc::now();
+                    connection.execute(
+                        "UPDATE events SET deleted_at = ?1 WHERE id = ?2",
+                        params![format_date(now), id.to_string()],
+                    )?;
+                    event.deleted_at = Some(now);
+                } else {
+                    connection.execute("DELETE FROM events WHERE id = ?1", [id.to_string()])?;
+                }
+                Ok(event)
+            })
+            .await?;
+        self.mutated(result)
+    }
+
+    async fn update(&self, event: Event, expected_version: u64) -> Result<(), StoreError> {
+        let result = self.blocking(move |connection| {
+            let updated = connection.execute(
+                "UPDATE events SET calendar = ?2, date = ?3, end_date = ?4, name = ?5, recurrence = ?6,
+                    recurrence_until = ?7, created_at = ?8, updated_at = ?9, version = ?10,
+                    tags = ?12, all_day = ?13, date_ts = ?14, end_ts = ?15,
+                    description = ?16, location = ?17, tz = ?18
+                 WHERE id = ?1 AND deleted_at IS NULL AND version = ?11",
+                params![
+                    event.id.to_string(),
+                    event.calendar,
+                    format_date(event.date),
+                    event.end_date.map(format_date),
+                    event.name,
+                    event.recurrence.map(recurrence_name),
+                    event.recurrence_until.map(format_date),
+                    format_date(event.created_at),
+                    format_date(event.updated_at),
+                    event.version,
+                    expected_version,
+                    tags_json(&event.tags),
+                    event.all_day,
+                    event.date.timestamp(),
+                    event.end_date.map(|end| end.timestamp()),
+                    event.description,
+                    event.location,
+                    event.tz.map(|tz| tz.name()),
+                ],
+            )?;
+            // Ничего не изменилось - либо события нет, либо его версия уже другая
+            if updated == 0 {
+                return match find_event(connection, event.id)? {
+                    Some(existing) => Err(StoreError::VersionMismatch(existing.version)),
+                    None => Err(StoreError::NotFound),
+                };
+            }
+            Ok(())
+        })
+        .await?;
+        self.mutated(result)
+    }
+
+    async fn find(&self, id: Uuid) -> Result<Option<Event>, StoreError> {
+        Ok(self
+            .blocking(move |connection| find_event(connection, id))
+            .await??)
+    }
+
+    async fn find_by_name(
+        &self,
+        calendar: &str,
+        date: DateTime<Utc>,
+        name: &str,
+    ) -> Result<Vec<Event>, StoreError> {
+        let calendar = calendar.to_string();
+        let name = name.to_string();
+        // Дата сравнивается точно, с наносекундами, поэтому ищем по строке в UTC, а не по секундам в date_ts
+        Ok(self
+            .blocking(move |connection| {
+                let mut statement = connection.prepare(&format!(
+                    "SELECT {} FROM events
+                     WHERE calendar = ?1 AND date = ?2 AND name = ?3 AND deleted_at IS NULL
+                     ORDER BY rowid",
+                    COLUMNS
+                ))?;
+                let events = statement
+                    .query_map(params![calendar, format_date(date), name], event_from_row)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok::<_, rusqlite::Error>(events)
+            })
+            .await??)
+    }
+
+    async fn query_range(
+        &self,
+        calendar: Option<&str>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<Event>, StoreError> {
+        let calendar = calendar.map(str::to_string);
+        // Диапазон расширяется на день в обе стороны, как и в хранилище в памяти
+        // Сравниваются секунды с начала эпохи из столбцов date_ts и end_ts, а не строки дат
+        let from = from
+            .checked_sub_signed(Duration::days(1))
+            .unwrap_or(NaiveDate::MIN);
+        let until = to.checked_add_signed(Duration::days(2)).unwrap_or(to);
+        let from_ts = from.and_time(NaiveTime::MIN).and_utc().timestamp();
+        let until_ts = until.and_time(NaiveTime::MIN).and_utc().timestamp();
+        self.blocking(move |connection| {
+            if let Some(calendar) = &calendar {
+                check_calendar(connection, calendar)?;
+            }
+            // Событие с окончанием попадает в диапазон, если окончание не раньше его начала,
+            // а повторяющиеся события возвращаются всегда: их повторения могут попасть в любой диапазон
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM events
+                 WHERE deleted_at IS NULL AND (?1 IS NULL OR calendar = ?1)
+                   AND (recurrence IS NOT NULL OR (date_ts < ?3 AND COALESCE(end_ts, date_ts) >= ?2))
+                 ORDER BY rowid",
+                COLUMNS
+            ))?;
+            let events = statement
+                .query_map(params![calendar, from_ts, until_ts], event_from_row)?
+                .collect::<Result<Vec<Event>, _>>()?;
+            Ok(events)
+        })
+        .await?
+    }
+
+    async fn list(&self, calendar: Option<&str>) -> Result<Vec<Event>, StoreError> {
+        let calendar = calendar.map(str::to_string);
+        self.blocking(move |connection| {
+            if let Some(calendar) = &calendar {
+                check_calendar(connection, calendar)?;
+            }
+            let mut statement = connection.prepare(&format!(
+                "SELECT {} FROM events
+                 WHERE deleted_at IS NULL AND (?1 IS NULL OR calendar = ?1)
+                 ORDER BY rowid",
+                COLUMNS
+            ))?;
+            let events = statement
+                .query_map([calendar], event_from_row)?
+                .collect::<Result<Vec<Event>, _>>()?;
+            Ok(events)
+        })
+        .await?
+    }
+
+    async fn calendars(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self
+            .blocking(|connection| {
+                // Календарь, в котором остались только мягко удаленные события, не показываем
+                let mut statement = connection.prepare(
+                    "SELECT DISTINCT calendar FROM events WHERE deleted_at IS NULL ORDER BY calendar",
+                )?;
+                let names = statement
+                    .query_map([], |row| row.get(0))?
+                    .collect::<Result<Vec<String>, _>>()?;
+                Ok::<_, rusqlite::Error>(names)
+            })
+            .await??)
+    }
+
+    async fn restore(&self, id: Uuid) -> Result<Event, StoreError> {
+        let result = self
+            .blocking(move |connection| {
+                let mut event = connection
+                    .query_row(
+                        &format!(
+                            "SELECT {} FROM events WHERE id = ?1 AND deleted_at IS NOT NULL",
+                            COLUMNS
+                        ),
+                        [id.to_string()],
+                        event_from_row,
+                    )
+                    .optional()?
+                    .ok_or(StoreError::NotFound)?;
+                connection.execute(
+                    "UPDATE events SET deleted_at = NULL WHERE id = ?1",
+                    [id.to_string()],
+                )?;
+                event.deleted_at = None;
+                Ok(event)
+            })
+            .await?;
+        self.mutated(result)
+    }
+
+    async fn purge(&self, before: DateTime<Utc>) -> Result<usize, StoreError> {
+        let purged = self
+            .blocking(move |connection| {
+                connection.execute(
+                    "DELETE FROM events WHERE deleted_at IS NOT NULL AND deleted_at < ?1",
+                    [format_date(before)],
+                )
+            })
+            .await??;
+        if purged > 0 {
+            self.last_mutation.touch();
+        }
+        Ok(purged)
+    }
+
+    // Индексы таблицы строит сама база: пересоздаем их командой REINDEX,
+    // а проблемы, найденные проверкой целостности, возвращаем в отчете
+    async fn reindex(&self) -> Result<ReindexReport, StoreError> {
+        Ok(self
+            .blocking(|connection| {
+                connection.execute_batch("REINDEX")?;
+                let (calendars, events) = connection.query_row(
+                    "SELECT COUNT(DISTINCT calendar), COUNT(*) FROM events WHERE deleted_at IS NULL",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )?;
+                let problems = connection
+                    .prepare("PRAGMA integrity_check")?
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .filter(|message| !matches!(message.as_deref(), Ok("ok")))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok::<_, rusqlite::Error>(ReindexReport {
+                    calendars,
+                    events,
+                    fixed: 0,
+                    problems,
+                })
+            })
+            .await??)
+    }
+
+    // Индексы базы содержат по записи на каждую строку таблицы, включая мягко удаленные
+    async fn dump(&self) -> Result<StoreDump, StoreError> {
+        let mut dump = self
+            .blocking(|connection| {
+                let (calendars, events, deleted): (usize, usize, usize) = connection.query_row(
+                    "SELECT COUNT(DISTINCT calendar), COUNT(*) - COUNT(deleted_at), COUNT(deleted_at) FROM events",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )?;
+                let indexes = connection
+                    .prepare("SELECT name FROM sqlite_master WHERE type = 'index' AND tbl_name = 'events'")?
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .map(|name| name.map(|name| (name, events + deleted)))
+                    .collect::<Result<BTreeMap<_, _>, _>>()?;
+                Ok::<_, rusqlite::Error>(StoreDump {
+                    calendars,
+                    events,
+                    deleted,
+                    indexes,
+                    last_mutation: None,
+                    read_only: connection.is_readonly(DatabaseName::Main)?,
+                })
+            })
+            .await??;
+        dump.last_mutation = self.last_mutation.get();
+        Ok(dump)
+    }
+
+    async fn check_ready(&self) -> Result<(), StoreError> {
+        Ok(self
+            .blocking(|connection| connection.query_row("SELECT 1", [], |_| Ok(())))
+            .await??)
+    }
+
+    // Изменения записываются в базу сразу, поэтому перед остановкой записывать нечего
+    async fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    // Изменение либо записано в базу, либо завершилось ошибкой, незаписанных изменений не бывает
+    fn has_unsaved_changes(&self) -> bool {
+        false
+    }
+}
+
+// Соединение с базой, отравленная паникой блокировка не мешает работе
+fn lock(connection: &Mutex<Connection>) -> MutexGuard<'_, Connection> {
+    connection.lock().unwrap_or_else(|e| {
+        tracing::warn!("recovered poisoned event store lock");
+        connection.clear_poison();
+        e.into_inner()
+    })
+}
+
+// Можно ли добавить еще count событий, лимит общий для всех календарей
+fn has_room(
+    connection: &Connection,
+    max_events: Option<usize>,
+    count: usize,
+) -> Result<bool, rusqlite::Error> {
+    let Some(max) = max_events else {
+        return Ok(true);
+    };
+    let total: usize = connection.query_row(
+        "SELECT COUNT(*) FROM events WHERE deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(total + count <= max)
+}
+
+// Ошибка базы означает, что хранилище недоступно
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Unavailable(e.to_string())
+    }
+}
+
+// Функция для применения миграций, которые еще не применялись к базе
+fn migrate(connection: &mut Connection) -> Result<(), rusqlite::Error> {
+    let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", i + 1)?;
+        transaction.commit()?;
+        tracing::info!(version = i + 1, "applied storage migration");
+    }
+    Ok(())
+}
+
+// Функция для записи нового события
+fn insert_event(connection: &Connection, event: &Event) -> Result<(), rusqlite::Error> {
+    connection.execute(
+        &format!(
+            "INSERT INTO events ({}, date_ts, end_ts)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
+            COLUMNS
+        ),
+        params![
+            event.id.to_string(),
+            event.calendar,
+            format_date(event.date),
+            event.end_date.map(format_date),
+            event.name,
+            event.recurrence.map(recurrence_name),
+            event.recurrence_until.map(format_date),
+            format_date(event.created_at),
+            format_date(event.updated_at),
+            event.deleted_at.map(format_date),
+            event.version,
+            tags_json(&event.tags),
+            event.all_day,
+            event.description,
+            event.location,
+            event.tz.map(|tz| tz.name()),
+            event.date.timestamp(),
+            event.end_date.map(|end| end.timestamp()),
+        ],
+    )?;
+    Ok(())
+}
+
+// Функция для проверки, есть ли событие с таким идентификатором, в том числе мягко удаленное
+fn exists(connection: &Connection, id: Uuid) -> Result<bool, rusqlite::Error> {
+    connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM events WHERE id = ?1)",
+        [id.to_string()],
+        |row| row.get(0),
+    )
+}
+
+// Функция для поиска не удаленного события по идентификатору
+fn find_event(connection: &Connection, id: Uuid) -> Result<Option<Event>, rusqlite::Error> {
+    connection
+        .query_row(
+            &format!(
+                "SELECT {} FROM events WHERE id = ?1 AND deleted_at IS NULL",
+                COLUMNS
+            ),
+            [id.to_string()],
+            event_from_row,
+        )
+        .optional()
+}
+
+// Функция для проверки существования календаря
+// Календарь существует, пока в нем есть события, в том числе мягко удаленные
+fn check_calendar(connection: &Connection, calendar: &str) -> Result<(), StoreError> {
+    let exists: bool = connection.query_row(
+        "SELECT EXISTS (SELECT 1 FROM events WHERE calendar = ?1)",
+        [calendar],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Err(StoreError::CalendarNotFound(calendar.to_string()));
+    }
+    Ok(())
+}
+
+// Функция для чтения события из строки результата, порядок столбцов - как в COLUMNS
+fn event_from_row(row: &Row) -> Result<Event, rusqlite::Error> {
+    let id: String = row.get(0)?;
+    let recurrence: Option<String> = row.get(5)?;
+    let tags: String = row.get(11)?;
+    let tz: Option<String> = row.get(15)?;
+    Ok(Event {
+        id: Uuid::parse_str(&id).map_err(|e| conversion_error(0, e))?,
+        calendar: row.get(1)?,
+        date: parse_date(row, 2)?,
+        end_date: parse_optional_date(row, 3)?,
+        name: row.get(4)?,
+        recurrence: recurrence
+            .map(|value| parse_recurrence(&value).ok_or_else(|| conversion_error(5, value)))
+            .transpose()?,
+        recurrence_until: parse_optional_date(row, 6)?,
+        tz: tz
+            .map(|value| value.parse::<Tz>().map_err(|e| conversion_error(15, e)))
+            .transpose()?,
+        created_at: parse_date(row, 7)?,
+        updated_at: parse_date(row, 8)?,
+        deleted_at: parse_optional_date(row, 9)?,
+        version: row.get(10)?,
+        tags: serde_json::from_str(&tags).map_err(|e| conversion_error(11, e))?,
+        all_day: row.get(12)?,
+        description: row.get(13)?,
+        location: row.get(14)?,
+    })
+}
+
+// Дата записывается в UTC с наносекундами, поэтому одна и та же дата всегда записывается одной строкой
+fn format_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Nanos, true)
+}
+
+fn parse_date(row: &Row, i: usize) -> Result<DateTime<Utc>, rusqlite::Error> {
+    let value: String = row.get(i)?;
+    DateTime::parse_from_rfc3339(&value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|e| conversion_error(i, e))
+}
+
+fn parse_optional_date(row: &Row, i: usize) -> Result<Option<DateTime<Utc>>, rusqlite::Error> {
+    match row.get::<_, Option<String>>(i)? {
+        Some(_) => parse_date(row, i).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Теги хранятся массивом JSON
+fn tags_json(tags: &[String]) -> String {
+    serde_json::to_string(tags).unwrap_or_else(|_| "[]".to_string())
+}
+
+// Правило повторения хранится так же, как в JSON: daily, weekly или monthly
+fn recurrence_name(recurrence: Recurrence) -> &'static str {
+    match recurrence {
+        Recurrence::Daily => "daily",
+        Recurrence::Weekly => "weekly",
+        Recurrence::Monthly => "monthly",
+    }
+}
+
+fn parse_recurrence(value: &str) -> Option<Recurrence> {
+    match value {
+        "daily" => Some(Recurrence::Daily),
+        "weekly" => Some(Recurrence::Weekly),
+        "monthly" => Some(Recurrence::Monthly),
+        _ => None,
+    }
+}
+
+// Ошибка чтения значения столбца
+fn conversion_error(i: usize, e: impl ToString) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(i, rusqlite::types::Type::Text, e.to_string().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    // Путь к новому файлу базы во временном каталоге
+    fn temp_db() -> PathBuf {
+        std::env::temp_dir().join(format!("sqlite-test-{}.db", Uuid::new_v4()))
+    }
+
+    fn open(path: &Path) -> SqliteStore {
+        SqliteStore::open(&path.to_string_lossy(), None, false).unwrap()
+    }
+
+    fn event(calendar: &str, date: &str, name: &str) -> Event {
+        Event::new(
+            calendar.to_string(),
+            date.parse().unwrap(),
+            name.to_string(),
+        )
+    }
+
+    fn names(events: &[Event]) -> Vec<&str> {
+        events.iter().map(|event| event.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn migrations_upgrade_database_with_first_schema() {
+        let path = temp_db();
+        {
+            // База, созданная до появления версий, тегов и числовых дат
+            let connection = Connection::open(&path).unwrap();
+            connection.execute_batch(MIGRATIONS[0]).unwrap();
+            connection.pragma_update(None, "user_version", 1).unwrap();
+            connection
+                .execute(
+                    "INSERT INTO events (id, calendar, date, name, created_at, updated_at)
+                     VALUES (?1, 'work', '2024-06-01T10:00:00Z', 'standup', ?2, ?2)",
+                    params![Uuid::nil().to_string(), "2024-05-01T00:00:00Z"],
+                )
+                .unwrap();
+        }
+
+        let store = open(&path);
+        let version: usize = lock(&store.connection)
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+
+        let found = store.find(Uuid::nil()).await.unwrap().unwrap();
+        assert_eq!(found.name, "standup");
+        assert_eq!(found.version, 1);
+        assert!(found.tags.is_empty());
+        // Числовая дата заполнена миграцией, поэтому старое событие находится выборкой по диапазону
+        let day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let events = store.query_range(Some("work"), day, day).await.unwrap();
+        assert_eq!(names(&events), ["standup"]);
+
+        // Повторное открытие не применяет миграции заново
+        drop(store);
+        let store = open(&path);
+        assert_eq!(store.list(None).await.unwrap().len(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn query_range_returns_events_near_range() {
+        let path = temp_db();
+        let store = open(&path);
+        store
+            .insert(event("work", "2024-06-01T10:00:00Z", "in range"))
+            .await
+            .unwrap();
+        store
+            .insert(event("work", "2024-06-05T10:00:00Z", "later"))
+            .await
+            .unwrap();
+        store
+            .insert(event("work", "2024-05-01T10:00:00Z", "earlier"))
+            .await
+            .unwrap();
+        let mut long = event("work", "2024-05-20T10:00:00Z", "spans range");
+        long.end_date = Some("2024-06-02T10:00:00Z".parse().unwrap());
+        store.insert(long).await.unwrap();
+        store
+            .insert(event("home", "2024-06-01T12:00:00Z", "other calendar"))
+            .await
+            .unwrap();
+
+        let day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let events = store.query_range(Some("work"), day, day).await.unwrap();
+        assert_eq!(names(&events), ["in range", "spans range"]);
+        let events = store.query_range(None, day, day).await.unwrap();
+        assert_eq!(
+            names(&events),
+            ["in range", "spans range", "other calendar"]
+        );
+        let result = store.query_range(Some("missing"), day, day).await;
+        assert!(matches!(result, Err(StoreError::CalendarNotFound(_))));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn update_moves_event_in_range_queries() {
+        let path = temp_db();
+        let store = open(&path);
+        let mut standup = event("work", "2024-06-01T10:00:00+03:00", "standup");
+        store.insert(standup.clone()).await.unwrap();
+        standup.date = "2024-06-10T10:00:00Z".parse().unwrap();
+        standup.version += 1;
+        store.update(standup, 1).await.unwrap();
+
+        let old_day = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+        let new_day = NaiveDate::from_ymd_opt(2024, 6, 10).unwrap();
+        assert!(store
+            .query_range(Some("work"), old_day, old_day)
+            .await
+            .unwrap()
+            .is_empty());
+        let events = store
+            .query_range(Some("work"), new_day, new_day)
+            .await
+            .unwrap();
+        assert_eq!(names(&events), ["standup"]);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,93 @@
+// Сохранение событий в JSON-файл, чтобы данные переживали перезапуск сервера
+use std::{fs, io, path::Path};
+
+use chrono::Utc;
+
+use crate::{model::INCLUDE_NULLS, Event};
+
+// Функция для загрузки событий из файла
+// Если файла нет - начинаем с пустого списка
+// Поврежденный или нечитаемый файл переименовываем в <файл>.corrupt-<время> и тоже начинаем с пустого списка:
+// иначе первая же запись затерла бы его содержимое. Если переименовать не удалось - возвращаем ошибку
+pub fn load_events(path: &str) -> io::Result<Vec<Event>> {
+    let error = match fs::read_to_string(path) {
+        Ok(data) => match serde_json::from_str(&data) {
+            Ok(events) => return Ok(events),
+            Err(e) => e.to_string(),
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => e.to_string(),
+    };
+    let corrupt_path = format!(
+        "{}.corrupt-{}",
+        path,
+        Utc::now().format("%Y%m%dT%H%M%S%.3fZ")
+    );
+    fs::rename(path, &corrupt_path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "events file '{}' is unreadable ({}) and could not be moved aside: {}",
+                path, error, e
+            ),
+        )
+    })?;
+    tracing::warn!(path, %error, moved_to = %corrupt_path, "corrupt events file moved aside");
+    Ok(Vec::new())
+}
+
+// Функция для сериализации событий перед записью в файл
+// Незаданные поля в файл не записываются независимо от include_null_fields
+pub fn encode_events(events: &[&Event]) -> serde_json::Result<Vec<u8>> {
+    INCLUDE_NULLS.sync_scope(false, || serde_json::to_vec_pretty(events))
+}
+
+// Функция для сохранения сериализованных событий в файл
+// Сначала пишем во временный файл и затем переименовываем его, чтобы не оставить файл наполовину записанным
+pub fn save_events(path: &str, data: &[u8]) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", path);
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, Path::new(path))
+}
+
+// Функция для проверки, что в каталог файла событий можно писать
+// Пробуем создать и удалить рядом пробный файл, сам файл событий не трогаем
+pub fn is_writable(path: &str) -> bool {
+    let probe_path = format!("{}.probe", path);
+    let writable = fs::write(&probe_path, b"").is_ok();
+    let _ = fs::remove_file(&probe_path);
+    writable
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn corrupt_file_is_moved_aside() {
+        let dir = std::env::temp_dir().join(format!("storage-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.json").to_string_lossy().to_string();
+        fs::write(&path, b"[{\"truncated").unwrap();
+
+        assert!(load_events(&path).unwrap().is_empty());
+        // Файл убран в сторону целиком, и следующая запись его не затрет
+        assert!(fs::metadata(&path).is_err());
+        let moved: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(moved.len(), 1);
+        assert!(moved[0].to_string_lossy().contains("events.json.corrupt-"));
+        assert_eq!(fs::read(&moved[0]).unwrap(), b"[{\"truncated");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn missing_file_starts_empty() {
+        let path = std::env::temp_dir().join(format!("storage-test-{}.json", Uuid::new_v4()));
+        assert!(load_events(&path.to_string_lossy()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,903 @@
+// Хранилище событий
+// Обработчики работают с событиями только через трейт EventStore, поэтому хранилище в памяти
+// можно заменить другим (например, базой данных), не меняя обработчики
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    },
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    index::{self, Calendars},
+    storage, Event,
+};
+
+// Ошибка операции с хранилищем
+#[derive(Debug)]
+pub enum StoreError {
+    // Событие с таким идентификатором уже есть
+    Exists,
+    // Событие с таким идентификатором не найдено
+    NotFound,
+    // Календарь с таким названием не найден
+    CalendarNotFound(String),
+    // Событие изменено после того, как его прочитал клиент, в ошибке - текущая версия
+    VersionMismatch(u64),
+    // Достигнут лимит количества событий
+    LimitReached,
+    // Хранилище недоступно, в ошибке - причина
+    Unavailable(String),
+}
+
+// Ошибка добавления нескольких событий
+#[derive(Debug)]
+pub enum BulkInsertError {
+    // Номера не прошедших проверку событий и причины
+    Rejected(Vec<(usize, StoreError)>),
+    // Ошибка всего списка, не связанная с отдельным событием (например, не удалось записать изменения)
+    Failed(StoreError),
+}
+
+// Результат перестроения индексов хранилища
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ReindexReport {
+    // Сколько календарей и событий проверено, без мягко удаленных
+    pub calendars: usize,
+    pub events: usize,
+    // Сколько записей индексов разошлись с событиями и были исправлены
+    pub fixed: usize,
+    // Несоответствия, которые перестроение индексов не исправляет
+    pub problems: Vec<String>,
+}
+
+// Внутреннее состояние хранилища для /debug/dump
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct StoreDump {
+    pub calendars: usize,
+    // Количество событий без мягко удаленных и количество мягко удаленных
+    pub events: usize,
+    pub deleted: usize,
+    // Количество записей в каждом индексе
+    pub indexes: BTreeMap<String, usize>,
+    // Время последнего изменения событий с момента запуска сервера
+    pub last_mutation: Option<DateTime<Utc>>,
+    // Изменения нельзя записать в хранилище
+    pub read_only: bool,
+}
+
+// Время последнего изменения событий
+#[derive(Default)]
+pub struct LastMutation(Mutex<Option<DateTime<Utc>>>);
+
+impl LastMutation {
+    // Отмечаем изменение, сделанное сейчас
+    pub fn touch(&self) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(Utc::now());
+    }
+
+    pub fn get(&self) -> Option<DateTime<Utc>> {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Что делать с изменением, если его не удалось записать на диск
+// Имеет смысл только без отложенной записи: с ней изменение записывается позже, и отменять его некому
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PersistenceFailureMode {
+    // Отменить изменение в памяти и вернуть ошибку
+    Rollback,
+    // Оставить изменение в памяти и попробовать записать его позже
+    Keep,
+}
+
+#[async_trait]
+pub trait EventStore: Send + Sync {
+    // Добавляем событие, календарь создается при добавлении первого события
+    // Событие с уже существующим идентификатором (в том числе мягко удаленное) второй раз не добавляется,
+    // события с одинаковыми датой и названием допускаются
+    async fn insert(&self, event: Event) -> Result<(), StoreError>;
+
+    // Добавляем несколько событий: либо все, либо ни одного
+    // В ошибке - номера не прошедших проверку событий и причины либо ошибка всего списка
+    async fn insert_all(&self, events: Vec<Event>) -> Result<(), BulkInsertError>;
+
+    // Удаляем событие по идентификатору, в режиме мягкого удаления событие только помечается удаленным
+    async fn remove(&self, id: Uuid) -> Result<Event, StoreError>;
+
+    // Заменяем событие с тем же идентификатором, если его текущая версия равна expected_version
+    // Календарь события может измениться
+    async fn update(&self, event: Event, expected_version: u64) -> Result<(), StoreError>;
+
+    // Событие по идентификатору
+    async fn find(&self, id: Uuid) -> Result<Option<Event>, StoreError>;
+
+    // События календаря с указанными датой и названием в порядке добавления
+    // Таких событий может быть несколько: они различаются только идентификатором
+    async fn find_by_name(
+        &self,
+        calendar: &str,
+        date: DateTime<Utc>,
+        name: &str,
+    ) -> Result<Vec<Event>, StoreError>;
+
+    // События календаря (или всех календарей), которые могут попасть в диапазон дней [from, to], в порядке добавления
+    // Возвращаются с запасом: повторяющиеся события - всегда, точную проверку делает вызывающий код
+    async fn query_range(
+        &self,
+        calendar: Option<&str>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<Event>, StoreError>;
+
+    // Все события календаря (или всех календарей) в порядке добавления
+    async fn list(&self, calendar: Option<&str>) -> Result<Vec<Event>, StoreError>;
+
+    // Названия календарей, в которых есть события, по алфавиту
+    async fn calendars(&self) -> Result<Vec<String>, StoreError>;
+
+    // Восстанавливаем мягко удаленное событие
+    async fn restore(&self, id: Uuid) -> Result<Event, StoreError>;
+
+    // Окончательно удаляем события, мягко удаленные раньше указанного момента, возвращаем их количество
+    async fn purge(&self, before: DateTime<Utc>) -> Result<usize, StoreError>;
+
+    // Перестраиваем индексы по самим событиям и проверяем согласованность данных
+    // Изменения на время перестроения блокируются
+    async fn reindex(&self) -> Result<ReindexReport, StoreError>;
+
+    // Размеры хранилища и его индексов для диагностики
+    async fn dump(&self) -> Result<StoreDump, StoreError>;
+
+    // Проверяем, что хранилище готово принимать запросы
+    async fn check_ready(&self) -> Result<(), StoreError>;
+
+    // Записываем на диск изменения, которые еще не записаны
+    // Вызывается периодически и перед остановкой сервера
+    async fn flush(&self) -> Result<(), StoreError>;
+
+    // Есть изменения, которые не удалось записать на диск
+    fn has_unsaved_changes(&self) -> bool;
+}
+
+// Хранилище событий в памяти по календарям, события записываются в JSON-файл
+// Без отложенной записи файл записывается после каждого изменения, с отложенной - при вызове flush
+pub struct InMemoryStore {
+    calendars: RwLock<Calendars>,
+    // Изменения и записи на диск выполняются по одному: файл записывается уже после снятия блокировки
+    // календарей, и без этого более старое состояние могло бы записаться поверх более нового
+    writer: tokio::sync::Mutex<()>,
+    storage_path: String,
+    max_events: Option<usize>,
+    soft_delete: bool,
+    deferred_writes: bool,
+    failure_mode: PersistenceFailureMode,
+    // Есть изменения, еще не записанные на диск
+    dirty: AtomicBool,
+    // Последняя запись на диск не удалась
+    save_failed: AtomicBool,
+    last_mutation: LastMutation,
+}
+
+impl InMemoryStore {
+    // Загружаем события, сохраненные ранее
+    // Ошибка - если файл событий не удалось ни прочитать, ни убрать в сторону
+    pub fn open(
+        storage_path: String,
+        max_events: Option<usize>,
+        soft_delete: bool,
+        deferred_writes: bool,
+        failure_mode: PersistenceFailureMode,
+    ) -> std::io::Result<Self> {
+        let events = storage::load_events(&storage_path)?;
+        Ok(InMemoryStore {
+            calendars: RwLock::new(index::group_by_calendar(events)),
+            writer: tokio::sync::Mutex::new(()),
+            storage_path,
+            max_events,
+            soft_delete,
+            deferred_writes,
+            failure_mode,
+            dirty: AtomicBool::new(false),
+            save_failed: AtomicBool::new(false),
+            last_mutation: LastMutation::default(),
+        })
+    }
+
+    // Блокировка на чтение
+    // Блокировка, отравленная паникой другого обработчика, не мешает работе: данные остаются доступны
+    fn read(&self) -> RwLockReadGuard<'_, Calendars> {
+        self.calendars.read().unwrap_or_else(|e| {
+            tracing::warn!("recovered poisoned event store lock");
+            self.calendars.clear_poison();
+            e.into_inner()
+        })
+    }
+
+    // Блокировка на запись, отравленная блокировка восстанавливается так же, как при чтении
+    fn write(&self) -> RwLockWriteGuard<'_, Calendars> {
+        self.calendars.write().unwrap_or_else(|e| {
+            tracing::warn!("recovered poisoned event store lock");
+            self.calendars.clear_poison();
+            e.into_inner()
+        })
+    }
+
+    // Что записать на диск после изменения: без отложенной записи - все события, сериализованные под блокировкой
+    // С отложенной записью изменение только отмечается и записывается при вызове flush
+    fn pending(&self, calendars: &Calendars) -> Option<serde_json::Result<Vec<u8>>> {
+        (!self.deferred_writes).then(|| encode(calendars))
+    }
+
+    // Отмечаем изменение и записываем события на диск, блокировка календарей к этому моменту уже снята
+    // Если запись не удалась: в режиме rollback изменение отменяется по записи undo и возвращается ошибка,
+    // в режиме keep изменение остается в памяти и будет записано при вызове flush
+    // Вызывается под блокировкой writer, поэтому между изменением и его отменой других изменений нет
+    async fn changed(
+        &self,
+        undo: Undo,
+        pending: Option<serde_json::Result<Vec<u8>>>,
+    ) -> Result<(), StoreError> {
+        self.last_mutation.touch();
+        let Some(data) = pending else {
+            self.dirty.store(true, Ordering::SeqCst);
+            return Ok(());
+        };
+        if self.save(data).await {
+            self.save_failed.store(false, Ordering::SeqCst);
+            return Ok(());
+        }
+        match self.failure_mode {
+            PersistenceFailureMode::Rollback => {
+                undo.apply(&mut self.write());
+                Err(StoreError::Unavailable("failed to save events".to_string()))
+            }
+            PersistenceFailureMode::Keep => {
+                self.dirty.store(true, Ordering::SeqCst);
+                self.save_failed.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        }
+    }
+
+    // Записываем сериализованные события на диск в пуле блокирующих задач, чтобы не занимать поток среды выполнения
+    // Ошибка записи логируется, возвращается false
+    async fn save(&self, data: serde_json::Result<Vec<u8>>) -> bool {
+        let path = self.storage_path.clone();
+        let result = match data {
+            Ok(data) => tokio::task::spawn_blocking(move || storage::save_events(&path, &data))
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e))),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!(path = %self.storage_path, error = %e, "failed to save events");
+                false
+            }
+        }
+    }
+
+    // Можно ли добавить еще count событий, лимит общий для всех календарей
+    fn has_room(&self, calendars: &Calendars, count: usize) -> bool {
+        self.max_events
+            .is_none_or(|max| total_events(calendars) + count <= max)
+    }
+}
+
+#[async_trait]
+impl EventStore for InMemoryStore {
+    async fn insert(&self, event: Event) -> Result<(), StoreError> {
+        let _writer = self.writer.lock().await;
+        let (undo, pending) = {
+            let mut calendars = self.write();
+            if id_exists(&calendars, event.id) {
+                return Err(StoreError::Exists);
+            }
+            if !self.has_room(&calendars, 1) {
+                return Err(StoreError::LimitReached);
+            }
+            let undo = Undo::Inserted(vec![(event.calendar.clone(), event.id)]);
+            calendars
+                .entry(event.calendar.clone())
+                .or_default()
+                .push(event);
+            (undo, self.pending(&calendars))
+        };
+        self.changed(undo, pending).await
+    }
+
+    async fn insert_all(&self, events: Vec<Event>) -> Result<(), BulkInsertError> {
+        let _writer = self.writer.lock().await;
+        let (undo, pending) = {
+            let mut calendars = self.write();
+            let mut errors = Vec::new();
+            let mut accepted = 0;
+            for (i, event) in events.iter().enumerate() {
+                // Событие с таким идентификатором уже есть либо повторяется среди добавляемых
+                let exists = id_exists(&calendars, event.id)
+                    || events[..i].iter().any(|other| other.id == event.id);
+                if exists {
+                    errors.push((i, StoreError::Exists));
+                } else if !self.has_room(&calendars, accepted + 1) {
+                    errors.push((i, StoreError::LimitReached));
+                } else {
+                    accepted += 1;
+                }
+            }
+            if !errors.is_empty() {
+                return Err(BulkInsertError::Rejected(errors));
+            }
+            if events.is_empty() {
+                return Ok(());
+            }
+            let undo = Undo::Inserted(
+                events
+                    .iter()
+                    .map(|event| (event.calendar.clone(), event.id))
+                    .collect(),
+            );
+            for event in events {
+                calendars
+                    .entry(event.calendar.clone())
+                    .or_default()
+                    .push(event);
+            }
+            (undo, self.pending(&calendars))
+        };
+        self.changed(undo, pending)
+            .await
+            .map_err(BulkInsertError::Failed)
+    }
+
+    async fn remove(&self, id: Uuid) -> Result<Event, StoreError> {
+        let _writer = self.writer.lock().await;
+        let (event, undo, pending) = {
+            let mut calendars = self.write();
+            let calendar = find_by_id(&calendars, id).ok_or(StoreError::NotFound)?;
+            let undo = Undo::Removed(previous_entry(&calendars, &calendar, id)?);
+            let event = if self.soft_delete {
+                calendars
+                    .get_mut(&calendar)
+                    .and_then(|events| events.soft_remove(id, Utc::now()))
+                    .ok_or(StoreError::NotFound)?
+            } else {
+                remove_event(&mut calendars, &calendar, id).ok_or(StoreError::NotFound)?
+            };
+            (event, undo, self.pending(&calendars))
+        };
+        self.changed(undo, pending).await?;
+        Ok(event)
+    }
+
+    async fn update(&self, event: Event, expected_version: u64) -> Result<(), StoreError> {
+        let _writer = self.writer.lock().await;
+        let (undo, pending) = {
+            let mut calendars = self.write();
+            let calendar = find_by_id(&calendars, event.id).ok_or(StoreError::NotFound)?;
+            let version = calendars[&calendar]
+                .get(event.id)
+                .ok_or(StoreError::NotFound)?
+                .version;
+            if version != expected_version {
+                return Err(StoreError::VersionMismatch(version));
+            }
+            let undo = Undo::Updated(previous_entry(&calendars, &calendar, event.id)?);
+            // Если календарь изменился - переносим событие в новый календарь
+            if event.calendar == calendar {
+                if let Some(events) = calendars.get_mut(&calendar) {
+                    events.update(event.id, |existing| *existing = event);
+                }
+            } else {
+                remove_event(&mut calendars, &calendar, event.id);
+                calendars
+                    .entry(event.calendar.clone())
+                    .or_default()
+                    .push(event);
+            }
+            (undo, self.pending(&calendars))
+        };
+        self.changed(undo, pending).await
+    }
+
+    async fn find(&self, id: Uuid) -> Result<Option<Event>, StoreError> {
+        let calendars = self.read();
+        Ok(calendars
+            .values()
+            .find_map(|events| events.get(id))
+            .cloned())
+    }
+
+    async fn find_by_name(
+        &self,
+        calendar: &str,
+        date: DateTime<Utc>,
+        name: &str,
+    ) -> Result<Vec<Event>, StoreError> {
+        let calendars = self.read();
+        Ok(calendars
+            .get(calendar)
+            .map(|events| {
+                events
+                    .find_by_name(date, name)
+                    .into_iter()
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn query_range(
+        &self,
+        calendar: Option<&str>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<Event>, StoreError> {
+        let calendars = self.read();
+        match calendar {
+            Some(calendar) => match calendars.get(calendar) {
+                Some(events) => Ok(events.in_window(from, to).cloned().collect()),
+                None => Err(StoreError::CalendarNotFound(calendar.to_string())),
+            },
+            None => Ok(calendars
+                .values()
+                .flat_map(|events| events.in_window(from, to))
+                .cloned()
+                .collect()),
+        }
+    }
+
+    async fn list(&self, calendar: Option<&str>) -> Result<Vec<Event>, StoreError> {
+        let calendars = self.read();
+        match calendar {
+            Some(calendar) => match calendars.get(calendar) {
+                Some(events) => Ok(events.iter().cloned().collect()),
+                None => Err(StoreError::CalendarNotFound(calendar.to_string())),
+            },
+            None => Ok(calendars
+                .values()
+                .flat_map(|events| events.iter())
+                .cloned()
+                .collect()),
+        }
+    }
+
+    async fn calendars(&self) -> Result<Vec<String>, StoreError> {
+        let calendars = self.read();
+        // Календарь, в котором остались только мягко удаленные события, не показываем
+        let mut names: Vec<String> = calendars
+            .iter()
+            .filter(|(_, events)| !events.is_empty())
+            .map(|(name, _)| name.clone())
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    async fn restore(&self, id: Uuid) -> Result<Event, StoreError> {
+        let _writer = self.writer.lock().await;
+        let (event, undo, pending) = {
+            let mut calendars = self.write();
+            let calendar = calendars
+                .iter()
+                .find(|(_, events)| events.deleted().iter().any(|event| event.id == id))
+                .map(|(calendar, _)| calendar.clone())
+                .ok_or(StoreError::NotFound)?;
+            let events = calendars.get_mut(&calendar).ok_or(StoreError::NotFound)?;
+            let position = events
+                .deleted()
+                .iter()
+                .position(|event| event.id == id)
+                .ok_or(StoreError::NotFound)?;
+            let undo = Undo::Restored(calendar, position, events.deleted()[position].clone());
+            let event = events.restore(id).ok_or(StoreError::NotFound)?;
+            (event, undo, self.pending(&calendars))
+        };
+        self.changed(undo, pending).await?;
+        Ok(event)
+    }
+
+    async fn purge(&self, before: DateTime<Utc>) -> Result<usize, StoreError> {
+        let _writer = self.writer.lock().await;
+        let (purged, undo, pending) = {
+            let mut calendars = self.write();
+            // Запоминаем списки мягко удаленных событий, а не все календари
+            let undo = Undo::Purged(
+                calendars
+                    .iter()
+                    .filter(|(_, events)| !events.deleted().is_empty())
+                    .map(|(calendar, events)| (calendar.clone(), events.deleted().to_vec()))
+                    .collect(),
+            );
+            let purged: usize = calendars
+                .values_mut()
+                .map(|events| events.purge(before))
+                .sum();
+            if purged == 0 {
+                return Ok(0);
+            }
+            calendars.retain(|_, events| !events.is_unused());
+            (purged, undo, self.pending(&calendars))
+        };
+        self.changed(undo, pending).await?;
+        Ok(purged)
+    }
+
+    async fn reindex(&self) -> Result<ReindexReport, StoreError> {
+        let _writer = self.writer.lock().await;
+        let mut calendars = self.write();
+        let mut report = ReindexReport {
+            calendars: calendars.len(),
+            ..ReindexReport::default()
+        };
+        let mut seen = HashSet::new();
+        let mut names: Vec<&String> = calendars.keys().collect();
+        names.sort();
+        for name in names {
+            let events = &calendars[name];
+            report.events += events.len();
+            for event in events.iter().chain(events.deleted()) {
+                if &event.calendar != name {
+                    report.problems.push(format!(
+                        "event {} is stored in calendar {} but belongs to {}",
+                        event.id, name, event.calendar
+                    ));
+                }
+                if !seen.insert(event.id) {
+                    report
+                        .problems
+                        .push(format!("event {} is stored more than once", event.id));
+                }
+            }
+        }
+        report.fixed = calendars.values_mut().map(|events| events.reindex()).sum();
+        if report.fixed > 0 {
+            tracing::warn!(fixed = report.fixed, "repaired event store indexes");
+        }
+        Ok(report)
+    }
+
+    async fn dump(&self) -> Result<StoreDump, StoreError> {
+        let mut dump = {
+            let calendars = self.read();
+            let mut dump = StoreDump {
+                calendars: calendars.len(),
+                ..StoreDump::default()
+            };
+            for events in calendars.values() {
+                dump.events += events.len();
+                dump.deleted += events.deleted().len();
+                for (name, size) in events.index_sizes() {
+                    *dump.indexes.entry(name.to_string()).or_default() += size;
+                }
+            }
+            dump
+        };
+        dump.last_mutation = self.last_mutation.get();
+        dump.read_only = !storage::is_writable(&self.storage_path);
+        Ok(dump)
+    }
+
+    async fn check_ready(&self) -> Result<(), StoreError> {
+        // Блокировку событий пробуем взять без ожидания: если она занята, хранилище сейчас не обслуживает запросы
+        // Отравленная блокировка восстанавливается при следующем обращении, поэтому готовности не мешает
+        if let Err(TryLockError::WouldBlock) = self.calendars.try_write() {
+            return Err(StoreError::Unavailable("event store is locked".to_string()));
+        }
+        // Пока изменения не удалось записать на диск, данные в памяти и в файле расходятся
+        if self.has_unsaved_changes() {
+            return Err(StoreError::Unavailable("failed to save events".to_string()));
+        }
+        if !storage::is_writable(&self.storage_path) {
+            return Err(StoreError::Unavailable(
+                "storage path is not writable".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        let _writer = self.writer.lock().await;
+        // Флаг сбрасываем до чтения событий: изменение, сделанное во время записи, снова его выставит
+        if self.dirty.swap(false, Ordering::SeqCst) {
+            let data = encode(&self.read());
+            if !self.save(data).await {
+                // Не получилось записать - попробуем при следующем вызове
+                self.dirty.store(true, Ordering::SeqCst);
+                self.save_failed.store(true, Ordering::SeqCst);
+                return Err(StoreError::Unavailable("failed to save events".to_string()));
+            }
+            self.save_failed.store(false, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    fn has_unsaved_changes(&self) -> bool {
+        self.save_failed.load(Ordering::SeqCst)
+    }
+}
+
+// Функция для сериализации событий всех календарей для записи на диск
+// Календари записываются по порядку названий, чтобы файл не менялся от порядка обхода HashMap
+fn encode(calendars: &Calendars) -> serde_json::Result<Vec<u8>> {
+    let mut names: Vec<&String> = calendars.keys().collect();
+    names.sort();
+    let events: Vec<&Event> = names
+        .into_iter()
+        .flat_map(|name| calendars[name].iter().chain(calendars[name].deleted()))
+        .collect();
+    storage::encode_events(&events)
+}
+
+// Функция для подсчета событий во всех календарях
+fn total_events(calendars: &Calendars) -> usize {
+    calendars.values().map(|events| events.len()).sum()
+}
+
+// Функция для поиска события по идентификатору во всех календарях
+// Возвращает название календаря, в котором лежит событие
+fn find_by_id(calendars: &Calendars, id: Uuid) -> Option<String> {
+    calendars
+        .iter()
+        .find(|(_, events)| events.get(id).is_some())
+        .map(|(name, _)| name.clone())
+}
+
+// Функция для проверки, есть ли событие с таким идентификатором, в том числе среди мягко удаленных
+fn id_exists(calendars: &Calendars, id: Uuid) -> bool {
+    calendars.values().any(|events| {
+        events.get(id).is_some() || events.deleted().iter().any(|event| event.id == id)
+    })
+}
+
+// Функция для удаления события по идентификатору
+// Опустевший календарь удаляем: календарь существует, пока в нем есть события, в том числе мягко удаленные
+fn remove_event(calendars: &mut Calendars, calendar: &str, id: Uuid) -> Option<Event> {
+    let events = calendars.get_mut(calendar)?;
+    let event = events.remove(id)?;
+    if events.is_unused() {
+        calendars.remove(calendar);
+    }
+    Some(event)
+}
+
+// Событие до изменения: календарь, порядковый номер и само событие
+type Entry = (String, u64, Event);
+
+// Функция для получения события до изменения, чтобы изменение можно было отменить
+fn previous_entry(calendars: &Calendars, calendar: &str, id: Uuid) -> Result<Entry, StoreError> {
+    let events = calendars.get(calendar).ok_or(StoreError::NotFound)?;
+    let seq = events.seq(id).ok_or(StoreError::NotFound)?;
+    let event = events.get(id).ok_or(StoreError::NotFound)?.clone();
+    Ok((calendar.to_string(), seq, event))
+}
+
+// Запись для отмены изменения, которое не удалось записать на диск
+// Хранит только затронутые изменением события, а не копию всех календарей
+enum Undo {
+    // Добавленные события: календарь и идентификатор
+    Inserted(Vec<(String, Uuid)>),
+    // Удаленное (в том числе мягко) событие
+    Removed(Entry),
+    // Событие до обновления, после обновления оно могло перейти в другой календарь
+    Updated(Entry),
+    // Восстановленное событие: календарь, место в списке мягко удаленных и событие до восстановления
+    Restored(String, usize, Event),
+    // Списки мягко удаленных событий календарей до окончательного удаления
+    Purged(Vec<(String, Vec<Event>)>),
+}
+
+impl Undo {
+    // Возвращаем календари к состоянию до изменения
+    fn apply(self, calendars: &mut Calendars) {
+        match self {
+            Undo::Inserted(added) => {
+                for (calendar, id) in added {
+                    remove_event(calendars, &calendar, id);
+                }
+            }
+            Undo::Removed((calendar, seq, event)) => {
+                let events = calendars.entry(calendar).or_default();
+                events
+                    .deleted_mut()
+                    .retain(|deleted| deleted.id != event.id);
+                events.put_back(seq, event);
+            }
+            Undo::Updated((calendar, seq, event)) => {
+                if let Some(current) = find_by_id(calendars, event.id) {
+                    remove_event(calendars, &current, event.id);
+                }
+                calendars.entry(calendar).or_default().put_back(seq, event);
+            }
+            Undo::Restored(calendar, position, event) => {
+                if let Some(events) = calendars.get_mut(&calendar) {
+                    events.remove(event.id);
+                    events.deleted_mut().insert(position, event);
+                }
+            }
+            Undo::Purged(deleted) => {
+                for (calendar, events) in deleted {
+                    *calendars.entry(calendar).or_default().deleted_mut() = events;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    // Хранилище, запись которого всегда завершается ошибкой: на месте файла событий - каталог
+    fn failing_store(mode: PersistenceFailureMode) -> InMemoryStore {
+        let dir = std::env::temp_dir().join(format!("store-test-{}", Uuid::new_v4()));
+        let path = dir.join("events.json");
+        let store =
+            InMemoryStore::open(path.to_string_lossy().to_string(), None, false, false, mode)
+                .unwrap();
+        fs::create_dir_all(&path).unwrap();
+        store
+    }
+
+    fn event(name: &str) -> Event {
+        Event::new(
+            "work".to_string(),
+            "2024-06-01T10:00:00Z".parse().unwrap(),
+            name.to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn rollback_undoes_change_that_failed_to_save() {
+        let store = failing_store(PersistenceFailureMode::Rollback);
+        let result = store.insert(event("standup")).await;
+        assert!(matches!(result, Err(StoreError::Unavailable(_))));
+        assert!(store.list(None).await.unwrap().is_empty());
+        assert!(!store.has_unsaved_changes());
+    }
+
+    #[tokio::test]
+    async fn keep_leaves_change_and_reports_unsaved() {
+        let store = failing_store(PersistenceFailureMode::Keep);
+        store.insert(event("standup")).await.unwrap();
+        assert_eq!(store.list(None).await.unwrap().len(), 1);
+        assert!(store.has_unsaved_changes());
+        assert!(store.check_ready().await.is_err());
+    }
+
+    // Блокировка удерживается через await намеренно: так проверка видит ее занятой
+    #[allow(clippy::await_holding_lock)]
+    #[tokio::test]
+    async fn ready_fails_while_lock_is_held() {
+        let dir = std::env::temp_dir().join(format!("store-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.json").to_string_lossy().to_string();
+        let store = InMemoryStore::open(path, None, false, false, PersistenceFailureMode::Rollback)
+            .unwrap();
+        store.check_ready().await.unwrap();
+        {
+            let _calendars = store.read();
+            assert!(matches!(
+                store.check_ready().await,
+                Err(StoreError::Unavailable(_))
+            ));
+        }
+        store.check_ready().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn poisoned_lock_is_recovered() {
+        let dir = std::env::temp_dir().join(format!("store-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.json").to_string_lossy().to_string();
+        let store = InMemoryStore::open(path, None, false, false, PersistenceFailureMode::Rollback)
+            .unwrap();
+        store.insert(event("standup")).await.unwrap();
+
+        // Паника во время удержания блокировки на запись отравляет ее
+        std::thread::scope(|scope| {
+            let _ = scope
+                .spawn(|| {
+                    let _calendars = store.write();
+                    panic!("handler panicked while holding the lock");
+                })
+                .join();
+        });
+        assert!(store.calendars.is_poisoned());
+
+        store.insert(event("review")).await.unwrap();
+        assert_eq!(store.list(None).await.unwrap().len(), 2);
+        assert!(!store.calendars.is_poisoned());
+    }
+
+    #[tokio::test]
+    async fn deferred_writes_save_many_changes_at_once() {
+        let dir = std::env::temp_dir().join(format!("store-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.json").to_string_lossy().to_string();
+        let store = InMemoryStore::open(
+            path.clone(),
+            None,
+            false,
+            true,
+            PersistenceFailureMode::Keep,
+        )
+        .unwrap();
+        for i in 0..200 {
+            store.insert(event(&format!("event {i}"))).await.unwrap();
+        }
+        // Изменения только отмечены, на диск ничего не записано
+        assert!(fs::metadata(&path).is_err());
+
+        store.flush().await.unwrap();
+        assert_eq!(storage::load_events(&path).unwrap().len(), 200);
+
+        // Без новых изменений flush файл не записывает
+        fs::remove_file(&path).unwrap();
+        store.flush().await.unwrap();
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[tokio::test]
+    async fn rollback_restores_removed_and_moved_events_in_place() {
+        let dir = std::env::temp_dir().join(format!("store-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("events.json");
+        let store = InMemoryStore::open(
+            path.to_string_lossy().to_string(),
+            None,
+            true,
+            false,
+            PersistenceFailureMode::Rollback,
+        )
+        .unwrap();
+        for name in ["a", "b", "c"] {
+            store.insert(event(name)).await.unwrap();
+        }
+        let events = store.list(None).await.unwrap();
+        // Дальше запись всегда завершается ошибкой: на месте файла событий - каталог
+        fs::remove_file(&path).unwrap();
+        fs::create_dir_all(&path).unwrap();
+
+        let result = store.remove(events[1].id).await;
+        assert!(matches!(result, Err(StoreError::Unavailable(_))));
+        let mut moved = events[0].clone();
+        moved.calendar = "home".to_string();
+        moved.version += 1;
+        let result = store.update(moved, 1).await;
+        assert!(matches!(result, Err(StoreError::Unavailable(_))));
+
+        let names: Vec<String> = store
+            .list(None)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| event.name)
+            .collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(store.calendars().await.unwrap(), ["work"]);
+        assert!(store.calendars.read().unwrap()["work"].deleted().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_bulk_save_is_a_batch_error() {
+        let store = failing_store(PersistenceFailureMode::Rollback);
+        let result = store.insert_all(vec![event("a"), event("b")]).await;
+        assert!(matches!(
+            result,
+            Err(BulkInsertError::Failed(StoreError::Unavailable(_)))
+        ));
+        assert!(store.list(None).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,3000 @@
+// Тесты HTTP API: запросы проходят через роутер со всеми middleware, но без открытия сокета
+use axum::{body::Body, http::Request, Router};
+use chrono::{DateTime, TimeZone};
+use config::builder::{ConfigBuilder, DefaultState};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use super::*;
+use crate::settings::settings_defaults;
+
+// Настройки по умолчанию, события записываются во временный файл сразу после изменения
+fn test_config() -> ConfigBuilder<DefaultState> {
+    let path = std::env::temp_dir().join(format!("api-test-{}.json", Uuid::new_v4()));
+    settings_defaults()
+        .unwrap()
+        .set_override("storage_path", path.to_string_lossy().to_string())
+        .unwrap()
+        .set_override("flush_interval_ms", 0)
+        .unwrap()
+}
+
+// Роутер и его состояние с указанными настройками
+fn server(config: ConfigBuilder<DefaultState>) -> (Router, AppState) {
+    let settings: Settings = config.build().unwrap().try_deserialize().unwrap();
+    let store = open_store(&settings).unwrap();
+    let state = app_state(settings, store);
+    (app(state.clone()).unwrap(), state)
+}
+
+async fn call(app: &Router, req: Request<Body>) -> Response {
+    app.clone().oneshot(req).await.unwrap()
+}
+
+async fn body_text(res: Response) -> String {
+    let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+async fn body_json(res: Response) -> Value {
+    serde_json::from_str(&body_text(res).await).unwrap()
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+fn delete(uri: &str) -> Request<Body> {
+    Request::delete(uri).body(Body::empty()).unwrap()
+}
+
+fn send_json(method: Method, uri: &str, body: Value) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn post_json(uri: &str, body: Value) -> Request<Body> {
+    send_json(Method::POST, uri, body)
+}
+
+// Создаем событие и возвращаем его в том виде, в каком его вернул сервер
+async fn create(app: &Router, calendar: &str, date_time: &str, name: &str) -> Value {
+    let res = call(
+        app,
+        post_json(
+            "/create_event",
+            json!({"calendar": calendar, "date_time": date_time, "event_name": name}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    body_json(res).await["result"].clone()
+}
+
+// Названия событий из ответа {"result": [...]}
+fn names(body: &Value) -> Vec<&str> {
+    body["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["name"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn poisoned_pending_deletes_lock_is_recovered() {
+    let (app, state) = server(
+        test_config()
+            .set_override("delete_grace_period_secs", 60)
+            .unwrap(),
+    );
+    create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+
+    // Паника в другом потоке во время удержания блокировки отравляет ее
+    let pending_deletes = state.pending_deletes.clone();
+    let _ = std::thread::spawn(move || {
+        let _guard = pending_deletes.lock().unwrap();
+        panic!("handler panicked while holding the lock");
+    })
+    .join();
+    assert!(state.pending_deletes.is_poisoned());
+
+    let res = call(
+        &app,
+        post_json(
+            "/delete_event",
+            json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": "standup"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+    let undo_id = body_json(res).await["undo_id"].clone();
+    let res = call(&app, post_json("/undo_delete", json!({"id": undo_id}))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn rapid_creates_are_flushed_to_disk() {
+    let (app, state) = server(
+        test_config()
+            .set_override("flush_interval_ms", 50)
+            .unwrap()
+            .set_override("persistence_failure_mode", "keep")
+            .unwrap(),
+    );
+    tokio::spawn(flush_periodically(state.clone()));
+    for i in 0..100 {
+        create(&app, "work", "2024-06-01T10:00:00Z", &format!("event {i}")).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    let saved = storage::load_events(&state.settings.storage_path).unwrap();
+    assert_eq!(saved.len(), 100);
+}
+
+#[tokio::test]
+async fn websocket_receives_created_event() {
+    let (app, _) = server(test_config());
+    // WebSocket требует настоящего соединения, поэтому запускаем сервер на свободном порту
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.clone().into_make_service()),
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/ws"))
+        .await
+        .unwrap();
+
+    let created = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let message = tokio::time::timeout(std::time::Duration::from_secs(5), socket.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let change: Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+    assert_eq!(change["type"], "created");
+    assert_eq!(change["event"]["id"], created["id"]);
+    assert_eq!(change["event"]["name"], "standup");
+}
+
+#[tokio::test]
+async fn create_rejects_existing_id_and_upsert_replaces() {
+    let (app, _) = server(test_config());
+    let id = Uuid::new_v4();
+    let body = json!({
+        "id": id,
+        "calendar": "work",
+        "date_time": "2024-06-01T10:00:00Z",
+        "event_name": "standup",
+    });
+    let res = call(&app, post_json("/create_event", body.clone())).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(body_json(res).await["id"], json!(id));
+
+    // Тот же идентификатор - конфликт, те же дата и название с другим идентификатором допускаются
+    let res = call(&app, post_json("/create_event", body.clone())).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+
+    let mut replacement = body.clone();
+    replacement["event_name"] = json!("retro");
+    let res = call(&app, post_json("/create_event?mode=upsert", replacement)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = call(&app, get(&format!("/event/{id}"))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let event = body_json(res).await["result"].clone();
+    assert_eq!(event["name"], "retro");
+    assert_eq!(event["version"], 2);
+}
+
+#[tokio::test]
+async fn delete_by_ambiguous_name_is_rejected() {
+    let (app, _) = server(test_config());
+    let first = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let second = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let res = call(
+        &app,
+        post_json(
+            "/delete_event",
+            json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": "standup"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    assert_eq!(
+        body_json(res).await["ids"],
+        json!([first["id"], second["id"]])
+    );
+}
+
+fn get_with(uri: &str, name: HeaderName, value: &str) -> Request<Body> {
+    Request::get(uri)
+        .header(name, value)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn conditional_get_uses_etag() {
+    let (app, _) = server(test_config());
+    let event = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let uri = format!("/event/{}", event["id"].as_str().unwrap());
+    let res = call(&app, get(&uri)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+
+    let res = call(&app, get_with(&uri, header::IF_NONE_MATCH, &etag)).await;
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(res.headers()[header::ETAG], etag.as_str());
+    assert!(body_text(res).await.is_empty());
+
+    let res = call(&app, get_with(&uri, header::IF_NONE_MATCH, "\"other\"")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // После изменения события прежний ETag больше не совпадает
+    let replacement = json!({
+        "id": event["id"],
+        "calendar": "work",
+        "date_time": "2024-06-01T10:00:00Z",
+        "event_name": "retro",
+    });
+    let res = call(&app, post_json("/create_event?mode=upsert", replacement)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = call(&app, get_with(&uri, header::IF_NONE_MATCH, &etag)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_ne!(res.headers()[header::ETAG], etag.as_str());
+}
+
+// Ответ со списком событий в формате XML
+#[derive(Deserialize)]
+struct XmlResponse {
+    result: XmlEvents,
+}
+
+#[derive(Deserialize)]
+struct XmlEvents {
+    #[serde(default)]
+    event: Vec<XmlEvent>,
+}
+
+#[derive(Deserialize)]
+struct XmlEvent {
+    id: Uuid,
+    name: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[tokio::test]
+async fn day_query_is_served_as_xml() {
+    let (app, _) = server(test_config());
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-06-01T10:00:00Z",
+                "event_name": "plan & review",
+                "tags": ["team", "weekly"],
+            }),
+        ),
+    )
+    .await;
+    let created = body_json(res).await["result"].clone();
+    create(&app, "work", "2024-06-02T10:00:00Z", "retro").await;
+
+    let uri = "/events_for_day?calendar=work&date=2024-06-01";
+    let res = call(&app, get_with(uri, header::ACCEPT, "application/xml")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "application/xml");
+    let body: XmlResponse = quick_xml::de::from_str(&body_text(res).await).unwrap();
+    assert_eq!(body.result.event.len(), 1);
+    let event = &body.result.event[0];
+    assert_eq!(json!(event.id), created["id"]);
+    assert_eq!(event.name, "plan & review");
+    assert_eq!(event.tags, ["team", "weekly"]);
+
+    let res = call(&app, get_with(uri, header::ACCEPT, "text/plain")).await;
+    assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    let res = call(&app, get(uri)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(names(&body_json(res).await), ["plan & review"]);
+}
+
+#[tokio::test]
+async fn create_beyond_max_events_is_rejected() {
+    let (app, _) = server(test_config().set_override("max_events", 2).unwrap());
+    let first = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    create(&app, "home", "2024-06-01T18:00:00Z", "dinner").await;
+
+    let third =
+        json!({"calendar": "work", "date_time": "2024-06-02T10:00:00Z", "event_name": "retro"});
+    let res = call(&app, post_json("/create_event", third.clone())).await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body_json(res).await["error"], "event limit reached");
+
+    // После удаления события место освобождается
+    let uri = format!("/event/{}", first["id"].as_str().unwrap());
+    let res = call(&app, delete(&uri)).await;
+    assert!(res.status().is_success());
+    let res = call(&app, post_json("/create_event", third)).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+}
+
+fn bulk_item(date_time: &str, name: &str) -> Value {
+    json!({"calendar": "bulk", "date_time": date_time, "event_name": name})
+}
+
+#[tokio::test]
+async fn bulk_create_valid_and_mixed_batches() {
+    let (app, _) = server(test_config());
+    let valid = json!([
+        bulk_item("2024-06-01T10:00:00Z", "first"),
+        bulk_item("2024-06-01T11:00:00Z", "second"),
+    ]);
+    let res = call(&app, post_json("/events/bulk", valid)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    assert_eq!(body["created"].as_array().unwrap().len(), 2);
+    assert_eq!(body["errors"], json!([]));
+
+    // Второе событие без названия
+    let mixed = json!([
+        bulk_item("2024-06-02T10:00:00Z", "third"),
+        {"calendar": "bulk", "date_time": "2024-06-02T11:00:00Z"},
+        bulk_item("2024-06-02T12:00:00Z", "fourth"),
+    ]);
+    for uri in [
+        "/events/bulk?atomic=true",
+        "/events/bulk?rollback_on_error=true",
+    ] {
+        let res = call(&app, post_json(uri, mixed.clone())).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body = body_json(res).await;
+        assert_eq!(body["mode"], "atomic");
+        assert_eq!(body["created"], json!([]));
+        assert_eq!(body["errors"].as_array().unwrap().len(), 1);
+        assert_eq!(body["errors"][0]["index"], 1);
+    }
+    let res = call(&app, get("/events?calendar=bulk")).await;
+    assert_eq!(names(&body_json(res).await), ["first", "second"]);
+
+    let res = call(&app, post_json("/events/bulk", mixed)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    assert_eq!(body["mode"], "best_effort");
+    assert_eq!(body["created"].as_array().unwrap().len(), 2);
+    assert_eq!(body["errors"][0]["index"], 1);
+    let res = call(&app, get("/events?calendar=bulk")).await;
+    assert_eq!(
+        names(&body_json(res).await),
+        ["first", "second", "third", "fourth"]
+    );
+}
+
+#[tokio::test]
+async fn soft_deleted_event_can_be_restored() {
+    let (app, _) = server(test_config().set_override("soft_delete", true).unwrap());
+    let event = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let uri = format!("/event/{}", event["id"].as_str().unwrap());
+    let day = "/events_for_day?calendar=work&date=2024-06-01";
+
+    let res = call(&app, delete(&uri)).await;
+    assert!(res.status().is_success());
+    assert_eq!(call(&app, get(&uri)).await.status(), StatusCode::NOT_FOUND);
+    let res = call(&app, get(day)).await;
+    assert!(names(&body_json(res).await).is_empty());
+
+    let res = call(&app, post_json(&format!("{uri}/restore"), json!({}))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(call(&app, get(&uri)).await.status(), StatusCode::OK);
+    let res = call(&app, get(day)).await;
+    assert_eq!(names(&body_json(res).await), ["standup"]);
+}
+
+#[tokio::test]
+async fn soft_deleted_event_is_purged_after_retention() {
+    let (app, state) = server(
+        test_config()
+            .set_override("soft_delete", true)
+            .unwrap()
+            .set_override("soft_delete_retention_secs", 0)
+            .unwrap(),
+    );
+    let event = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let uri = format!("/event/{}", event["id"].as_str().unwrap());
+    assert!(call(&app, delete(&uri)).await.status().is_success());
+
+    tokio::spawn(purge_deleted(state.clone()));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let res = call(&app, post_json(&format!("{uri}/restore"), json!({}))).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert!(storage::load_events(&state.settings.storage_path)
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn calendars_do_not_share_events() {
+    let (app, _) = server(test_config());
+    create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    create(&app, "personal", "2024-06-01T10:00:00Z", "gym").await;
+
+    let res = call(&app, get("/events_for_day?calendar=work&date=2024-06-01")).await;
+    assert_eq!(names(&body_json(res).await), ["standup"]);
+    let res = call(
+        &app,
+        get("/events_for_day?calendar=personal&date=2024-06-01"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["gym"]);
+    let res = call(
+        &app,
+        get("/events_for_day?calendar=missing&date=2024-06-01"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = call(&app, get("/calendars")).await;
+    assert_eq!(body_json(res).await["result"], json!(["personal", "work"]));
+}
+
+#[tokio::test]
+async fn cors_allows_configured_origin_only() {
+    let (app, _) = server(
+        test_config()
+            .set_override("cors_allowed_origins", vec!["http://localhost:3000"])
+            .unwrap(),
+    );
+    let res = call(
+        &app,
+        get_with("/calendars", header::ORIGIN, "http://localhost:3000"),
+    )
+    .await;
+    assert_eq!(
+        res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "http://localhost:3000"
+    );
+    let res = call(
+        &app,
+        get_with("/calendars", header::ORIGIN, "http://evil.example"),
+    )
+    .await;
+    assert!(!res
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    // Предварительный запрос браузера перед POST
+    let preflight = Request::options("/create_event")
+        .header(header::ORIGIN, "http://localhost:3000")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type")
+        .body(Body::empty())
+        .unwrap();
+    let res = call(&app, preflight).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "http://localhost:3000"
+    );
+    let methods = res.headers()[header::ACCESS_CONTROL_ALLOW_METHODS]
+        .to_str()
+        .unwrap();
+    assert!(methods.contains("POST"));
+}
+
+#[tokio::test]
+async fn openapi_document_lists_create_event() {
+    let (app, _) = server(test_config());
+    let res = call(&app, get("/openapi.json")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let spec = body_json(res).await;
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    assert!(spec["paths"]["/create_event"]["post"].is_object());
+    assert!(spec["components"]["schemas"]["EventReq"].is_object());
+
+    let res = call(&app, get("/docs")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+// Даты событий из ответа {"result": [...]}
+fn dates(body: &Value) -> Vec<&str> {
+    body["result"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|event| event["date"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn weekly_event_expands_across_month_boundary() {
+    let (app, _) = server(test_config());
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-05-27T10:00:00Z",
+                "event_name": "sync",
+                "recurrence": "weekly",
+                "recurrence_until": "2024-06-20T00:00:00Z",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    // Повторение 24 июня позже recurrence_until и не попадает в выборку
+    let res = call(&app, get("/events_for_month?calendar=work&date=2024-06-01")).await;
+    assert_eq!(
+        dates(&body_json(res).await),
+        [
+            "2024-06-03T10:00:00Z",
+            "2024-06-10T10:00:00Z",
+            "2024-06-17T10:00:00Z"
+        ]
+    );
+    let res = call(&app, get("/events_for_month?calendar=work&date=2024-05-01")).await;
+    assert_eq!(dates(&body_json(res).await), ["2024-05-27T10:00:00Z"]);
+    // Неделя с 27 мая по 2 июня начинается в одном месяце и заканчивается в другом
+    let res = call(&app, get("/events_for_week?calendar=work&date=2024-06-01")).await;
+    assert_eq!(dates(&body_json(res).await), ["2024-05-27T10:00:00Z"]);
+    let res = call(&app, get("/events_for_week?calendar=work&date=2024-06-05")).await;
+    assert_eq!(dates(&body_json(res).await), ["2024-06-03T10:00:00Z"]);
+}
+
+#[tokio::test]
+async fn range_query_includes_both_bounds() {
+    let (app, _) = server(test_config());
+    create(&app, "work", "2024-06-30T23:00:00Z", "last day").await;
+    create(&app, "work", "2024-06-01T00:00:00Z", "first day").await;
+    create(&app, "work", "2024-07-01T00:00:00Z", "next month").await;
+    create(&app, "work", "2024-05-31T23:59:59Z", "previous month").await;
+
+    let res = call(
+        &app,
+        get("/events_in_range?calendar=work&from=2024-06-01&to=2024-06-30"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(names(&body_json(res).await), ["first day", "last day"]);
+
+    let res = call(
+        &app,
+        get("/events_in_range?calendar=work&from=2024-01-01&to=2024-01-31"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(names(&body_json(res).await).is_empty());
+
+    let res = call(
+        &app,
+        get("/events_in_range?calendar=work&from=2024-06-30&to=2024-06-01"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = call(
+        &app,
+        get("/events_in_range?calendar=work&from=2024-06-01&to=june"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn update_finds_event_by_equivalent_offset() {
+    let (app, _) = server(test_config());
+    let event = create(&app, "work", "2024-01-01T12:00:00Z", "standup").await;
+
+    // То же мгновение, записанное со смещением -05:00
+    let res = call(
+        &app,
+        post_json(
+            "/update_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-01-01T07:00:00-05:00",
+                "event_name": "standup",
+                "new_date_time": "2024-01-02T12:00:00+03:00",
+                "new_event_name": "retro",
+                "version": 1,
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = call(
+        &app,
+        get(&format!("/event/{}", event["id"].as_str().unwrap())),
+    )
+    .await;
+    let updated = body_json(res).await["result"].clone();
+    assert_eq!(updated["name"], "retro");
+    assert_eq!(updated["date"], "2024-01-02T09:00:00Z");
+    assert_eq!(updated["version"], 2);
+}
+
+// Буфер, в который тест записывает строки лога
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn slow_request_latency_is_logged_and_measured() {
+    let logs = LogBuffer::default();
+    let writer = logs.clone();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_writer(move || writer.clone())
+        .finish();
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let metrics = Arc::new(Metrics::default());
+    let app = Router::new()
+        .route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                "done"
+            }),
+        )
+        .layer(middleware::from_fn_with_state(
+            metrics.clone(),
+            metrics::record_metrics,
+        ))
+        .layer(middleware::from_fn(log_request));
+    let res = call(&app, get("/slow")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Время обработки включает работу обработчика и не округляется до целых миллисекунд
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    let line: Value = logs
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|line| line["fields"]["message"] == "log_request")
+        .unwrap();
+    let latency_ms = line["fields"]["latency_ms"].as_f64().unwrap();
+    assert!(latency_ms >= 20.0);
+    assert_ne!(latency_ms.fract(), 0.0);
+
+    let rendered = metrics.render();
+    let seconds: f64 = rendered
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix("http_request_duration_seconds_sum{method=\"GET\",route=\"/slow\"} ")
+        })
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(seconds >= 0.02);
+}
+
+#[tokio::test]
+async fn ndjson_stream_matches_buffered_range() {
+    let (app, _) = server(test_config());
+    create(&app, "work", "2024-06-12T09:00:00Z", "review").await;
+    create(&app, "work", "2024-06-03T08:00:00Z", "planning").await;
+    // Событие на несколько дней попадает в каждое окно, но в потоке должно появиться один раз
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-05-30T10:00:00Z",
+                "end_date_time": "2024-06-04T10:00:00Z",
+                "event_name": "offsite",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-05-27T10:00:00Z",
+                "event_name": "sync",
+                "recurrence": "weekly",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    for query in ["", "&sort=date_desc"] {
+        let uri = format!("/events_in_range?calendar=work&from=2024-06-01&to=2024-06-30{query}");
+        let buffered = body_json(call(&app, get(&uri)).await).await;
+        let res = call(&app, get_with(&uri, header::ACCEPT, "application/x-ndjson")).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.headers().get("x-total-count").is_none());
+        let streamed: Vec<Value> = body_text(res)
+            .await
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(streamed.len(), 7);
+        assert_eq!(json!(streamed), buffered["result"]);
+    }
+
+    // С limit поток обрезается, а общее количество считается отдельным проходом
+    let res = call(
+        &app,
+        get_with(
+            "/events_for_month?calendar=work&date=2024-06-01&limit=3",
+            header::ACCEPT,
+            "application/x-ndjson",
+        ),
+    )
+    .await;
+    assert_eq!(res.headers()["x-total-count"], "7");
+    let lines: Vec<Value> = body_text(res)
+        .await
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let dates: Vec<&str> = lines
+        .iter()
+        .map(|event| event["date"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        dates,
+        [
+            "2024-05-30T10:00:00Z",
+            "2024-06-03T08:00:00Z",
+            "2024-06-03T10:00:00Z"
+        ]
+    );
+
+    let res = call(
+        &app,
+        get_with(
+            "/events_for_day?calendar=missing&date=2024-06-01",
+            header::ACCEPT,
+            "application/x-ndjson",
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn poisoned_rate_limiter_lock_is_recovered() {
+    let (app, state) = server(
+        test_config()
+            .set_override("global_rate_limit_per_sec", 100)
+            .unwrap(),
+    );
+    let rate_limiter = state.rate_limiter.clone().unwrap();
+    let _ = std::thread::spawn(move || {
+        let _guard = rate_limiter.lock().unwrap();
+        panic!("handler panicked while holding the lock");
+    })
+    .join();
+    assert!(state.rate_limiter.as_ref().unwrap().is_poisoned());
+
+    let res = call(&app, get("/calendars")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(!state.rate_limiter.as_ref().unwrap().is_poisoned());
+}
+
+#[tokio::test]
+async fn version_conflict_returns_current_event_unless_minimal() {
+    let (app, _) = server(test_config());
+    let created = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let update = |name: &str, prefer: Option<&str>| {
+        let mut req =
+            Request::post("/update_event").header(header::CONTENT_TYPE, "application/json");
+        if let Some(prefer) = prefer {
+            req = req.header("prefer", prefer);
+        }
+        req.body(Body::from(
+            json!({
+                "calendar": "work",
+                "date_time": "2024-06-01T10:00:00Z",
+                "new_date_time": "2024-06-01T10:00:00Z",
+                "event_name": "standup",
+                "new_event_name": name,
+                "version": 1,
+            })
+            .to_string(),
+        ))
+        .unwrap()
+    };
+    let res = call(&app, update("standup", None)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // Устаревший маршрут тоже возвращает текущее событие
+    let res = call(&app, update("standup", None)).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let body = body_json(res).await;
+    assert_eq!(body["version"], 2);
+    assert_eq!(body["current"]["id"], created["id"]);
+    assert_eq!(body["current"]["version"], 2);
+
+    let res = call(&app, update("standup", Some("return=minimal"))).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    assert_eq!(res.headers()["preference-applied"], "return=minimal");
+    let body = body_json(res).await;
+    assert_eq!(body["version"], 2);
+    assert!(body.get("current").is_none());
+
+    // PUT /event/{id} учитывает тот же заголовок
+    let id = created["id"].as_str().unwrap();
+    let req = Request::put(format!("/event/{id}"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("prefer", "respond-async, return=representation")
+        .body(Body::from(
+            json!({"calendar": "work", "date_time": "2024-06-02T10:00:00Z", "event_name": "standup", "version": 1})
+                .to_string(),
+        ))
+        .unwrap();
+    let res = call(&app, req).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    assert_eq!(res.headers()["preference-applied"], "return=representation");
+    assert_eq!(body_json(res).await["current"]["version"], 2);
+}
+
+#[tokio::test]
+async fn busy_time_merges_or_sums_overlaps() {
+    let (app, _) = server(test_config());
+    for (start, end, name) in [
+        // Понедельник: 10:00-12:00 и пересекающееся 11:00-13:00 - три часа без повторов
+        ("2024-06-03T10:00:00Z", "2024-06-03T12:00:00Z", "review"),
+        ("2024-06-03T11:00:00Z", "2024-06-03T13:00:00Z", "planning"),
+        // Воскресенье 23:00 - понедельник 01:00 следующей недели: в неделю попадает только час
+        ("2024-06-09T23:00:00Z", "2024-06-10T01:00:00Z", "release"),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "work", "date_time": start, "end_date_time": end, "event_name": name}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+    // Событие без окончания время не занимает
+    create(&app, "work", "2024-06-04T09:00:00Z", "ping").await;
+
+    let res = call(
+        &app,
+        get("/busy_time?calendar=work&period=week&date=2024-06-05"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    assert_eq!(body["from"], "2024-06-03");
+    assert_eq!(body["to"], "2024-06-09");
+    assert_eq!(body["overlaps"], "merge");
+    assert_eq!(body["events"], 3);
+    assert_eq!(body["busy_minutes"], 4 * 60);
+
+    let res = call(
+        &app,
+        get("/busy_time?calendar=work&period=week&date=2024-06-05&overlaps=sum"),
+    )
+    .await;
+    assert_eq!(body_json(res).await["busy_minutes"], 5 * 60);
+
+    // Граница периода считается в часовом поясе запроса: в Берлине 10 июня начинается в 22:00 UTC
+    let res = call(
+        &app,
+        get("/busy_time?calendar=work&period=day&date=2024-06-10"),
+    )
+    .await;
+    assert_eq!(body_json(res).await["busy_minutes"], 60);
+    let res = call(
+        &app,
+        get("/busy_time?calendar=work&period=day&date=2024-06-10&tz=Europe/Berlin"),
+    )
+    .await;
+    assert_eq!(body_json(res).await["busy_minutes"], 120);
+
+    let res = call(&app, get("/busy_time?calendar=work&date=2024-06-05")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = call(
+        &app,
+        get("/busy_time?calendar=work&period=decade&date=2024-06-05"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn events_are_filtered_by_duration() {
+    let (app, _) = server(test_config());
+    for (start, end, name) in [
+        ("2024-06-03T10:00:00Z", "2024-06-03T10:30:00Z", "sync"),
+        ("2024-06-03T11:00:00Z", "2024-06-03T12:00:00Z", "review"),
+        ("2024-06-04T09:00:00Z", "2024-06-04T17:00:00Z", "workshop"),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "work", "date_time": start, "end_date_time": end, "event_name": name}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+    create(&app, "work", "2024-06-05T09:00:00Z", "ping").await;
+
+    // Границы включаются
+    let res = call(&app, get("/events/by_duration?min=60&max=240")).await;
+    assert_eq!(names(&body_json(res).await), ["review"]);
+    let res = call(&app, get("/events/by_duration?min=241")).await;
+    assert_eq!(names(&body_json(res).await), ["workshop"]);
+    // Событие без окончания - нулевой длительности
+    let res = call(&app, get("/events/by_duration?max=30&calendar=work")).await;
+    assert_eq!(names(&body_json(res).await), ["sync", "ping"]);
+    let res = call(&app, get("/events/by_duration?min=0&max=0")).await;
+    assert_eq!(names(&body_json(res).await), ["ping"]);
+
+    let res = call(&app, get("/events/by_duration?min=60&max=30")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = call(&app, get("/events/by_duration?min=-5")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn admin_reindex_requires_configured_key() {
+    let reindex = |key: Option<&str>| {
+        let mut req = Request::post("/admin/reindex");
+        if let Some(key) = key {
+            req = req.header(API_KEY_HEADER, key);
+        }
+        req.body(Body::empty()).unwrap()
+    };
+    // Без ключа в настройках служебные маршруты отключены
+    let (app, _) = server(test_config());
+    let res = call(&app, reindex(None)).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    let (app, state) = server(test_config().set_override("api_key", "secret").unwrap());
+    for name in ["standup", "review"] {
+        let date = "2024-06-03T10:00:00Z".parse().unwrap();
+        let event = Event::new("work".to_string(), date, name.to_string());
+        state.store.insert(event).await.unwrap();
+    }
+    let res = call(&app, reindex(Some("wrong"))).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = call(&app, reindex(Some("secret"))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    assert_eq!(
+        body["result"],
+        json!({"calendars": 1, "events": 2, "fixed": 0, "problems": []})
+    );
+}
+
+#[tokio::test]
+async fn debug_dump_is_gated_and_redacts_secrets() {
+    // Без debug_endpoints маршрута нет даже с верным ключом
+    let (app, _) = server(test_config().set_override("api_key", "secret").unwrap());
+    let res = call(
+        &app,
+        get_with(
+            "/debug/dump",
+            HeaderName::from_static(API_KEY_HEADER),
+            "secret",
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let (app, _) = server(
+        test_config()
+            .set_override("api_key", "secret")
+            .unwrap()
+            .set_override("debug_endpoints", true)
+            .unwrap(),
+    );
+    let res = call(&app, get("/debug/dump")).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let mut req = post_json(
+        "/create_event",
+        json!({"calendar": "work", "date_time": "2024-06-03T10:00:00Z", "event_name": "standup"}),
+    );
+    req.headers_mut()
+        .insert(API_KEY_HEADER, HeaderValue::from_static("secret"));
+    assert_eq!(call(&app, req).await.status(), StatusCode::CREATED);
+
+    let res = call(
+        &app,
+        get_with(
+            "/debug/dump",
+            HeaderName::from_static(API_KEY_HEADER),
+            "secret",
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let text = body_text(res).await;
+    assert!(!text.contains("secret"));
+    let body: Value = serde_json::from_str(&text).unwrap();
+    let dump = &body["result"];
+    assert_eq!(dump["settings"]["api_key"], "[redacted]");
+    assert_eq!(dump["store"]["events"], 1);
+    assert_eq!(dump["store"]["indexes"]["ids"], 1);
+    assert_eq!(dump["store"]["read_only"], false);
+    assert!(dump["store"]["last_mutation"].is_string());
+    assert_eq!(dump["recent_audit"].as_array().unwrap().len(), 1);
+    assert_eq!(dump["recent_audit"][0]["operation"], "create");
+}
+
+#[tokio::test]
+async fn events_are_found_by_location() {
+    let (app, _) = server(test_config());
+    for (date, name, location) in [
+        ("2024-06-03T10:00:00Z", "planning", Some("Room A")),
+        ("2024-06-20T10:00:00Z", "retro", Some("room a, 3rd floor")),
+        ("2024-07-01T10:00:00Z", "offsite", Some("Room A")),
+        ("2024-06-04T10:00:00Z", "review", Some("Room B")),
+        ("2024-06-05T10:00:00Z", "call", None),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "work", "date_time": date, "event_name": name, "location": location}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    let res = call(&app, get("/events/by_location?q=ROOM%20A")).await;
+    assert_eq!(
+        names(&body_json(res).await),
+        ["planning", "retro", "offsite"]
+    );
+    let res = call(
+        &app,
+        get("/events/by_location?q=room%20a&from=2024-06-01&to=2024-06-30"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["planning", "retro"]);
+
+    for uri in [
+        "/events/by_location",
+        "/events/by_location?q=",
+        "/events/by_location?q=%20",
+    ] {
+        let res = call(&app, get(uri)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn recurrence_preview_lists_dates_without_storing() {
+    let (app, _) = server(test_config().set_override("max_range_days", 31).unwrap());
+    let res = call(
+        &app,
+        post_json(
+            "/preview_recurrence",
+            json!({
+                "date_time": "2024-01-31T09:00:00Z",
+                "recurrence": "monthly",
+                "recurrence_until": "2024-05-01T00:00:00Z",
+                "from": "2024-02-01",
+                "to": "2024-03-01",
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    // Повторения считаются так же, как в выборках: в феврале - последний день месяца
+    assert_eq!(body["result"], json!(["2024-02-29T09:00:00Z"]));
+    assert_eq!(body["count"], 1);
+
+    let res = call(&app, get("/events")).await;
+    assert_eq!(body_json(res).await["total"], 0);
+
+    // Окно ограничено так же, как диапазон выборок
+    let res = call(
+        &app,
+        post_json(
+            "/preview_recurrence",
+            json!({"date_time": "2024-01-01T09:00:00Z", "recurrence": "daily", "from": "2024-01-01", "to": "2024-12-31"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = call(
+        &app,
+        post_json(
+            "/preview_recurrence",
+            json!({"date_time": "2024-01-01T09:00:00Z", "from": "2024-01-01", "to": "2024-01-07"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let errors = body_json(res).await;
+    assert_eq!(errors["errors"][0]["field"], "recurrence");
+}
+
+#[tokio::test]
+async fn recurrence_keeps_local_time_across_dst() {
+    let (app, _) = server(test_config());
+    for (calendar, date, recurrence, tz) in [
+        // 09:00 по Берлину до перехода на летнее время 31 марта
+        (
+            "berlin",
+            "2024-03-25T08:00:00Z",
+            "weekly",
+            Some("Europe/Berlin"),
+        ),
+        ("utc", "2024-03-25T08:00:00Z", "weekly", None),
+        // 09:00 по Берлину до перехода на зимнее время 27 октября
+        (
+            "autumn",
+            "2024-10-26T07:00:00Z",
+            "daily",
+            Some("Europe/Berlin"),
+        ),
+        // 02:30 по Берлину: 31 марта такого времени нет
+        (
+            "gap",
+            "2024-03-30T01:30:00Z",
+            "daily",
+            Some("Europe/Berlin"),
+        ),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": calendar, "date_time": date, "event_name": "standup", "recurrence": recurrence, "tz": tz}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    let range = |calendar: &str, from: &str, to: &str| {
+        get(&format!(
+            "/events_in_range?calendar={calendar}&from={from}&to={to}"
+        ))
+    };
+    let res = call(&app, range("berlin", "2024-03-25", "2024-04-08")).await;
+    assert_eq!(
+        dates(&body_json(res).await),
+        [
+            "2024-03-25T08:00:00Z",
+            "2024-04-01T07:00:00Z",
+            "2024-04-08T07:00:00Z"
+        ]
+    );
+    // Без часового пояса повторения идут с шагом по UTC
+    let res = call(&app, range("utc", "2024-03-25", "2024-04-08")).await;
+    assert_eq!(
+        dates(&body_json(res).await),
+        [
+            "2024-03-25T08:00:00Z",
+            "2024-04-01T08:00:00Z",
+            "2024-04-08T08:00:00Z"
+        ]
+    );
+    let res = call(&app, range("autumn", "2024-10-26", "2024-10-28")).await;
+    assert_eq!(
+        dates(&body_json(res).await),
+        [
+            "2024-10-26T07:00:00Z",
+            "2024-10-27T08:00:00Z",
+            "2024-10-28T08:00:00Z"
+        ]
+    );
+    // Пропущенное время сдвигается на час позже, следующие повторения снова в 02:30
+    let res = call(&app, range("gap", "2024-03-30", "2024-04-01")).await;
+    assert_eq!(
+        dates(&body_json(res).await),
+        [
+            "2024-03-30T01:30:00Z",
+            "2024-03-31T01:30:00Z",
+            "2024-04-01T00:30:00Z"
+        ]
+    );
+
+    // В iCalendar даты повторяющегося события записываются в его часовом поясе
+    let res = call(&app, get("/events.ics?calendar=berlin")).await;
+    assert!(body_text(res)
+        .await
+        .contains("DTSTART;TZID=Europe/Berlin:20240325T090000"));
+
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({"calendar": "work", "date_time": "2024-03-25T08:00:00Z", "event_name": "standup", "tz": "Mars/Olympus"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn ics_export_is_filtered_by_categories() {
+    let (app, _) = server(test_config());
+    for (name, tags) in [
+        ("standup", json!(["Work"])),
+        ("dentist", json!(["health"])),
+        ("gym", json!(["health", "personal"])),
+        ("party", json!([])),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "main", "date_time": "2024-06-03T10:00:00Z", "event_name": name, "tags": tags}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+    let summaries = |ics: String| -> Vec<String> {
+        ics.lines()
+            .filter_map(|line| line.strip_prefix("SUMMARY:"))
+            .map(str::to_string)
+            .collect()
+    };
+
+    let res = call(&app, get("/events.ics?category=WORK")).await;
+    assert_eq!(summaries(body_text(res).await), ["standup"]);
+    // Несколько категорий объединяются, событие с двумя подходящими тегами попадает один раз
+    let res = call(&app, get("/events.ics?category=work,health,personal")).await;
+    assert_eq!(
+        summaries(body_text(res).await),
+        ["standup", "dentist", "gym"]
+    );
+    let res = call(&app, get("/events.ics")).await;
+    assert_eq!(summaries(body_text(res).await).len(), 4);
+
+    let res = call(&app, get("/events.ics?category=,")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn period_queries_exclude_tags() {
+    let (app, _) = server(test_config());
+    for (date, name, tags) in [
+        ("2024-06-03T09:00:00Z", "standup", json!(["work"])),
+        ("2024-06-04T09:00:00Z", "review", json!(["work", "urgent"])),
+        ("2024-06-05T09:00:00Z", "dentist", json!(["health"])),
+        ("2024-06-06T09:00:00Z", "party", json!([])),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "main", "date_time": date, "event_name": name, "tags": tags}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    let res = call(
+        &app,
+        get("/events_for_week?calendar=main&date=2024-06-03&exclude_categories=Work"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["dentist", "party"]);
+    let res = call(
+        &app,
+        get("/events_in_range?calendar=main&from=2024-06-01&to=2024-06-30&exclude_tags=urgent,health"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["standup", "party"]);
+    // Сначала применяется тег, затем исключения
+    let res = call(
+        &app,
+        get("/events_for_month?calendar=main&date=2024-06-01&tag=work&exclude_tags=urgent"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["standup"]);
+    let res = call(
+        &app,
+        get("/events_for_days?calendar=main&dates=2024-06-03,2024-06-04&exclude_tags=urgent"),
+    )
+    .await;
+    let body = body_json(res).await;
+    assert_eq!(body["result"]["2024-06-03"][0]["name"], "standup");
+    assert_eq!(body["result"]["2024-06-04"], json!([]));
+}
+
+#[tokio::test]
+async fn events_closer_than_min_gap_conflict() {
+    let create_meeting = |start: &str, end: Option<&str>, name: &str| {
+        post_json(
+            "/create_event?reject_overlap=true",
+            json!({"calendar": "work", "date_time": start, "end_date_time": end, "event_name": name}),
+        )
+    };
+    // Без промежутка события могут идти вплотную
+    let (app, _) = server(test_config());
+    let res = call(
+        &app,
+        create_meeting("2024-06-03T10:00:00Z", Some("2024-06-03T11:00:00Z"), "sync"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let res = call(
+        &app,
+        create_meeting(
+            "2024-06-03T11:00:00Z",
+            Some("2024-06-03T12:00:00Z"),
+            "review",
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+
+    let (app, _) = server(test_config().set_override("min_gap_minutes", 15).unwrap());
+    let res = call(
+        &app,
+        create_meeting("2024-06-03T10:00:00Z", Some("2024-06-03T11:00:00Z"), "sync"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let sync = body_json(res).await["result"].clone();
+    let res = call(
+        &app,
+        create_meeting(
+            "2024-06-03T11:10:00Z",
+            Some("2024-06-03T12:00:00Z"),
+            "review",
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let body = body_json(res).await;
+    assert_eq!(body["overlaps"], json!([sync["id"]]));
+    assert_eq!(body["conflicts"][0]["name"], "sync");
+    // Событие без окончания тоже должно отстоять от других на промежуток
+    let res = call(&app, create_meeting("2024-06-03T09:50:00Z", None, "ping")).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    // Ровно через промежуток - можно
+    let res = call(
+        &app,
+        create_meeting(
+            "2024-06-03T11:15:00Z",
+            Some("2024-06-03T12:00:00Z"),
+            "review",
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn exports_are_served_by_range() {
+    let (app, _) = server(test_config());
+    for (date, name) in [
+        ("2024-06-03T09:00:00Z", "standup"),
+        ("2024-06-04T09:00:00Z", "review"),
+    ] {
+        create(&app, "main", date, name).await;
+    }
+    let res = call(
+        &app,
+        get_with("/events.csv", header::ACCEPT_ENCODING, "gzip"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::ACCEPT_RANGES], "bytes");
+    // Выгрузки не сжимаются, иначе диапазоны байтов не совпали бы с телом
+    assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+    let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+    let csv = body_text(res).await;
+
+    let res = call(&app, get_with("/events.csv", header::RANGE, "bytes=10-")).await;
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        res.headers()[header::CONTENT_RANGE].to_str().unwrap(),
+        format!("bytes 10-{}/{}", csv.len() - 1, csv.len())
+    );
+    assert_eq!(body_text(res).await, csv[10..]);
+    let res = call(&app, get_with("/events.csv", header::RANGE, "bytes=-5")).await;
+    assert_eq!(body_text(res).await, csv[csv.len() - 5..]);
+
+    let range = format!("bytes={}-", csv.len());
+    let res = call(&app, get_with("/events.csv", header::RANGE, &range)).await;
+    assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(
+        res.headers()[header::CONTENT_RANGE].to_str().unwrap(),
+        format!("bytes */{}", csv.len())
+    );
+
+    // Выгрузка iCalendar одинакова между запросами, поэтому ее тоже можно докачивать
+    let ics = body_text(call(&app, get("/events.ics")).await).await;
+    let res = call(&app, get_with("/events.ics", header::RANGE, "bytes=0-9")).await;
+    assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body_text(res).await, ics[..10]);
+
+    // После изменения событий старый ETag в If-Range не совпадает, и возвращается вся выгрузка
+    create(&app, "main", "2024-06-05T09:00:00Z", "retro").await;
+    let req = Request::get("/events.csv")
+        .header(header::RANGE, "bytes=10-")
+        .header(header::IF_RANGE, etag.as_str())
+        .body(Body::empty())
+        .unwrap();
+    let res = call(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(body_text(res).await.contains("retro"));
+}
+
+#[tokio::test]
+async fn audit_replay_reports_and_repairs_divergence() {
+    let (app, state) = server(test_config().set_override("api_key", "secret").unwrap());
+    let with_key = |mut req: Request<Body>| {
+        req.headers_mut()
+            .insert(API_KEY_HEADER, HeaderValue::from_static("secret"));
+        req
+    };
+    let mut ids = Vec::new();
+    for name in ["standup", "review", "retro"] {
+        let body =
+            json!({"calendar": "work", "date_time": "2024-06-03T10:00:00Z", "event_name": name});
+        let res = call(&app, with_key(post_json("/create_event", body))).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let id: Uuid = body_json(res).await["result"]["id"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        ids.push(id);
+    }
+    let replay = |uri: &'static str| with_key(Request::post(uri).body(Body::empty()).unwrap());
+    let res = call(&app, replay("/admin/replay")).await;
+    let body = body_json(res).await;
+    assert_eq!(body["result"]["entries"], 3);
+    assert_eq!(body["result"]["missing"], json!([]));
+    assert_eq!(body["result"]["different"], json!([]));
+
+    // Изменения в обход API журнал не видит: событие пропало, появилось лишнее, одно изменено
+    state.store.remove(ids[0]).await.unwrap();
+    let mut changed = state.store.find(ids[2]).await.unwrap().unwrap();
+    changed.name = "renamed".to_string();
+    state.store.update(changed, 1).await.unwrap();
+    let date = "2024-06-04T10:00:00Z".parse().unwrap();
+    let extra = Event::new("work".to_string(), date, "extra".to_string());
+    let extra_id = extra.id;
+    state.store.insert(extra).await.unwrap();
+
+    let res = call(&app, replay("/admin/replay?apply=true")).await;
+    let body = body_json(res).await;
+    assert_eq!(body["result"]["missing"], json!([ids[0]]));
+    assert_eq!(body["result"]["unexpected"], json!([extra_id]));
+    assert_eq!(body["result"]["different"], json!([ids[2]]));
+    assert_eq!(body["result"]["problems"], json!([]));
+    assert_eq!(body["result"]["applied"], true);
+
+    // После применения хранилище совпадает с журналом
+    let res = call(&app, replay("/admin/replay")).await;
+    let body = body_json(res).await;
+    for field in ["missing", "unexpected", "different", "problems"] {
+        assert_eq!(body["result"][field], json!([]), "{}", field);
+    }
+    let restored = state.store.find(ids[2]).await.unwrap().unwrap();
+    assert_eq!(restored.name, "retro");
+}
+
+#[tokio::test]
+async fn null_fields_are_omitted_unless_configured() {
+    let (app, _) = server(test_config());
+    let event = create(&app, "work", "2024-06-03T10:00:00Z", "standup").await;
+    assert!(event.get("description").is_none());
+    assert!(event.get("end_date").is_none());
+
+    let (app, state) = server(
+        test_config()
+            .set_override("include_null_fields", true)
+            .unwrap(),
+    );
+    let event = create(&app, "work", "2024-06-03T10:00:00Z", "standup").await;
+    for field in ["end_date", "recurrence", "tz", "description", "location"] {
+        assert_eq!(event[field], Value::Null, "{}", field);
+    }
+    let uri = format!("/event/{}", event["id"].as_str().unwrap());
+    let body = body_json(call(&app, get(&uri)).await).await;
+    assert_eq!(body["result"]["location"], Value::Null);
+    assert!(body["result"].as_object().unwrap().contains_key("location"));
+    // Файл событий остается без null
+    state.store.flush().await.unwrap();
+    let data = std::fs::read_to_string(&state.settings.storage_path).unwrap();
+    assert!(!data.contains("null"));
+}
+
+#[tokio::test]
+async fn rss_items_use_event_description() {
+    let (app, _) = server(test_config());
+    let body = json!({"calendar": "work", "date_time": "2099-06-03T10:00:00Z", "event_name": "standup", "description": "Daily sync & notes"});
+    let res = call(&app, post_json("/create_event", body)).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    create(&app, "work", "2099-06-04T10:00:00Z", "review").await;
+
+    let res = call(&app, get("/events.rss")).await;
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "application/rss+xml");
+    let rss = body_text(res).await;
+    assert!(rss.contains("<description>Daily sync &amp; notes</description>"));
+    // Без описания остаются название и дата
+    assert!(rss.contains(
+        "<description>Event &apos;review&apos; for date 2099-06-04 10:00:00 UTC</description>"
+    ));
+}
+
+#[tokio::test]
+async fn csv_export_quotes_names_with_commas() {
+    let (app, _) = server(test_config());
+    let event = create(&app, "work", "2024-06-03T10:00:00Z", "Smith, John \"JJ\"").await;
+    let res = call(&app, get("/events.csv")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let csv = body_text(res).await;
+    let lines: Vec<&str> = csv.split("\r\n").collect();
+    assert_eq!(
+        lines,
+        [
+            "id,date,name",
+            &format!(
+                "{},2024-06-03T10:00:00Z,\"Smith, John \"\"JJ\"\"\"",
+                event["id"].as_str().unwrap()
+            ),
+            ""
+        ]
+    );
+
+    // Разбор по правилам RFC 4180: запятая внутри кавычек не разделяет ячейки
+    let mut cells = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = lines[1].chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cells.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(String::new()),
+            c => cells.last_mut().unwrap().push(c),
+        }
+    }
+    assert_eq!(cells.len(), 3);
+    assert_eq!(cells[2], "Smith, John \"JJ\"");
+}
+
+fn post_text(uri: &str, body: String) -> Request<Body> {
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, "text/calendar")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn ics_export_round_trips_through_import() {
+    let (app, _) = server(test_config());
+    let name = "Lunch, team; notes\nsecond line";
+    create(&app, "work", "2024-06-03T10:15:00Z", name).await;
+
+    let ics = body_text(call(&app, get("/events.ics?calendar=work")).await).await;
+    let lines: Vec<&str> = ics.split("\r\n").collect();
+    // Время начала записывается в UTC, спецсимволы в названии экранируются
+    assert!(lines.contains(&"DTSTART:20240603T101500Z"));
+    assert!(lines.contains(&r"SUMMARY:Lunch\, team\; notes\nsecond line"));
+
+    let res = call(&app, post_text("/import/ics?calendar=copy", ics)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_json(res).await["imported"], 1);
+    let res = call(
+        &app,
+        get("/events_in_range?calendar=copy&from=2024-06-03&to=2024-06-03"),
+    )
+    .await;
+    let body = body_json(res).await;
+    assert_eq!(dates(&body), ["2024-06-03T10:15:00Z"]);
+    assert_eq!(names(&body), [name]);
+}
+
+#[tokio::test]
+async fn ics_import_skips_existing_events() {
+    let (app, _) = server(test_config());
+    create(&app, "work", "2024-06-03T10:00:00Z", "standup").await;
+    let ics = [
+        "BEGIN:VCALENDAR",
+        "VERSION:2.0",
+        "BEGIN:VEVENT",
+        "DTSTART:20240603T100000Z",
+        "SUMMARY:standup",
+        "END:VEVENT",
+        "BEGIN:VEVENT",
+        "DTSTART:20240604T120000Z",
+        "SUMMARY:review",
+        "END:VEVENT",
+        "END:VCALENDAR",
+        "",
+    ]
+    .join("\r\n");
+    let res = call(&app, post_text("/import/ics?calendar=work", ics)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    assert_eq!(body["imported"], 1);
+    assert_eq!(body["skipped"], 1);
+    let res = call(
+        &app,
+        get("/events_in_range?calendar=work&from=2024-06-01&to=2024-06-30"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["standup", "review"]);
+
+    // Некорректный файл отклоняется целиком
+    let ics =
+        "BEGIN:VCALENDAR\r\nBEGIN:VEVENT\r\nSUMMARY:broken\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n";
+    let res = call(
+        &app,
+        post_text("/import/ics?calendar=work", ics.to_string()),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert!(body_json(res).await["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("invalid iCalendar"));
+}
+
+#[tokio::test]
+async fn idempotent_create_stores_one_event() {
+    let (app, state) = server(test_config());
+    let create_with_key = |body: Value| {
+        let mut req = post_json("/create_event", body);
+        req.headers_mut()
+            .insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_static("retry-1"));
+        req
+    };
+    // Некорректный запрос отклоняется, не занимая ключ
+    let res = call(&app, create_with_key(json!({"calendar": "work"}))).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let body =
+        json!({"calendar": "work", "date_time": "2024-06-03T10:00:00Z", "event_name": "standup"});
+    let res = call(&app, create_with_key(body.clone())).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let first = body_json(res).await;
+    let res = call(&app, create_with_key(body)).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(res.headers()["idempotent-replayed"], "true");
+    assert_eq!(body_json(res).await["id"], first["id"]);
+    assert_eq!(state.store.list(None).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn stale_update_is_rejected() {
+    let (app, _) = server(test_config());
+    let created = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let update = |name: &str, version: Option<u64>| {
+        let mut body = json!({
+            "calendar": "work",
+            "date_time": "2024-06-01T10:00:00Z",
+            "new_date_time": "2024-06-01T10:00:00Z",
+            "event_name": "standup",
+            "new_event_name": name,
+        });
+        if let Some(version) = version {
+            body["version"] = json!(version);
+        }
+        post_json("/update_event", body)
+    };
+    // Без версии изменение отклоняется
+    let res = call(&app, update("standup", None)).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(body_json(res).await["errors"][0]["field"], "version");
+
+    // Оба клиента прочитали версию 1, первый успевает изменить событие
+    let res = call(&app, update("standup", Some(1))).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_json(res).await["result"]["version"], 2);
+    let res = call(&app, update("planning", Some(1))).await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    let body = body_json(res).await;
+    assert_eq!(body["error"], "version mismatch");
+    assert_eq!(body["version"], 2);
+
+    let uri = format!("/event/{}", created["id"].as_str().unwrap());
+    let body = body_json(call(&app, get(&uri)).await).await;
+    assert_eq!(body["result"]["name"], "standup");
+    assert_eq!(body["result"]["version"], 2);
+}
+
+#[tokio::test]
+async fn midnight_event_follows_midnight_boundary() {
+    let count = |body: Value| body["result"].as_array().unwrap().len();
+    // По умолчанию событие ровно в полночь относится к начинающемуся дню
+    let (app, _) = server(test_config());
+    create(&app, "work", "2024-06-01T00:00:00Z", "midnight").await;
+    for (uri, expected) in [
+        ("/events_for_day?calendar=work&date=2024-06-01", 1),
+        ("/events_for_day?calendar=work&date=2024-05-31", 0),
+        ("/events_for_week?calendar=work&date=2024-06-01", 1),
+        ("/events_for_month?calendar=work&date=2024-06-01", 1),
+        ("/events_for_month?calendar=work&date=2024-05-31", 0),
+    ] {
+        let body = body_json(call(&app, get(uri)).await).await;
+        assert_eq!(count(body), expected, "{}", uri);
+    }
+
+    // С midnight_boundary=end - к завершившемуся дню, неделе и месяцу
+    let (app, _) = server(
+        test_config()
+            .set_override("midnight_boundary", "end")
+            .unwrap(),
+    );
+    create(&app, "work", "2024-06-01T00:00:00Z", "midnight").await;
+    for (uri, expected) in [
+        ("/events_for_day?calendar=work&date=2024-06-01", 0),
+        ("/events_for_day?calendar=work&date=2024-05-31", 1),
+        ("/events_for_week?calendar=work&date=2024-05-31", 1),
+        ("/events_for_month?calendar=work&date=2024-06-01", 0),
+        ("/events_for_month?calendar=work&date=2024-05-31", 1),
+    ] {
+        let body = body_json(call(&app, get(uri)).await).await;
+        assert_eq!(count(body), expected, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn day_query_uses_requested_time_zone() {
+    let (app, _) = server(test_config());
+    // 2023-01-01T23:00:00-05:00 - это 2 января в UTC, но 1 января в Нью-Йорке
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({"calendar": "work", "date_time": "2023-01-01T23:00:00-05:00", "event_name": "late call"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    for (uri, expected) in [
+        (
+            "/events_for_day?calendar=work&date=2023-01-02",
+            vec!["late call"],
+        ),
+        ("/events_for_day?calendar=work&date=2023-01-01", vec![]),
+        (
+            "/events_for_day?calendar=work&date=2023-01-01&tz=America/New_York",
+            vec!["late call"],
+        ),
+        (
+            "/events_for_day?calendar=work&date=2023-01-02&tz=America/New_York",
+            vec![],
+        ),
+    ] {
+        let body = body_json(call(&app, get(uri)).await).await;
+        assert_eq!(names(&body), expected, "{}", uri);
+    }
+
+    let res = call(
+        &app,
+        get("/events_for_day?calendar=work&date=2023-01-01&tz=Mars/Olympus"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn week_query_groups_civil_days_across_dst() {
+    let (app, _) = server(test_config());
+    // В Нью-Йорке 10 марта 2024 переход на летнее время, неделя с понедельника 4 марта по воскресенье 10 марта
+    for (date, name) in [
+        ("2024-03-04T04:30:00Z", "sunday before"), // 3 марта 23:30 EST
+        ("2024-03-04T05:30:00Z", "monday"),        // 4 марта 00:30 EST
+        ("2024-03-11T03:30:00Z", "dst sunday"),    // 10 марта 23:30 EDT
+        ("2024-03-11T04:30:00Z", "next monday"),   // 11 марта 00:30 EDT
+    ] {
+        create(&app, "work", date, name).await;
+    }
+    let res = call(
+        &app,
+        get("/events_for_week?calendar=work&date=2024-03-07&tz=America/New_York"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["monday", "dst sunday"]);
+    // Без tz границы недели по UTC
+    let res = call(&app, get("/events_for_week?calendar=work&date=2024-03-07")).await;
+    assert_eq!(names(&body_json(res).await), ["sunday before", "monday"]);
+}
+
+#[tokio::test]
+async fn all_day_event_keeps_its_date_in_every_time_zone() {
+    let (app, _) = server(test_config());
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({"calendar": "home", "date_time": "2024-06-01", "event_name": "birthday", "all_day": true}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let body = body_json(res).await;
+    // Дата выводится без времени
+    assert_eq!(body["result"]["date"], "2024-06-01");
+    assert_eq!(body["result"]["all_day"], true);
+
+    for tz in ["UTC", "America/Los_Angeles", "Asia/Tokyo"] {
+        let uri = format!("/events_for_day?calendar=home&date=2024-06-01&tz={}", tz);
+        let body = body_json(call(&app, get(&uri)).await).await;
+        assert_eq!(names(&body), ["birthday"], "{}", tz);
+        let uri = format!("/events_for_day?calendar=home&date=2024-05-31&tz={}", tz);
+        let body = body_json(call(&app, get(&uri)).await).await;
+        assert!(names(&body).is_empty(), "{}", tz);
+    }
+}
+
+#[tokio::test]
+async fn relative_dates_resolve_in_requested_time_zone() {
+    let (app, _) = server(test_config());
+    // Кирибати (UTC+14) и Американское Самоа (UTC-11): "сегодня" в них - всегда разные даты
+    for tz in ["Pacific/Kiritimati", "Pacific/Pago_Pago"] {
+        let zone: chrono_tz::Tz = tz.parse().unwrap();
+        let today = Utc::now().with_timezone(&zone).date_naive();
+        let noon = zone
+            .from_local_datetime(&today.and_hms_opt(12, 0, 0).unwrap())
+            .unwrap()
+            .with_timezone(&Utc);
+        create(&app, tz, &noon.to_rfc3339(), "lunch").await;
+        for (date, expected) in [("today", 1), ("yesterday", 0), ("tomorrow", 0)] {
+            let uri = format!("/events_for_day?calendar={}&date={}&tz={}", tz, date, tz);
+            let body = body_json(call(&app, get(&uri)).await).await;
+            assert_eq!(names(&body).len(), expected, "{} {}", tz, date);
+        }
+        // Точная дата по-прежнему принимается
+        let uri = format!("/events_for_day?calendar={}&date={}&tz={}", tz, today, tz);
+        let body = body_json(call(&app, get(&uri)).await).await;
+        assert_eq!(names(&body), ["lunch"]);
+    }
+
+    let res = call(&app, get("/events_for_day?calendar=work&date=someday")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn epoch_timestamps_are_accepted() {
+    let (app, _) = server(test_config());
+    // Целое число и строка с числом секунд - то же, что 2024-06-01T10:00:00Z
+    for (date_time, name) in [
+        (json!(1717236000), "integer"),
+        (json!("1717236000"), "string"),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "work", "date_time": date_time, "event_name": name}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(
+            body_json(res).await["result"]["date"],
+            "2024-06-01T10:00:00Z"
+        );
+    }
+
+    let res = call(
+        &app,
+        post_json(
+            "/update_event",
+            json!({
+                "calendar": "work",
+                "date_time": 1717236000,
+                "event_name": "integer",
+                "new_date_time": "1717239600",
+                "new_event_name": "integer",
+                "version": 1,
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        body_json(res).await["result"]["date"],
+        "2024-06-01T11:00:00Z"
+    );
+
+    for date_time in [json!(1.5), json!("yesterday noon"), json!(true)] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "work", "date_time": date_time, "event_name": "bad"}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", date_time);
+    }
+}
+
+#[tokio::test]
+async fn validation_reports_every_invalid_field() {
+    let (app, _) = server(test_config());
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({"calendar": "work", "date_time": "2024-13-45T99:00:00Z"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let body = body_json(res).await;
+    let mut fields: Vec<&str> = body["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| {
+            assert!(!error["message"].as_str().unwrap().is_empty());
+            error["field"].as_str().unwrap()
+        })
+        .collect();
+    fields.sort();
+    assert_eq!(fields, ["date_time", "event_name"]);
+
+    // Ошибки query-строки возвращаются в том же виде
+    let res = call(&app, get("/events_for_day?calendar=work&date=2024-02-30")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(body_json(res).await["errors"][0]["field"], "date");
+}
+
+#[tokio::test]
+async fn body_over_limit_is_rejected() {
+    const LIMIT: usize = 256;
+    let (app, _) = server(
+        test_config()
+            .set_override("max_body_bytes", LIMIT as u64)
+            .unwrap(),
+    );
+    // Тело ровно заданной длины: название дополняется до нужного размера
+    let body_of = |len: usize| {
+        let empty =
+            json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": ""});
+        let name = "x".repeat(len - empty.to_string().len());
+        json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": name})
+    };
+    assert_eq!(body_of(LIMIT).to_string().len(), LIMIT);
+    let res = call(&app, post_json("/create_event", body_of(LIMIT))).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let res = call(&app, post_json("/create_event", body_of(LIMIT + 1))).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn slow_handler_times_out() {
+    let (_, state) = server(
+        test_config()
+            .set_override("request_timeout_ms", 50)
+            .unwrap(),
+    );
+    // Тот же middleware, что и в приложении, перед обработчиком, который спит дольше таймаута
+    let app = Router::new()
+        .route(
+            "/slow",
+            axum::routing::get(|| async {
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                "done"
+            }),
+        )
+        .route("/fast", axum::routing::get(|| async { "done" }))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            request_timeout,
+        ))
+        .with_state(state);
+
+    let started = std::time::Instant::now();
+    let res = call(&app, get("/slow")).await;
+    assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+    assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    assert_eq!(body_json(res).await["error"], "request timed out");
+    let res = call(&app, get("/fast")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+// Обработчик, паникующий во время удержания блокировки
+async fn panicking_handler(State(state): State<AppState>) -> &'static str {
+    let _guard = state.pending_deletes.lock().unwrap();
+    panic!("deliberate panic");
+}
+
+#[tokio::test]
+async fn panicking_handler_returns_json_500() {
+    let (app, state) = server(
+        test_config()
+            .set_override("delete_grace_period_secs", 60)
+            .unwrap(),
+    );
+    create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    // Те же слои, что и в приложении, перед обработчиком, который паникует, удерживая блокировку
+    let panicking = Router::new()
+        .route("/panic", axum::routing::get(panicking_handler))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            recover_poisoned_locks,
+        ))
+        .with_state(state.clone())
+        .layer(CatchPanicLayer::custom(panic_response));
+
+    let res = call(&panicking, get("/panic")).await;
+    assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(
+        body_json(res).await,
+        json!({"error": "internal server error"})
+    );
+
+    // Отравленная паникой блокировка восстанавливается, следующие запросы обрабатываются
+    let res = call(
+        &app,
+        post_json(
+            "/delete_event",
+            json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": "standup"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::ACCEPTED);
+}
+
+#[tokio::test]
+async fn large_responses_are_compressed_on_request() {
+    let month = "/events_for_month?calendar=work&date=2024-06-01";
+    let (app, _) = server(test_config());
+    for day in 1..=28 {
+        let date = format!("2024-06-{:02}T10:00:00Z", day);
+        create(&app, "work", &date, "daily standup with a fairly long name").await;
+    }
+    for encoding in ["gzip", "br"] {
+        let res = call(&app, get_with(month, header::ACCEPT_ENCODING, encoding)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], encoding);
+    }
+    let res = call(&app, get(month)).await;
+    assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+
+    // С compression=false ответ не сжимается, даже если клиент просит
+    let (app, _) = server(test_config().set_override("compression", false).unwrap());
+    create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let res = call(&app, get_with(month, header::ACCEPT_ENCODING, "gzip")).await;
+    assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+}
+
+#[tokio::test]
+async fn graceful_shutdown_finishes_active_requests() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let app = Router::new().route(
+        "/slow",
+        axum::routing::get(|| async {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            "done"
+        }),
+    );
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown, signal) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        serve(listener, app, async {
+            let _ = signal.await;
+        })
+        .await
+        .is_ok()
+    });
+
+    let mut connection = tokio::net::TcpStream::connect(addr).await.unwrap();
+    connection
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    // Сигнал приходит, пока запрос еще выполняется
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    shutdown.send(()).unwrap();
+
+    let mut response = String::new();
+    connection.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with("done"));
+    let stopped = tokio::time::timeout(std::time::Duration::from_secs(5), server)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(stopped);
+    // Новые соединения после остановки не принимаются
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn ready_reflects_storage_while_health_stays_ok() {
+    let (app, _) = server(test_config());
+    let res = call(&app, get("/ready")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_json(res).await["status"], "ok");
+
+    // Каталога для файла событий нет: записать события нельзя, но сервер жив
+    let path = std::env::temp_dir()
+        .join(format!("missing-{}", Uuid::new_v4()))
+        .join("events.json");
+    let (app, _) = server(
+        test_config()
+            .set_override("storage_path", path.to_string_lossy().to_string())
+            .unwrap(),
+    );
+    let res = call(&app, get("/ready")).await;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body_json(res).await["status"], "unavailable");
+    let res = call(&app, get("/health")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_json(res).await, json!({"status": "ok"}));
+}
+
+#[tokio::test]
+async fn api_key_guards_routes_when_configured() {
+    let events = "/events";
+    // Без ключа в настройках все маршруты открыты
+    let (app, _) = server(test_config());
+    let res = call(&app, get(events)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let (app, _) = server(test_config().set_override("api_key", "secret").unwrap());
+    let res = call(&app, get(events)).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(body_json(res).await, json!({"error": "unauthorized"}));
+    let key = HeaderName::from_static(API_KEY_HEADER);
+    let res = call(&app, get_with(events, key.clone(), "wrong")).await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let res = call(&app, get_with(events, key, "secret")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    // Проверка работоспособности доступна без ключа
+    let res = call(&app, get("/health")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn tags_are_normalized_filtered_and_counted() {
+    let (app, _) = server(test_config());
+    for (name, tags) in [
+        ("standup", json!([" Work ", "URGENT", "work"])),
+        ("review", json!(["work"])),
+        ("dentist", json!(["health"])),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "main", "date_time": "2024-06-03T10:00:00Z", "event_name": name, "tags": tags}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        if name == "standup" {
+            // Теги в нижнем регистре, без пробелов по краям и повторов
+            assert_eq!(
+                body_json(res).await["result"]["tags"],
+                json!(["work", "urgent"])
+            );
+        }
+    }
+
+    let res = call(
+        &app,
+        get("/events_for_day?calendar=main&date=2024-06-03&tag=WORK"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["standup", "review"]);
+    let res = call(
+        &app,
+        get("/events_for_week?calendar=main&date=2024-06-03&tag=urgent"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["standup"]);
+    let res = call(
+        &app,
+        get("/events_for_month?calendar=main&date=2024-06-03&tag=none"),
+    )
+    .await;
+    assert!(names(&body_json(res).await).is_empty());
+
+    let res = call(&app, get("/tags")).await;
+    assert_eq!(
+        body_json(res).await["result"],
+        json!([
+            {"tag": "health", "count": 1},
+            {"tag": "urgent", "count": 1},
+            {"tag": "work", "count": 2},
+        ])
+    );
+
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({"calendar": "main", "date_time": "2024-06-03T10:00:00Z", "event_name": "bad", "tags": [" "]}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn metrics_count_requests_except_their_own() {
+    let (app, _) = server(test_config());
+    let counter = "http_requests_total{method=\"GET\",route=\"/events\",status=\"200\"}";
+    let metric = |text: &str| -> Option<String> {
+        text.lines()
+            .find_map(|line| line.strip_prefix(counter))
+            .map(|value| value.trim().to_string())
+    };
+    let res = call(&app, get("/events")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let text = body_text(call(&app, get("/metrics")).await).await;
+    assert_eq!(metric(&text).as_deref(), Some("1"));
+    assert!(text.contains("http_request_duration_seconds_bucket{method=\"GET\",route=\"/events\""));
+
+    call(&app, get("/events")).await;
+    let text = body_text(call(&app, get("/metrics")).await).await;
+    assert_eq!(metric(&text).as_deref(), Some("2"));
+    assert!(!text.contains("route=\"/metrics\""));
+}
+
+#[tokio::test]
+async fn sse_stream_sends_created_event() {
+    use hyper::body::HttpBody;
+
+    let (app, _) = server(test_config());
+    // Первый кадр из потока SSE как текст
+    async fn next_frame(body: &mut axum::body::BoxBody) -> String {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.data())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        String::from_utf8(chunk.to_vec()).unwrap()
+    }
+
+    let res = call(&app, get("/events/stream")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "text/event-stream");
+    let mut body = res.into_body();
+    let created = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let frame = next_frame(&mut body).await;
+    let data = frame
+        .lines()
+        .find_map(|line| line.strip_prefix("data:"))
+        .unwrap();
+    let change: Value = serde_json::from_str(data).unwrap();
+    assert!(frame.lines().any(|line| line == "id:1"));
+    assert_eq!(change["type"], "created");
+    assert_eq!(change["event"]["id"], created["id"]);
+
+    // Клиент, получивший изменения до id 0, сразу получает пропущенное изменение
+    let res = call(
+        &app,
+        get_with(
+            "/events/stream",
+            HeaderName::from_static("last-event-id"),
+            "0",
+        ),
+    )
+    .await;
+    let frame = next_frame(&mut res.into_body()).await;
+    assert!(frame.lines().any(|line| line == "id:1"));
+}
+
+#[tokio::test]
+async fn audit_lists_create_then_delete_in_order() {
+    let (app, _) = server(
+        test_config()
+            .set_override("api_key", "secret-key-1234")
+            .unwrap(),
+    );
+    let with_key = |mut req: Request<Body>| {
+        req.headers_mut()
+            .insert(API_KEY_HEADER, HeaderValue::from_static("secret-key-1234"));
+        req
+    };
+    let body =
+        json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": "standup"});
+    let res = call(&app, with_key(post_json("/create_event", body))).await;
+    let id = body_json(res).await["id"].as_str().unwrap().to_string();
+    let res = call(&app, with_key(delete(&format!("/event/{}", id)))).await;
+    assert!(res.status().is_success());
+
+    let res = call(&app, with_key(get("/audit?limit=10"))).await;
+    let body = body_json(res).await;
+    assert_eq!(body["total"], 2);
+    let entries = body["result"].as_array().unwrap();
+    let operations: Vec<&str> = entries
+        .iter()
+        .map(|entry| entry["operation"].as_str().unwrap())
+        .collect();
+    assert_eq!(operations, ["create", "delete"]);
+    for entry in entries {
+        assert_eq!(entry["event_id"], id.as_str());
+        // От ключа API в журнале остаются только последние символы
+        assert_eq!(entry["api_key"], "****1234");
+    }
+    let timestamps: Vec<DateTime<Utc>> = entries
+        .iter()
+        .map(|entry| entry["timestamp"].as_str().unwrap().parse().unwrap())
+        .collect();
+    assert!(timestamps[0] <= timestamps[1]);
+
+    let res = call(&app, with_key(get("/audit?limit=1&offset=1"))).await;
+    let body = body_json(res).await;
+    assert_eq!(body["result"][0]["operation"], "delete");
+}
+
+#[tokio::test]
+async fn stats_count_events_per_month() {
+    let (app, _) = server(test_config());
+    for date in [
+        "2024-01-05T10:00:00Z",
+        "2024-01-20T10:00:00Z",
+        "2024-03-01T10:00:00Z",
+        "2024-02-10T10:00:00Z",
+        "2024-02-11T10:00:00Z",
+        "2024-02-12T10:00:00Z",
+        "2023-12-31T10:00:00Z",
+    ] {
+        create(&app, "work", date, "event").await;
+    }
+    let res = call(&app, get("/stats?year=2024")).await;
+    let text = body_text(res).await;
+    assert_eq!(
+        serde_json::from_str::<Value>(&text).unwrap()["result"],
+        json!({"2024-01": 2, "2024-02": 3, "2024-03": 1})
+    );
+    // Месяцы идут по времени, а не в порядке добавления событий
+    let positions: Vec<usize> = ["2024-01", "2024-02", "2024-03"]
+        .iter()
+        .map(|month| text.find(month).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|pair| pair[0] < pair[1]));
+    let res = call(&app, get("/stats")).await;
+    assert_eq!(body_json(res).await["result"]["2023-12"], 1);
+}
+
+#[tokio::test]
+async fn days_query_returns_each_requested_date() {
+    let (app, _) = server(
+        test_config()
+            .set_override("max_dates_per_query", 3)
+            .unwrap(),
+    );
+    create(&app, "work", "2024-01-01T09:00:00Z", "standup").await;
+    create(&app, "work", "2024-01-03T09:00:00Z", "review").await;
+    let res = call(
+        &app,
+        get("/events_for_days?calendar=work&dates=2024-01-01,2024-01-02,2024-01-03"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    assert_eq!(
+        names(&json!({"result": body["result"]["2024-01-01"]})),
+        ["standup"]
+    );
+    assert_eq!(body["result"]["2024-01-02"], json!([]));
+    assert_eq!(
+        names(&json!({"result": body["result"]["2024-01-03"]})),
+        ["review"]
+    );
+
+    for dates in [
+        "2024-01-01,2024-13-01",
+        "2024-01-01,2024-01-02,2024-01-03,2024-01-04",
+    ] {
+        let uri = format!("/events_for_days?calendar=work&dates={}", dates);
+        let res = call(&app, get(&uri)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", dates);
+    }
+}
+
+#[tokio::test]
+async fn period_queries_filter_by_name() {
+    let (app, _) = server(test_config());
+    for (date, name) in [
+        ("2024-06-03T09:00:00Z", "standup"),
+        ("2024-06-03T11:00:00Z", "Code Review"),
+        ("2024-06-03T15:00:00Z", "design review"),
+        ("2024-06-05T15:00:00Z", "review retro"),
+    ] {
+        create(&app, "work", date, name).await;
+    }
+    let res = call(
+        &app,
+        get("/events_for_day?calendar=work&date=2024-06-03&name_contains=REVIEW"),
+    )
+    .await;
+    assert_eq!(
+        names(&body_json(res).await),
+        ["Code Review", "design review"]
+    );
+    let res = call(
+        &app,
+        get("/events_for_week?calendar=work&date=2024-06-03&name_contains=review"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await).len(), 3);
+    let res = call(
+        &app,
+        get("/events_for_month?calendar=work&date=2024-06-03&name_contains=standup"),
+    )
+    .await;
+    assert_eq!(names(&body_json(res).await), ["standup"]);
+    // Без параметра возвращаются все события дня
+    let res = call(&app, get("/events_for_day?calendar=work&date=2024-06-03")).await;
+    assert_eq!(names(&body_json(res).await).len(), 3);
+}
+
+#[tokio::test]
+async fn search_matches_names_case_insensitively() {
+    let (app, _) = server(test_config());
+    for (date, name) in [
+        ("2024-06-05T10:00:00Z", "team mtg"),
+        ("2024-06-01T10:00:00Z", "Team MTG"),
+        ("2024-07-01T10:00:00Z", "MTG prep"),
+        ("2024-06-02T10:00:00Z", "lunch"),
+    ] {
+        create(&app, "work", date, name).await;
+    }
+    // Результат отсортирован по дате
+    let res = call(&app, get("/search?q=mtg")).await;
+    assert_eq!(
+        names(&body_json(res).await),
+        ["Team MTG", "team mtg", "MTG prep"]
+    );
+    let res = call(&app, get("/search?q=Mtg&from=2024-06-01&to=2024-06-30")).await;
+    assert_eq!(names(&body_json(res).await), ["Team MTG", "team mtg"]);
+
+    for uri in ["/search?q=", "/search?q=%20%20", "/search"] {
+        let res = call(&app, get(uri)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn multi_day_event_appears_on_each_day() {
+    let (app, _) = server(test_config());
+    let create_with_end = |start: &str, end: &str| {
+        post_json(
+            "/create_event",
+            json!({"calendar": "work", "date_time": start, "end_date_time": end, "event_name": "offsite"}),
+        )
+    };
+    let res = call(
+        &app,
+        create_with_end("2024-06-01T18:00:00Z", "2024-06-02T10:00:00Z"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(
+        body_json(res).await["result"]["end_date"],
+        "2024-06-02T10:00:00Z"
+    );
+    for (date, expected) in [
+        ("2024-05-31", 0),
+        ("2024-06-01", 1),
+        ("2024-06-02", 1),
+        ("2024-06-03", 0),
+    ] {
+        let uri = format!("/events_for_day?calendar=work&date={}", date);
+        let body = body_json(call(&app, get(&uri)).await).await;
+        assert_eq!(names(&body).len(), expected, "{}", date);
+    }
+
+    // Окончание должно быть строго позже начала
+    for (start, end) in [
+        ("2024-06-01T18:00:00Z", "2024-06-01T18:00:00Z"),
+        ("2024-06-01T18:00:00Z", "2024-06-01T17:00:00Z"),
+        ("2024-06-01T18:00:00Z", "tomorrow"),
+    ] {
+        let res = call(&app, create_with_end(start, end)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", end);
+        assert_eq!(body_json(res).await["errors"][0]["field"], "end_date_time");
+    }
+}
+
+#[tokio::test]
+async fn overlaps_warn_or_reject_but_touching_events_do_not() {
+    let (app, state) = server(test_config());
+    let meeting = |start: &str, end: &str, name: &str, reject: bool| {
+        let uri = if reject {
+            "/create_event?reject_overlap=true"
+        } else {
+            "/create_event"
+        };
+        post_json(
+            uri,
+            json!({"calendar": "work", "date_time": start, "end_date_time": end, "event_name": name}),
+        )
+    };
+    let res = call(
+        &app,
+        meeting(
+            "2024-06-03T10:00:00Z",
+            "2024-06-03T11:00:00Z",
+            "sync",
+            false,
+        ),
+    )
+    .await;
+    let sync_id = body_json(res).await["id"].clone();
+
+    // Пересекающееся событие создается с предупреждением
+    let res = call(
+        &app,
+        meeting(
+            "2024-06-03T10:30:00Z",
+            "2024-06-03T11:30:00Z",
+            "review",
+            false,
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let body = body_json(res).await;
+    assert_eq!(
+        body["warnings"],
+        json!([format!("overlaps with {}", sync_id.as_str().unwrap())])
+    );
+
+    // С reject_overlap - отклоняется и не добавляется
+    let res = call(
+        &app,
+        meeting(
+            "2024-06-03T09:30:00Z",
+            "2024-06-03T10:15:00Z",
+            "early",
+            true,
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CONFLICT);
+    assert_eq!(state.store.list(None).await.unwrap().len(), 2);
+
+    // Вплотную (окончание одного - начало другого) и без пересечения - без предупреждений
+    for (start, end, name) in [
+        ("2024-06-03T11:30:00Z", "2024-06-03T12:00:00Z", "adjacent"),
+        ("2024-06-03T14:00:00Z", "2024-06-03T15:00:00Z", "disjoint"),
+    ] {
+        let res = call(&app, meeting(start, end, name, true)).await;
+        assert_eq!(res.status(), StatusCode::CREATED, "{}", name);
+        assert!(body_json(res).await.get("warnings").is_none(), "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn free_slots_keep_only_long_enough_gaps() {
+    let (app, _) = server(test_config());
+    for (start, end, name) in [
+        ("2024-06-03T09:30:00Z", "2024-06-03T12:00:00Z", "morning"),
+        ("2024-06-03T13:00:00Z", "2024-06-03T18:00:00Z", "afternoon"),
+    ] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "work", "date_time": start, "end_date_time": end, "event_name": name}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+    }
+
+    // Промежуток 09:00-09:30 короче часа, остается только обеденный
+    let res = call(
+        &app,
+        get("/free_slots?calendar=work&date=2024-06-03&duration_minutes=60"),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(
+        body_json(res).await["result"],
+        json!([{"from": "2024-06-03T12:00:00+00:00", "to": "2024-06-03T13:00:00+00:00"}])
+    );
+
+    for duration in ["0", "-30"] {
+        let uri = format!(
+            "/free_slots?calendar=work&date=2024-06-03&duration_minutes={}",
+            duration
+        );
+        let res = call(&app, get(&uri)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", duration);
+    }
+}
+
+#[tokio::test]
+async fn upcoming_returns_future_events_in_order() {
+    let (app, _) = server(test_config());
+    let at = |days: i64| (Utc::now() + chrono::Duration::days(days)).to_rfc3339();
+    create(&app, "work", &at(-2), "past-2").await;
+    create(&app, "work", &at(-1), "past-1").await;
+    let res = call(&app, get("/events/upcoming")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_json(res).await["result"], json!([]));
+
+    // Будущие события добавляются в обратном порядке, ответ отсортирован по дате
+    for day in (1..=12).rev() {
+        create(&app, "work", &at(day), &format!("future-{}", day)).await;
+    }
+    let res = call(&app, get("/events/upcoming")).await;
+    let body = body_json(res).await;
+    let expected: Vec<String> = (1..=10).map(|day| format!("future-{}", day)).collect();
+    assert_eq!(names(&body), expected);
+
+    let res = call(&app, get("/events/upcoming?limit=3&tz=Europe/Moscow")).await;
+    let body = body_json(res).await;
+    assert_eq!(names(&body), ["future-1", "future-2", "future-3"]);
+    assert!(body["now"].as_str().unwrap().ends_with("+03:00"));
+}
+
+#[tokio::test]
+async fn create_and_update_return_the_event() {
+    let (app, _) = server(test_config());
+    let res = call(
+        &app,
+        post_json(
+            "/create_event",
+            json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": "standup"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let body = body_json(res).await;
+    let created: Event = serde_json::from_value(body["result"].clone()).unwrap();
+    assert_eq!(body["id"], json!(created.id));
+    assert_eq!(created.calendar, "work");
+    assert_eq!(created.name, "standup");
+    assert_eq!(
+        created.date,
+        Utc.with_ymd_and_hms(2024, 6, 1, 10, 0, 0).unwrap()
+    );
+    assert_eq!(created.version, 1);
+
+    let res = call(
+        &app,
+        post_json(
+            "/update_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-06-01T10:00:00Z",
+                "event_name": "standup",
+                "new_date_time": "2024-06-02T10:00:00Z",
+                "new_event_name": "retro",
+                "version": 1,
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let updated: Event = serde_json::from_value(body_json(res).await["result"].clone()).unwrap();
+    assert_eq!(updated.id, created.id);
+    assert_eq!(updated.name, "retro");
+    assert_eq!(
+        updated.date,
+        Utc.with_ymd_and_hms(2024, 6, 2, 10, 0, 0).unwrap()
+    );
+    assert_eq!(updated.version, 2);
+}
+
+#[tokio::test]
+async fn update_bumps_updated_at_but_keeps_created_at() {
+    let (app, state) = server(test_config());
+    let created = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    assert_eq!(created["created_at"], created["updated_at"]);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+
+    let res = call(
+        &app,
+        post_json(
+            "/update_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-06-01T10:00:00Z",
+                "event_name": "standup",
+                "new_date_time": "2024-06-01T10:00:00Z",
+                "new_event_name": "retro",
+                "version": 1,
+            }),
+        ),
+    )
+    .await;
+    let updated: Event = serde_json::from_value(body_json(res).await["result"].clone()).unwrap();
+    let created_at: DateTime<Utc> = serde_json::from_value(created["created_at"].clone()).unwrap();
+    assert_eq!(updated.created_at, created_at);
+    assert!(updated.updated_at > created_at);
+
+    // Отметки времени сохраняются в файл и читаются из него без изменений
+    let saved = storage::load_events(&state.settings.storage_path).unwrap();
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].created_at, updated.created_at);
+    assert_eq!(saved[0].updated_at, updated.updated_at);
+}
+
+#[tokio::test]
+async fn get_event_finds_by_id_or_returns_404() {
+    let (app, _) = server(test_config());
+    let event = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+
+    let res = call(
+        &app,
+        get(&format!("/event/{}", event["id"].as_str().unwrap())),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_json(res).await["result"], event);
+
+    let res = call(&app, get(&format!("/event/{}", Uuid::new_v4()))).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(body_json(res).await, json!({"error": "event not found"}));
+}
+
+#[tokio::test]
+async fn events_are_paged_by_date_with_defaults_and_clamp() {
+    let (app, _) = server(test_config());
+    // События добавляются в обратном порядке дат
+    for minute in (0..60).rev() {
+        let date_time = format!("2024-06-01T10:{minute:02}:00Z");
+        create(&app, "work", &date_time, &format!("event {minute:02}")).await;
+    }
+
+    let res = call(&app, get("/events")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    assert_eq!(body["total"], 60);
+    assert_eq!(body["limit"], 50);
+    assert_eq!(body["offset"], 0);
+    let expected: Vec<String> = (0..50).map(|minute| format!("event {minute:02}")).collect();
+    assert_eq!(names(&body), expected);
+
+    let res = call(&app, get("/events?limit=1000&offset=55")).await;
+    let body = body_json(res).await;
+    assert_eq!(body["limit"], 500);
+    assert_eq!(body["total"], 60);
+    assert_eq!(
+        names(&body),
+        ["event 55", "event 56", "event 57", "event 58", "event 59"]
+    );
+
+    for uri in [
+        "/events?limit=-1",
+        "/events?limit=ten",
+        "/events?offset=1.5",
+    ] {
+        let res = call(&app, get(uri)).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn put_and_delete_by_id_and_deprecated_routes() {
+    let (app, _) = server(test_config());
+    let event = create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let uri = format!("/event/{}", event["id"].as_str().unwrap());
+    let missing = format!("/event/{}", Uuid::new_v4());
+    let replacement = json!({"calendar": "work", "date_time": "2024-06-02T11:00:00Z", "event_name": "retro", "version": 1});
+
+    let res = call(&app, send_json(Method::PUT, &uri, replacement.clone())).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("deprecation").is_none());
+    let body = body_json(res).await;
+    assert_eq!(body["result"]["id"], event["id"]);
+    assert_eq!(body["result"]["name"], "retro");
+    assert_eq!(body["result"]["date"], "2024-06-02T11:00:00Z");
+    let res = call(&app, send_json(Method::PUT, &missing, replacement)).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    let res = call(&app, delete(&uri)).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert!(res.headers().get("deprecation").is_none());
+    let res = call(&app, get(&uri)).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    let res = call(&app, delete(&missing)).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+    // Старые маршруты работают, но помечены устаревшими
+    create(&app, "work", "2024-06-01T10:00:00Z", "standup").await;
+    let res = call(
+        &app,
+        post_json(
+            "/update_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-06-01T10:00:00Z",
+                "event_name": "standup",
+                "new_date_time": "2024-06-01T10:00:00Z",
+                "new_event_name": "planning",
+                "version": 1,
+            }),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["deprecation"], "true");
+    let res = call(
+        &app,
+        post_json(
+            "/delete_event",
+            json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": "planning"}),
+        ),
+    )
+    .await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["deprecation"], "true");
+}
+
+#[tokio::test]
+async fn event_names_are_trimmed_and_must_not_be_blank() {
+    let (app, _) = server(test_config());
+    for name in ["", "   \t"] {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": name}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST, "{:?}", name);
+        let body = body_json(res).await;
+        assert_eq!(body["errors"][0]["field"], "event_name");
+        assert_eq!(body["errors"][0]["message"], "must not be empty");
+    }
+
+    let event = create(&app, "work", "2024-06-01T10:00:00Z", "  Meeting ").await;
+    assert_eq!(event["name"], "Meeting");
+
+    // Событие находится по названию без пробелов, пустое новое название отклоняется
+    let update = |event_name: &str, new_event_name: &str| {
+        post_json(
+            "/update_event",
+            json!({
+                "calendar": "work",
+                "date_time": "2024-06-01T10:00:00Z",
+                "event_name": event_name,
+                "new_date_time": "2024-06-01T10:00:00Z",
+                "new_event_name": new_event_name,
+                "version": 1,
+            }),
+        )
+    };
+    let res = call(&app, update("Meeting", " ")).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(body_json(res).await["errors"][0]["field"], "new_event_name");
+    let res = call(&app, update(" Meeting", " Review  ")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(body_json(res).await["result"]["name"], "Review");
+}
+
+#[tokio::test]
+async fn year_query_returns_that_year_sorted() {
+    let (app, _) = server(test_config());
+    for (date_time, name) in [
+        ("2024-12-31T23:00:00Z", "new year's eve"),
+        ("2025-01-01T00:00:00Z", "new year"),
+        ("2024-03-08T10:00:00Z", "march"),
+        ("2024-01-01T00:00:00Z", "first day"),
+        ("2023-12-31T23:59:59Z", "last year"),
+    ] {
+        create(&app, "work", date_time, name).await;
+    }
+
+    let res = call(&app, get("/events_for_year?calendar=work&date=2024-06-15")).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = body_json(res).await;
+    assert_eq!(names(&body), ["first day", "march", "new year's eve"]);
+
+    let res = call(&app, get("/events_for_year?calendar=work&date=2025-02-01")).await;
+    assert_eq!(names(&body_json(res).await), ["new year"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_reads_see_the_whole_store() {
+    let (app, _) = server(test_config());
+    for day in 1..=7 {
+        for hour in [9, 12, 15] {
+            let date_time = format!("2024-06-{day:02}T{hour:02}:00:00Z");
+            create(&app, "work", &date_time, &format!("day {day} at {hour}")).await;
+        }
+    }
+
+    let reads: Vec<_> = (0..32)
+        .map(|i| {
+            let app = app.clone();
+            // Router не Sync, поэтому задачи вызывают его напрямую, а не через call
+            tokio::spawn(async move {
+                let day = i % 7 + 1;
+                let uri = format!("/events_for_day?calendar=work&date=2024-06-{day:02}");
+                let res = app.clone().oneshot(get(&uri)).await.unwrap();
+                assert_eq!(res.status(), StatusCode::OK);
+                let body = body_json(res).await;
+                assert_eq!(
+                    names(&body),
+                    [9, 12, 15].map(|hour| format!("day {day} at {hour}"))
+                );
+
+                let uri = "/events_for_week?calendar=work&date=2024-06-05";
+                let res = app.oneshot(get(uri)).await.unwrap();
+                assert_eq!(res.status(), StatusCode::OK);
+                body_json(res).await["result"].as_array().unwrap().len()
+            })
+        })
+        .collect();
+    for read in reads {
+        // Неделя с понедельника 3 июня по воскресенье 9 июня
+        assert_eq!(read.await.unwrap(), 15);
+    }
+}
+
+#[tokio::test]
+async fn same_name_and_date_get_distinct_ids_in_every_response() {
+    let (app, _) = server(test_config());
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let res = call(
+            &app,
+            post_json(
+                "/create_event",
+                json!({"calendar": "work", "date_time": "2024-06-01T10:00:00Z", "event_name": "standup"}),
+            ),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let body = body_json(res).await;
+        assert_eq!(body["id"], body["result"]["id"]);
+        ids.push(body["id"].as_str().unwrap().parse::<Uuid>().unwrap());
+    }
+    assert_ne!(ids[0], ids[1]);
+
+    // Идентификаторы возвращаются запросами за день, неделю и месяц
+    for period in ["day", "week", "month"] {
+        let uri = format!("/events_for_{period}?calendar=work&date=2024-06-01");
+        let body = body_json(call(&app, get(&uri)).await).await;
+        let mut returned: Vec<Uuid> = body["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| serde_json::from_value(event["id"].clone()).unwrap())
+            .collect();
+        returned.sort();
+        let mut expected = ids.clone();
+        expected.sort();
+        assert_eq!(returned, expected, "{}", period);
+    }
+}
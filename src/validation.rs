@@ -0,0 +1,151 @@
+// Проверка полей запроса с накоплением ошибок: клиент получает сразу список всех некорректных полей
+use std::str::FromStr;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use utoipa::ToSchema;
+
+// Ошибка в отдельном поле запроса
+#[derive(Serialize, ToSchema)]
+pub struct ValidationError {
+    pub field: String,
+    pub message: String,
+}
+
+// Сборщик ошибок проверки полей json-объекта (тела запроса или query-строки)
+// Методы чтения полей возвращают None при ошибке и запоминают ее, проверка не прерывается на первой ошибке
+pub struct Validator<'a> {
+    body: &'a Value,
+    errors: Vec<ValidationError>,
+}
+
+impl<'a> Validator<'a> {
+    pub fn new(body: &'a Value) -> Self {
+        let mut validator = Validator {
+            body,
+            errors: Vec::new(),
+        };
+        if !body.is_object() {
+            validator.error("body", "expected a JSON object");
+        }
+        validator
+    }
+
+    // Добавляем ошибку для поля
+    pub fn error(&mut self, field: &str, message: impl Into<String>) {
+        self.errors.push(ValidationError {
+            field: field.to_string(),
+            message: message.into(),
+        });
+    }
+
+    // Поле произвольного типа, отсутствующее поле для Option читается как None
+    pub fn field<T: DeserializeOwned>(&mut self, name: &str) -> Option<T> {
+        if !self.body.is_object() {
+            return None;
+        }
+        let value = self.body.get(name);
+        match serde_json::from_value(value.cloned().unwrap_or(Value::Null)) {
+            Ok(value) => Some(value),
+            Err(_) if value.is_none() => {
+                self.error(name, "is required");
+                None
+            }
+            Err(e) => {
+                self.error(name, e.to_string());
+                None
+            }
+        }
+    }
+
+    // Непустая строка, при trim пробелы по краям обрезаются
+    pub fn non_empty_string(&mut self, name: &str, trim: bool) -> Option<String> {
+        let value: String = self.field(name)?;
+        if value.trim().is_empty() {
+            self.error(name, "must not be empty");
+            return None;
+        }
+        Some(if trim {
+            value.trim().to_string()
+        } else {
+            value
+        })
+    }
+
+    // Обязательная дата и время: строка rfc3339 либо число секунд с начала эпохи Unix (числом или строкой)
+    pub fn date_time(&mut self, name: &str) -> Option<DateTime<Utc>> {
+        if self.body.is_object() && self.body.get(name).is_none() {
+            self.error(name, "is required");
+            return None;
+        }
+        self.optional_date_time(name)
+    }
+
+    // Необязательная дата и время, отсутствующее поле - None без ошибки
+    pub fn optional_date_time(&mut self, name: &str) -> Option<DateTime<Utc>> {
+        let parsed = match self.body.get(name)? {
+            Value::Null => return None,
+            Value::String(value) => date_time_parse(value),
+            Value::Number(value) if value.is_i64() => date_time_parse(&value.to_string()),
+            _ => Err("expected an RFC3339 string or a Unix timestamp in seconds".to_string()),
+        };
+        match parsed {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error(name, e);
+                None
+            }
+        }
+    }
+
+    // Обязательная дата в формате "год-месяц-день"
+    pub fn date(&mut self, name: &str) -> Option<NaiveDate> {
+        let value: String = self.field(name)?;
+        match NaiveDate::from_str(&value) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error(name, e.to_string());
+                None
+            }
+        }
+    }
+
+    // Завершаем проверку: Ok, если ошибок нет
+    pub fn finish(self) -> Result<(), Vec<ValidationError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    // Накопленные ошибки, если разбор пришлось прервать
+    pub fn into_errors(self) -> Vec<ValidationError> {
+        self.errors
+    }
+}
+
+// Функция для формирования ответа 400 со списком ошибок проверки
+pub fn errors_response(errors: Vec<ValidationError>) -> Response {
+    let res = json!({
+        "errors": errors,
+    });
+    (StatusCode::BAD_REQUEST, Json(res)).into_response()
+}
+
+// Функция для разбора даты и времени из запроса: строка rfc3339 либо число секунд с начала эпохи Unix
+pub fn date_time_parse(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(secs) = value.parse::<i64>() {
+        return DateTime::from_timestamp(secs, 0)
+            .ok_or_else(|| format!("timestamp {} is out of range", secs));
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,115 @@
+// Формат XML для клиентов, которые не принимают JSON
+// Документ повторяет поля JSON-ответа: элементы списка result записываются элементами event
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use quick_xml::{se::Serializer, SeError};
+use serde::Serialize;
+
+use crate::Event;
+
+pub const XML_CONTENT_TYPE: &str = "application/xml";
+
+// Корневой элемент ответа со списком событий
+#[derive(Serialize)]
+#[serde(rename = "response")]
+struct EventsDocument<'a> {
+    result: EventList<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct EventList<'a> {
+    event: &'a [Event],
+}
+
+// Ответ с одним событием
+#[derive(Serialize)]
+#[serde(rename = "response")]
+struct EventDocument<'a> {
+    result: &'a Event,
+}
+
+// Страница событий вместе с общим количеством, ограничением и смещением
+#[derive(Serialize)]
+#[serde(rename = "response")]
+struct PageDocument<'a> {
+    result: EventList<'a>,
+    total: usize,
+    limit: usize,
+    offset: usize,
+}
+
+// События по датам: для каждой даты - элемент day с атрибутом date
+#[derive(Serialize)]
+#[serde(rename = "response")]
+struct DaysDocument<'a> {
+    result: DayList<'a>,
+}
+
+#[derive(Serialize)]
+struct DayList<'a> {
+    day: Vec<Day<'a>>,
+}
+
+#[derive(Serialize)]
+struct Day<'a> {
+    #[serde(rename = "@date")]
+    date: NaiveDate,
+    event: &'a [Event],
+}
+
+// Функция для записи списка событий в XML
+// Если список был обрезан - как и в JSON, добавляются truncated и общее количество событий total
+pub fn write_events(events: &[Event], total: Option<usize>) -> Result<String, SeError> {
+    let document = EventsDocument {
+        result: EventList { event: events },
+        truncated: total.map(|_| true),
+        total,
+    };
+    write_document(&document)
+}
+
+// Функция для записи одного события в XML
+pub fn write_event(event: &Event) -> Result<String, SeError> {
+    write_document(&EventDocument { result: event })
+}
+
+// Функция для записи страницы событий в XML
+pub fn write_page(
+    events: &[Event],
+    total: usize,
+    limit: usize,
+    offset: usize,
+) -> Result<String, SeError> {
+    write_document(&PageDocument {
+        result: EventList { event: events },
+        total,
+        limit,
+        offset,
+    })
+}
+
+// Функция для записи событий, сгруппированных по датам, в XML
+pub fn write_events_by_date(days: &BTreeMap<NaiveDate, Vec<Event>>) -> Result<String, SeError> {
+    let day = days
+        .iter()
+        .map(|(date, events)| Day {
+            date: *date,
+            event: events,
+        })
+        .collect();
+    write_document(&DaysDocument {
+        result: DayList { day },
+    })
+}
+
+// Функция для записи документа с заголовком XML
+fn write_document(document: &impl Serialize) -> Result<String, SeError> {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    document.serialize(Serializer::new(&mut xml))?;
+    Ok(xml)
+}